@@ -1,18 +1,56 @@
-use crate::Result;
+use crate::{Error, Result};
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub workers: usize,
+    #[serde(default)]
+    pub event_source: EventSource,
+    #[serde(default)]
     pub brokers: Vec<String>,
     pub group_id: String,
+    #[serde(default)]
     pub topic: String,
+    pub redis_uri: Option<String>,
+    #[serde(default = "default_redis_stream_partitions")]
+    pub redis_stream_partitions: u16,
+    #[serde(default = "default_consumer_name")]
+    pub consumer_name: String,
     pub postgres_uri: String,
     pub metric_server_addr: String,
 }
 
+/// Where events are consumed from. Must match the EVENT_STREAM of the sharders.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventSource {
+    #[default]
+    Kafka,
+    Redis,
+}
+
 impl Config {
     pub fn from_env() -> Result<Self> {
-        envy::from_env().map_err(Into::into)
+        let config: Config = envy::from_env()?;
+
+        match config.event_source {
+            EventSource::Kafka if config.brokers.is_empty() || config.topic.is_empty() => Err(
+                Error::ConfigError("EVENT_SOURCE is kafka, but BROKERS or TOPIC is not set"),
+            ),
+            EventSource::Redis if config.redis_uri.is_none() => Err(Error::ConfigError(
+                "EVENT_SOURCE is redis, but REDIS_URI is not set",
+            )),
+            _ => Ok(config),
+        }
     }
 }
+
+fn default_redis_stream_partitions() -> u16 {
+    1
+}
+
+// Consumer names only need to be unique within the group, and pod names are unique within the
+// namespace
+fn default_consumer_name() -> String {
+    std::env::var("HOSTNAME").unwrap_or_else(|_| "cache-sync-service".to_owned())
+}
@@ -2,6 +2,9 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error("config error: {0}")]
+    ConfigError(&'static str),
+
     #[error("envy error: {0}")]
     EnvyError(#[from] envy::Error),
 
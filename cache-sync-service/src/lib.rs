@@ -1,5 +1,5 @@
 mod config;
-pub use config::Config;
+pub use config::{Config, EventSource};
 
 mod error;
 pub use error::{Error, Result};
@@ -19,7 +19,7 @@ async fn main() -> Result<()> {
 
     info!(workers = %config.workers, "Starting workers...");
     let manager = Manager::new(config, cache);
-    manager.start()?;
+    manager.start().await?;
 
     ctrl_c().await.expect("Failed to listen for ctrl-c");
 
@@ -0,0 +1,56 @@
+use common::event_forwarding::{Event, EVENT_KEY};
+use event_stream::{Consumer, RedisStreamConsumer};
+use tracing::debug;
+
+use crate::{Config, EventSource, Result};
+
+/// Reads events from the source chosen by EVENT_SOURCE
+pub enum EventConsumer {
+    Kafka(Consumer),
+    Redis(RedisStreamConsumer),
+}
+
+impl EventConsumer {
+    pub async fn connect(config: &Config) -> Result<Self> {
+        let consumer = match config.event_source {
+            EventSource::Kafka => {
+                debug!(topic = %config.topic, "Connecting Kafka consumer");
+
+                Self::Kafka(Consumer::new(
+                    config.brokers.clone(),
+                    config.topic.clone(),
+                    config.group_id.clone(),
+                )?)
+            }
+            EventSource::Redis => {
+                debug!(
+                    key = %EVENT_KEY,
+                    partitions = %config.redis_stream_partitions,
+                    "Connecting Redis stream consumer"
+                );
+
+                let redis_uri = config.redis_uri.as_deref().expect("REDIS_URI is not set");
+
+                Self::Redis(
+                    RedisStreamConsumer::connect(
+                        redis_uri,
+                        EVENT_KEY,
+                        config.redis_stream_partitions,
+                        config.group_id.clone(),
+                        config.consumer_name.clone(),
+                    )
+                    .await?,
+                )
+            }
+        };
+
+        Ok(consumer)
+    }
+
+    pub async fn recv(&self) -> event_stream::Result<Event> {
+        match self {
+            Self::Kafka(consumer) => consumer.recv().await,
+            Self::Redis(consumer) => consumer.recv().await,
+        }
+    }
+}
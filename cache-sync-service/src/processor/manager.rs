@@ -1,12 +1,11 @@
 use std::sync::Arc;
 
 use cache::Cache;
-use event_stream::Consumer;
 use tracing::debug;
 
 use crate::{Config, Result};
 
-use super::worker::Worker;
+use super::{worker::Worker, EventConsumer};
 
 pub struct Manager<C: Cache> {
     config: Config,
@@ -20,15 +19,8 @@ impl<C: Cache> Manager<C> {
         Self { config, cache }
     }
 
-    pub fn start(&self) -> Result<()> {
-        let topic = self.config.topic.clone();
-
-        debug!(%topic, "Connecting consumer");
-        let consumer = Arc::new(Consumer::new(
-            self.config.brokers.clone(),
-            topic.clone(),
-            self.config.group_id.clone(),
-        )?);
+    pub async fn start(&self) -> Result<()> {
+        let consumer = Arc::new(EventConsumer::connect(&self.config).await?);
 
         debug!("Consumer connected!");
 
@@ -1,5 +1,7 @@
+mod consumer;
 mod manager;
 mod worker;
 
+pub use consumer::EventConsumer;
 pub use manager::Manager;
 pub use worker::Worker;
@@ -1,8 +1,8 @@
 use std::{sync::Arc, time::Instant};
 
+use super::EventConsumer;
 use crate::Result;
 use cache::Cache;
-use event_stream::Consumer;
 use lazy_static::lazy_static;
 use model::{
     guild::{Guild, Member},
@@ -37,12 +37,12 @@ lazy_static! {
 
 pub struct Worker<C: Cache> {
     id: usize,
-    consumer: Arc<Consumer>,
+    consumer: Arc<EventConsumer>,
     cache: Arc<C>,
 }
 
 impl<C: Cache> Worker<C> {
-    pub fn new(id: usize, consumer: Arc<Consumer>, cache: Arc<C>) -> Self {
+    pub fn new(id: usize, consumer: Arc<EventConsumer>, cache: Arc<C>) -> Self {
         Self {
             id,
             consumer,
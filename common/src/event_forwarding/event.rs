@@ -3,11 +3,21 @@ use serde_json::value::RawValue;
 
 pub const EVENT_KEY: &str = "tickets:events";
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Event {
-    pub bot_token: String,
+    /// Only set while the producer is configured to include tokens. Consumers should resolve the
+    /// token from `bot_id` and `token_key` instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bot_token: Option<String>,
     pub bot_id: u64,
+    /// See `token_key`. Empty for events from producers that predate it.
+    #[serde(default)]
+    pub token_key: String,
     pub is_whitelabel: bool,
+    /// Set on events from the public bot in guilds that a whitelabel bot is also in, when the
+    /// sharder is configured to tag them. The same event is received from the whitelabel bot.
+    #[serde(default)]
+    pub served_by_whitelabel: bool,
     pub shard_id: u16,
     pub event: Box<RawValue>,
 }
@@ -6,8 +6,11 @@ pub const COMMAND_KEY: &str = "tickets:commands";
 
 #[derive(Serialize, Debug)]
 pub struct ForwardedInteraction<'a> {
-    pub bot_token: &'a str,
+    /// See `Event::bot_token`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bot_token: Option<&'a str>,
     pub bot_id: u64,
+    pub token_key: String,
     pub is_whitelabel: bool,
     pub interaction_type: InteractionType,
     pub data: Box<RawValue>,
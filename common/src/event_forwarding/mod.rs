@@ -3,3 +3,11 @@ pub use event::*;
 
 mod interaction;
 pub use interaction::*;
+
+mod token;
+pub use token::*;
+
+#[cfg(feature = "token-resolver")]
+mod token_resolver;
+#[cfg(feature = "token-resolver")]
+pub use token_resolver::*;
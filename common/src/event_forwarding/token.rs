@@ -0,0 +1,8 @@
+use sha2::{Digest, Sha256};
+
+/// Identifies a bot token without revealing it, so that events can reference the token that the
+/// bot was connected with. Consumers use it to tell whether a cached token is out of date.
+pub fn token_key(token: &str) -> String {
+    let digest = Sha256::digest(token.as_bytes());
+    hex::encode(&digest[..8])
+}
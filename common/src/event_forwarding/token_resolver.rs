@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use database::Database;
+use model::Snowflake;
+
+use super::Event;
+
+pub type ResolveResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Looks up bot tokens for events that reference them by `bot_id` and `token_key`
+#[async_trait]
+pub trait TokenResolver: Send + Sync {
+    /// Returns the current token of the bot, or None if the bot no longer exists
+    async fn resolve(&self, bot_id: u64, token_key: &str) -> ResolveResult<Option<String>>;
+}
+
+impl Event {
+    /// Uses the token included in the event if there is one, so that consumers work with events
+    /// from producers on either side of the migration
+    pub async fn resolve_token<R: TokenResolver + ?Sized>(
+        &self,
+        resolver: &R,
+    ) -> ResolveResult<Option<String>> {
+        match &self.bot_token {
+            Some(token) => Ok(Some(token.clone())),
+            None => resolver.resolve(self.bot_id, &self.token_key).await,
+        }
+    }
+}
+
+/// Fetches the current token of a bot, for `CachingTokenResolver`
+#[async_trait]
+pub trait TokenLookup: Send + Sync {
+    /// Returns None if the bot doesn't exist
+    async fn fetch_token(&self, bot_id: u64) -> ResolveResult<Option<String>>;
+}
+
+#[async_trait]
+impl TokenLookup for Database {
+    async fn fetch_token(&self, bot_id: u64) -> ResolveResult<Option<String>> {
+        let bot = self.whitelabel.get_bot_by_id(Snowflake(bot_id)).await?;
+        Ok(bot.map(|bot| bot.token))
+    }
+}
+
+#[async_trait]
+impl<L: TokenLookup + ?Sized> TokenLookup for Arc<L> {
+    async fn fetch_token(&self, bot_id: u64) -> ResolveResult<Option<String>> {
+        (**self).fetch_token(bot_id).await
+    }
+}
+
+// A key that doesn't match the cached token is expected for a short while after the token is
+// changed, so the token isn't fetched again for every event that still has the old key
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Resolves whitelabel tokens from the `whitelabel` table
+pub type DatabaseTokenResolver = CachingTokenResolver<Arc<Database>>;
+
+/// Resolves tokens with a `TokenLookup`. Tokens are cached until they are `ttl` old, or an event
+/// references a different token. Tokens of bots that the lookup doesn't know, such as the public
+/// bot, are added with `with_token`.
+pub struct CachingTokenResolver<L> {
+    lookup: L,
+    ttl: Duration,
+    static_tokens: HashMap<u64, String>,
+    cache: RwLock<HashMap<u64, CachedToken>>,
+}
+
+struct CachedToken {
+    token: String,
+    key: String,
+    fetched_at: Instant,
+}
+
+impl<L: TokenLookup> CachingTokenResolver<L> {
+    pub fn new(lookup: L, ttl: Duration) -> Self {
+        Self {
+            lookup,
+            ttl,
+            static_tokens: HashMap::new(),
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_token(mut self, bot_id: u64, token: String) -> Self {
+        self.static_tokens.insert(bot_id, token);
+        self
+    }
+
+    fn cached(&self, bot_id: u64, token_key: &str, now: Instant) -> Option<String> {
+        let cache = self.cache.read().unwrap();
+        let cached = cache.get(&bot_id)?;
+
+        let age = now.saturating_duration_since(cached.fetched_at);
+        let fresh = if cached.key == token_key {
+            age < self.ttl
+        } else {
+            age < MIN_REFETCH_INTERVAL
+        };
+
+        fresh.then(|| cached.token.clone())
+    }
+
+    async fn resolve_at(
+        &self,
+        bot_id: u64,
+        token_key: &str,
+        now: Instant,
+    ) -> ResolveResult<Option<String>> {
+        if let Some(token) = self.static_tokens.get(&bot_id) {
+            return Ok(Some(token.clone()));
+        }
+
+        if let Some(token) = self.cached(bot_id, token_key, now) {
+            return Ok(Some(token));
+        }
+
+        let token = self.lookup.fetch_token(bot_id).await?;
+
+        let mut cache = self.cache.write().unwrap();
+        match token {
+            Some(token) => {
+                cache.insert(
+                    bot_id,
+                    CachedToken {
+                        key: super::token_key(&token),
+                        token: token.clone(),
+                        fetched_at: now,
+                    },
+                );
+
+                Ok(Some(token))
+            }
+            None => {
+                cache.remove(&bot_id);
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<L: TokenLookup> TokenResolver for CachingTokenResolver<L> {
+    async fn resolve(&self, bot_id: u64, token_key: &str) -> ResolveResult<Option<String>> {
+        self.resolve_at(bot_id, token_key, Instant::now()).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::event_forwarding::token_key;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    // Bot 1 has the token in `token`. Every other bot doesn't exist.
+    #[derive(Default)]
+    struct StubLookup {
+        token: Mutex<String>,
+        fetches: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl TokenLookup for StubLookup {
+        async fn fetch_token(&self, bot_id: u64) -> ResolveResult<Option<String>> {
+            self.fetches.fetch_add(1, Ordering::Relaxed);
+            Ok(Some(self.token.lock().unwrap().clone()).filter(|_| bot_id == 1))
+        }
+    }
+
+    fn resolver(token: &str) -> CachingTokenResolver<Arc<StubLookup>> {
+        let lookup = StubLookup::default();
+        *lookup.token.lock().unwrap() = token.to_owned();
+
+        CachingTokenResolver::new(Arc::new(lookup), Duration::from_secs(60))
+    }
+
+    fn fetches(resolver: &CachingTokenResolver<Arc<StubLookup>>) -> usize {
+        resolver.lookup.fetches.load(Ordering::Relaxed)
+    }
+
+    #[tokio::test]
+    async fn test_caches_until_ttl() {
+        let resolver = resolver("a");
+        let now = Instant::now();
+
+        for offset in [0, 59] {
+            let token = resolver
+                .resolve_at(1, &token_key("a"), now + Duration::from_secs(offset))
+                .await
+                .unwrap();
+            assert_eq!(token.as_deref(), Some("a"));
+        }
+        assert_eq!(fetches(&resolver), 1);
+
+        *resolver.lookup.token.lock().unwrap() = "b".to_owned();
+        let token = resolver
+            .resolve_at(1, &token_key("a"), now + Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(token.as_deref(), Some("b"));
+        assert_eq!(fetches(&resolver), 2);
+    }
+
+    #[tokio::test]
+    async fn test_refetches_changed_token() {
+        let resolver = resolver("a");
+        let now = Instant::now();
+
+        resolver.resolve_at(1, &token_key("a"), now).await.unwrap();
+        *resolver.lookup.token.lock().unwrap() = "b".to_owned();
+
+        // Events with the old key are still in flight just after the token changes
+        let token = resolver
+            .resolve_at(1, &token_key("b"), now + Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(token.as_deref(), Some("a"));
+        assert_eq!(fetches(&resolver), 1);
+
+        let token = resolver
+            .resolve_at(1, &token_key("b"), now + MIN_REFETCH_INTERVAL)
+            .await
+            .unwrap();
+        assert_eq!(token.as_deref(), Some("b"));
+        assert_eq!(fetches(&resolver), 2);
+
+        // Cached under the new key
+        let token = resolver
+            .resolve_at(1, &token_key("b"), now + MIN_REFETCH_INTERVAL * 2)
+            .await
+            .unwrap();
+        assert_eq!(token.as_deref(), Some("b"));
+        assert_eq!(fetches(&resolver), 2);
+    }
+
+    #[tokio::test]
+    async fn test_static_and_unknown_bots() {
+        let resolver = resolver("a").with_token(2, "public".to_owned());
+        let now = Instant::now();
+
+        let token = resolver.resolve_at(2, "", now).await.unwrap();
+        assert_eq!(token.as_deref(), Some("public"));
+        assert_eq!(fetches(&resolver), 0);
+
+        assert!(resolver.resolve_at(3, "", now).await.unwrap().is_none());
+        assert!(resolver.resolve_at(3, "", now).await.unwrap().is_none());
+        assert_eq!(fetches(&resolver), 2);
+    }
+}
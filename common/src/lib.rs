@@ -1,6 +1,11 @@
 pub mod event_forwarding;
+pub mod presence_updates;
 pub mod status_updates;
+pub mod ticket_channels;
 pub mod token_change;
+pub mod whitelabel_guilds;
+pub mod whitelabel_health;
+pub mod whitelabel_retry;
 
 #[cfg(feature = "prometheus-server")]
 pub mod prometheus_server;
@@ -0,0 +1,16 @@
+use model::user::{ActivityType, StatusType};
+use serde::{Deserialize, Serialize};
+
+/// Changes the presence of every shard of the public bot
+pub const KEY: &str = "tickets:presence";
+
+/// The latest presence published to `KEY`, which shards identify with when they start. Set by the
+/// publisher before it publishes to `KEY`, as the sharders only read it.
+pub const CURRENT_KEY: &str = "tickets:presence:current";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Payload {
+    pub activity: String,
+    pub activity_type: ActivityType,
+    pub status: StatusType,
+}
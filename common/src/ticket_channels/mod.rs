@@ -0,0 +1,18 @@
+use model::Snowflake;
+use serde::{Deserialize, Serialize};
+
+/// A Redis set of the IDs of every open ticket channel and thread, maintained by the worker.
+/// Sharders with the channel interest filter enabled only forward MESSAGE_CREATE for channels
+/// in the set.
+pub const KEY: &str = "tickets:channels:active";
+
+/// Published by the worker after it adds a channel to or removes a channel from `KEY`, so that
+/// sharders don't wait for their next reload of the set
+pub const UPDATES_KEY: &str = "tickets:channels:active:updates";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Payload {
+    pub channel_id: Snowflake,
+    /// False if the ticket has been closed
+    pub active: bool,
+}
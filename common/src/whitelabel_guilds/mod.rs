@@ -0,0 +1,15 @@
+use model::Snowflake;
+use serde::{Deserialize, Serialize};
+
+/// Published by the whitelabel sharder when a whitelabel bot joins or leaves a guild, so that the
+/// public sharder can tell which guilds are served by a whitelabel bot. No leaves are published
+/// for a deleted bot: `whitelabel_health::BotState::Removed` is published instead.
+pub const KEY: &str = "tickets:whitelabel:guilds";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Payload {
+    pub bot_id: Snowflake,
+    pub guild_id: Snowflake,
+    /// False if the bot has left the guild. Other whitelabel bots may still be in it.
+    pub joined: bool,
+}
@@ -0,0 +1,58 @@
+use model::Snowflake;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Published every time the state of a whitelabel bot changes, and every `REFRESH_INTERVAL` while
+/// it is connected
+pub const KEY: &str = "tickets:whitelabel:health";
+
+/// How often the health of connected bots is refreshed, in `whitelabel_health` and on `KEY`
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A bot recorded as connected is treated as down once its health hasn't been refreshed for this
+/// long, as the sharder running it may have stopped without recording it
+pub const CONNECTED_TTL: Duration = Duration::from_secs(180);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Payload {
+    pub bot_id: Snowflake,
+    pub state: BotState,
+    /// Unix timestamp, in seconds
+    pub last_connected: Option<i64>,
+    pub reconnect_count: u32,
+    pub guild_count: u32,
+    /// Whether the bot last connected with the Message Content intent. It connects without it if
+    /// it isn't in GATEWAY_INTENTS, or after Discord rejected its privileged intents.
+    #[serde(default)]
+    pub message_content: bool,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BotState {
+    Connecting,
+    Connected,
+    /// Disconnected, and about to reconnect
+    Reconnecting,
+    /// Stopped after an error that reconnecting won't fix, until the sharder restarts
+    Stopped,
+    /// Parked after failing to connect too many times, until it is released with
+    /// `whitelabel_retry::KEY`
+    Quarantined,
+    /// The bot was deleted, as its token is invalid or it no longer exists
+    Removed,
+}
+
+impl BotState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BotState::Connecting => "connecting",
+            BotState::Connected => "connected",
+            BotState::Reconnecting => "reconnecting",
+            BotState::Stopped => "stopped",
+            BotState::Quarantined => "quarantined",
+            BotState::Removed => "removed",
+        }
+    }
+}
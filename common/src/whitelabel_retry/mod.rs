@@ -0,0 +1,3 @@
+/// Releases a quarantined whitelabel bot, so that the sharder running it tries to connect again.
+/// The payload is the ID of the bot.
+pub const KEY: &str = "tickets:whitelabel:retry";
@@ -1,13 +1,18 @@
 use sqlx::postgres::PgPoolOptions;
 use std::sync::Arc;
 
-use crate::{Table, Whitelabel, WhitelabelErrorTable, WhitelabelGuilds, WhitelabelStatus};
+use crate::{
+    ShardSessions, Table, Whitelabel, WhitelabelErrorTable, WhitelabelGuilds, WhitelabelHealth,
+    WhitelabelStatus,
+};
 
 pub struct Database {
     pub whitelabel: Whitelabel,
     pub whitelabel_errors: WhitelabelErrorTable,
     pub whitelabel_guilds: WhitelabelGuilds,
+    pub whitelabel_health: WhitelabelHealth,
     pub whitelabel_status: WhitelabelStatus,
+    pub shard_sessions: ShardSessions,
 }
 
 impl Database {
@@ -18,7 +23,9 @@ impl Database {
             whitelabel: Whitelabel::new(Arc::clone(&pool)),
             whitelabel_errors: WhitelabelErrorTable::new(Arc::clone(&pool)),
             whitelabel_guilds: WhitelabelGuilds::new(Arc::clone(&pool)),
+            whitelabel_health: WhitelabelHealth::new(Arc::clone(&pool)),
             whitelabel_status: WhitelabelStatus::new(Arc::clone(&pool)),
+            shard_sessions: ShardSessions::new(Arc::clone(&pool)),
         })
     }
 
@@ -26,7 +33,9 @@ impl Database {
         self.whitelabel.create_schema().await?;
         self.whitelabel_errors.create_schema().await?;
         self.whitelabel_guilds.create_schema().await?;
+        self.whitelabel_health.create_schema().await?;
         self.whitelabel_status.create_schema().await?;
+        self.shard_sessions.create_schema().await?;
 
         Ok(())
     }
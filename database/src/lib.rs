@@ -4,6 +4,9 @@ pub use database::Database;
 mod table;
 pub use table::Table;
 
+mod shard_sessions;
+pub use shard_sessions::{ShardSession, ShardSessions};
+
 mod whitelabel;
 pub use whitelabel::*;
 
@@ -13,8 +16,11 @@ pub use whitelabel_error::*;
 mod whitelabel_guilds;
 pub use whitelabel_guilds::WhitelabelGuilds;
 
+mod whitelabel_health;
+pub use whitelabel_health::{BotHealth, WhitelabelHealth};
+
 mod whitelabel_status;
-pub use whitelabel_status::WhitelabelStatus;
+pub use whitelabel_status::{BotStatus, WhitelabelStatus};
 
 // re-export sqlx for errors etc
 pub use sqlx;
@@ -0,0 +1,170 @@
+use async_trait::async_trait;
+
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use sqlx::{Error, PgPool};
+use std::sync::Arc;
+
+use crate::Table;
+
+/// Gateway sessions of sharders, so that shards can resume after a restart. Sessions are kept
+/// separately for each sharder deployment by namespace, and keyed by shard ID (public) or bot ID
+/// (whitelabel).
+pub struct ShardSessions {
+    db: Arc<PgPool>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ShardSession {
+    pub session_id: String,
+    pub seq: u64,
+    pub resume_url: Option<String>,
+}
+
+#[async_trait]
+impl Table for ShardSessions {
+    async fn create_schema(&self) -> Result<(), Error> {
+        sqlx::query(
+            r#"
+CREATE TABLE IF NOT EXISTS shard_sessions(
+	"namespace" varchar(32) NOT NULL,
+	"id" int8 NOT NULL,
+	"session_id" varchar(255) NOT NULL,
+	"seq" int8 NOT NULL,
+	"resume_url" varchar(255),
+	"updated_at" timestamptz NOT NULL DEFAULT NOW(),
+	PRIMARY KEY("namespace", "id")
+);
+"#,
+        )
+        .execute(&*self.db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl ShardSessions {
+    pub fn new(db: Arc<PgPool>) -> ShardSessions {
+        ShardSessions { db }
+    }
+
+    /// Sessions last written before `updated_after` are ignored, as Discord will have expired them
+    pub async fn get(
+        &self,
+        namespace: &str,
+        id: u64,
+        updated_after: DateTime<Utc>,
+    ) -> Result<Option<ShardSession>, Error> {
+        let query = r#"
+SELECT "session_id", "seq", "resume_url"
+FROM shard_sessions
+WHERE "namespace" = $1 AND "id" = $2 AND "updated_at" > $3;
+"#;
+
+        let row = sqlx::query_as::<_, (String, i64, Option<String>)>(query)
+            .bind(namespace)
+            .bind(id as i64)
+            .bind(updated_after)
+            .fetch_optional(&*self.db)
+            .await?;
+
+        Ok(row.map(|(session_id, seq, resume_url)| ShardSession {
+            session_id,
+            seq: seq as u64,
+            resume_url,
+        }))
+    }
+
+    pub async fn get_bulk(
+        &self,
+        namespace: &str,
+        ids: &[u64],
+        updated_after: DateTime<Utc>,
+    ) -> Result<Vec<(u64, ShardSession)>, Error> {
+        let query = r#"
+SELECT "id", "session_id", "seq", "resume_url"
+FROM shard_sessions
+WHERE "namespace" = $1 AND "id" = ANY($2) AND "updated_at" > $3;
+"#;
+
+        let ids: Vec<i64> = ids.iter().map(|id| *id as i64).collect();
+
+        let mut rows = sqlx::query_as::<_, (i64, String, i64, Option<String>)>(query)
+            .bind(namespace)
+            .bind(ids)
+            .bind(updated_after)
+            .fetch(&*self.db);
+
+        let mut sessions = Vec::new();
+        while let Some((id, session_id, seq, resume_url)) = rows.try_next().await? {
+            sessions.push((
+                id as u64,
+                ShardSession {
+                    session_id,
+                    seq: seq as u64,
+                    resume_url,
+                },
+            ));
+        }
+
+        Ok(sessions)
+    }
+
+    pub async fn set_bulk(
+        &self,
+        namespace: &str,
+        sessions: Vec<(u64, ShardSession)>,
+    ) -> Result<(), Error> {
+        if sessions.is_empty() {
+            return Ok(());
+        }
+
+        let query = r#"
+INSERT INTO shard_sessions("namespace", "id", "session_id", "seq", "resume_url", "updated_at")
+SELECT $1, data.*, NOW() FROM UNNEST($2::int8[], $3::varchar[], $4::int8[], $5::varchar[]) AS data
+ON CONFLICT("namespace", "id") DO UPDATE SET
+	"session_id" = EXCLUDED."session_id",
+	"seq" = EXCLUDED."seq",
+	"resume_url" = EXCLUDED."resume_url",
+	"updated_at" = EXCLUDED."updated_at";
+"#;
+
+        let mut ids = Vec::with_capacity(sessions.len());
+        let mut session_ids = Vec::with_capacity(sessions.len());
+        let mut seqs = Vec::with_capacity(sessions.len());
+        let mut resume_urls = Vec::with_capacity(sessions.len());
+
+        for (id, session) in sessions {
+            ids.push(id as i64);
+            session_ids.push(session.session_id);
+            seqs.push(session.seq as i64);
+            resume_urls.push(session.resume_url);
+        }
+
+        sqlx::query(query)
+            .bind(namespace)
+            .bind(ids)
+            .bind(session_ids)
+            .bind(seqs)
+            .bind(resume_urls)
+            .execute(&*self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_bulk(&self, namespace: &str, ids: &[u64]) -> Result<(), Error> {
+        let query = r#"DELETE FROM shard_sessions WHERE "namespace" = $1 AND "id" = ANY($2);"#;
+
+        let ids: Vec<i64> = ids.iter().map(|id| *id as i64).collect();
+
+        sqlx::query(query)
+            .bind(namespace)
+            .bind(ids)
+            .execute(&*self.db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -109,6 +109,19 @@ impl Whitelabel {
         Ok(bots)
     }
 
+    pub async fn get_bot_ids(&self) -> Result<Vec<Snowflake>, Error> {
+        let query = r#"SELECT "bot_id" FROM whitelabel"#;
+
+        let mut rows = sqlx::query_scalar::<_, i64>(query).fetch(&*self.db);
+
+        let mut bot_ids = Vec::new();
+        while let Some(bot_id) = rows.try_next().await? {
+            bot_ids.push(Snowflake(bot_id as u64));
+        }
+
+        Ok(bot_ids)
+    }
+
     pub async fn insert(&self, bot: WhitelabelBot) -> Result<(), Error> {
         let query = r#"
 INSERT INTO whitelabel
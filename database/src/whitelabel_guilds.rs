@@ -52,6 +52,20 @@ impl WhitelabelGuilds {
         Ok(guilds)
     }
 
+    /// Returns the bot ID and guild ID of every guild that a whitelabel bot is in
+    pub async fn get_all(&self) -> Result<Vec<(Snowflake, Snowflake)>, Error> {
+        let query = r#"SELECT "bot_id", "guild_id" FROM whitelabel_guilds;"#;
+
+        let mut rows = sqlx::query_as::<_, (i64, i64)>(query).fetch(&*self.db);
+
+        let mut guilds = Vec::new();
+        while let Some(row) = rows.try_next().await? {
+            guilds.push((Snowflake(row.0 as u64), Snowflake(row.1 as u64)));
+        }
+
+        Ok(guilds)
+    }
+
     pub async fn get_bot_by_guild(&self, guild_id: Snowflake) -> Result<Option<Snowflake>, Error> {
         let query = r#"SELECT "bot_id" from whitelabel_guilds WHERE "guild_id"=$1 LIMIT 1;"#;
 
@@ -80,27 +94,29 @@ impl WhitelabelGuilds {
         }
     }
 
-    pub async fn insert(&self, bot_id: Snowflake, guild_id: Snowflake) -> Result<(), Error> {
+    /// Returns false if the bot was already recorded as being in the guild
+    pub async fn insert(&self, bot_id: Snowflake, guild_id: Snowflake) -> Result<bool, Error> {
         let query = r#"INSERT INTO whitelabel_guilds("bot_id", "guild_id") VALUES($1, $2) ON CONFLICT("bot_id", "guild_id") DO NOTHING;"#;
 
-        sqlx::query(query)
+        let res = sqlx::query(query)
             .bind(bot_id.0 as i64)
             .bind(guild_id.0 as i64)
             .execute(&*self.db)
             .await?;
 
-        Ok(())
+        Ok(res.rows_affected() > 0)
     }
 
-    pub async fn delete(&self, bot_id: Snowflake, guild_id: Snowflake) -> Result<(), Error> {
+    /// Returns false if the bot was not recorded as being in the guild
+    pub async fn delete(&self, bot_id: Snowflake, guild_id: Snowflake) -> Result<bool, Error> {
         let query = r#"DELETE FROM whitelabel_guilds WHERE "bot_id" = $1 AND "guild_id" = $2;"#;
 
-        sqlx::query(query)
+        let res = sqlx::query(query)
             .bind(bot_id.0 as i64)
             .bind(guild_id.0 as i64)
             .execute(&*self.db)
             .await?;
 
-        Ok(())
+        Ok(res.rows_affected() > 0)
     }
 }
@@ -0,0 +1,141 @@
+use async_trait::async_trait;
+
+use chrono::{DateTime, Utc};
+use sqlx::{Error, PgPool};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::Table;
+
+use futures::TryStreamExt;
+use model::Snowflake;
+
+/// The connection state of each whitelabel bot, written by the sharder whenever it changes
+pub struct WhitelabelHealth {
+    db: Arc<PgPool>,
+}
+
+#[derive(sqlx::FromRow, Clone, Debug)]
+pub struct BotHealth {
+    pub bot_id: i64,
+    pub state: String,
+    pub last_connected: Option<DateTime<Utc>>,
+    pub reconnect_count: i32,
+    pub guild_count: i32,
+    /// Whether the bot last connected with the Message Content intent. It connects without it if
+    /// it isn't in GATEWAY_INTENTS, or after Discord rejected its privileged intents.
+    pub message_content: bool,
+    /// The last error that the bot recovered from. Fatal errors are in `whitelabel_errors`.
+    pub last_error: Option<String>,
+}
+
+#[async_trait]
+impl Table for WhitelabelHealth {
+    async fn create_schema(&self) -> Result<(), Error> {
+        sqlx::query(
+            r#"
+CREATE TABLE IF NOT EXISTS whitelabel_health(
+	"bot_id" int8 NOT NULL,
+	"state" varchar(16) NOT NULL,
+	"last_connected" timestamptz,
+	"reconnect_count" int4 NOT NULL DEFAULT 0,
+	"guild_count" int4 NOT NULL DEFAULT 0,
+	"message_content" bool NOT NULL DEFAULT false,
+	"last_error" varchar(255),
+	"updated_at" timestamptz NOT NULL DEFAULT NOW(),
+	FOREIGN KEY("bot_id") REFERENCES whitelabel("bot_id") ON DELETE CASCADE ON UPDATE CASCADE,
+	PRIMARY KEY("bot_id")
+);
+"#,
+        )
+        .execute(&*self.db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl WhitelabelHealth {
+    pub fn new(db: Arc<PgPool>) -> WhitelabelHealth {
+        WhitelabelHealth { db }
+    }
+
+    pub async fn get(&self, bot_id: Snowflake) -> Result<Option<BotHealth>, Error> {
+        let query = r#"
+SELECT "bot_id", "state", "last_connected", "reconnect_count", "guild_count", "message_content", "last_error"
+FROM whitelabel_health
+WHERE "bot_id" = $1;
+"#;
+
+        sqlx::query_as::<_, BotHealth>(query)
+            .bind(bot_id.0 as i64)
+            .fetch_optional(&*self.db)
+            .await
+    }
+
+    /// Returns the bots that are connected with the Message Content intent, along with how long
+    /// ago their health was last written. Bots whose health is older than `max_age` are skipped.
+    pub async fn get_message_content_bots(
+        &self,
+        max_age: Duration,
+    ) -> Result<Vec<(Snowflake, Duration)>, Error> {
+        let query = r#"
+SELECT "bot_id", EXTRACT(EPOCH FROM NOW() - "updated_at")::float8
+FROM whitelabel_health
+WHERE "state" = 'connected' AND "message_content" AND "updated_at" > NOW() - $1 * INTERVAL '1 second';
+"#;
+
+        let mut rows = sqlx::query_as::<_, (i64, f64)>(query)
+            .bind(max_age.as_secs_f64())
+            .fetch(&*self.db);
+
+        let mut bots = Vec::new();
+        while let Some((bot_id, age)) = rows.try_next().await? {
+            bots.push((
+                Snowflake(bot_id as u64),
+                Duration::from_secs_f64(age.max(0.0)),
+            ));
+        }
+
+        Ok(bots)
+    }
+
+    /// Refreshes `updated_at` without changing the health of the bots
+    pub async fn touch(&self, bot_ids: &[Snowflake]) -> Result<(), Error> {
+        let query =
+            r#"UPDATE whitelabel_health SET "updated_at" = NOW() WHERE "bot_id" = ANY($1);"#;
+
+        let bot_ids: Vec<i64> = bot_ids.iter().map(|id| id.0 as i64).collect();
+        sqlx::query(query).bind(bot_ids).execute(&*self.db).await?;
+
+        Ok(())
+    }
+
+    pub async fn set(&self, health: &BotHealth) -> Result<(), Error> {
+        let query = r#"
+INSERT INTO whitelabel_health("bot_id", "state", "last_connected", "reconnect_count", "guild_count", "message_content", "last_error", "updated_at")
+VALUES($1, $2, $3, $4, $5, $6, $7, NOW())
+ON CONFLICT("bot_id") DO UPDATE SET
+	"state" = EXCLUDED."state",
+	"last_connected" = EXCLUDED."last_connected",
+	"reconnect_count" = EXCLUDED."reconnect_count",
+	"guild_count" = EXCLUDED."guild_count",
+	"message_content" = EXCLUDED."message_content",
+	"last_error" = EXCLUDED."last_error",
+	"updated_at" = EXCLUDED."updated_at";
+"#;
+
+        sqlx::query(query)
+            .bind(health.bot_id)
+            .bind(&health.state)
+            .bind(health.last_connected)
+            .bind(health.reconnect_count)
+            .bind(health.guild_count)
+            .bind(health.message_content)
+            .bind(&health.last_error)
+            .execute(&*self.db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -2,16 +2,26 @@ use async_trait::async_trait;
 
 use sqlx::{Error, PgPool};
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::Table;
 
-use model::user::ActivityType;
+use model::user::{ActivityType, StatusType};
 use model::Snowflake;
 
 pub struct WhitelabelStatus {
     db: Arc<PgPool>,
 }
 
+#[derive(Clone, Debug)]
+pub struct BotStatus {
+    pub presence: StatusType,
+    /// Rotated through in order, every `rotation_interval`. The first activity is the one stored
+    /// in `whitelabel_statuses`. Empty if none of the activities has a known type.
+    pub activities: Vec<(String, ActivityType)>,
+    pub rotation_interval: Duration,
+}
+
 #[async_trait]
 impl Table for WhitelabelStatus {
     async fn create_schema(&self) -> Result<(), Error> {
@@ -29,6 +39,31 @@ CREATE TABLE IF NOT EXISTS whitelabel_statuses(
         .execute(&*self.db)
         .await?;
 
+        sqlx::query(
+            r#"
+ALTER TABLE whitelabel_statuses
+	ADD COLUMN IF NOT EXISTS "presence" varchar(16) NOT NULL DEFAULT 'online',
+	ADD COLUMN IF NOT EXISTS "rotation_interval" int4 NOT NULL DEFAULT 300;
+"#,
+        )
+        .execute(&*self.db)
+        .await?;
+
+        sqlx::query(
+            r#"
+CREATE TABLE IF NOT EXISTS whitelabel_status_activities(
+	"bot_id" int8 NOT NULL,
+	"position" int2 NOT NULL,
+	"status" varchar(255) NOT NULL,
+	"status_type" int2 NOT NULL,
+	FOREIGN KEY("bot_id") REFERENCES whitelabel_statuses("bot_id") ON DELETE CASCADE ON UPDATE CASCADE,
+	PRIMARY KEY("bot_id", "position")
+);
+"#,
+        )
+        .execute(&*self.db)
+        .await?;
+
         Ok(())
     }
 }
@@ -50,12 +85,15 @@ impl WhitelabelStatus {
         Ok((row.0, ActivityType::from_i16(row.1)))
     }
 
+    /// Sets a single activity, removing any others that the bot rotates through
     pub async fn set(
         &self,
         bot_id: Snowflake,
         status: String,
         status_type: ActivityType,
     ) -> Result<(), Error> {
+        let mut tx = self.db.begin().await?;
+
         let query = r#"
 INSERT INTO whitelabel_statuses("bot_id", "status", "status_type")
 VALUES($1, $2, $3)
@@ -66,10 +104,110 @@ ON CONFLICT("bot_id") DO UPDATE SET "status" = $2, "status_type" = $3;
             .bind(bot_id.0 as i64)
             .bind(status)
             .bind(status_type as i16)
-            .execute(&*self.db)
+            .execute(&mut *tx)
             .await?;
 
-        Ok(())
+        sqlx::query(r#"DELETE FROM whitelabel_status_activities WHERE "bot_id" = $1;"#)
+            .bind(bot_id.0 as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await
+    }
+
+    /// Returns None if the bot has no status set. Activities with an unknown type are skipped, and
+    /// an unknown presence is treated as online.
+    pub async fn get_status(&self, bot_id: Snowflake) -> Result<Option<BotStatus>, Error> {
+        let query = r#"
+SELECT "status", "status_type", "presence", "rotation_interval"
+FROM whitelabel_statuses
+WHERE "bot_id" = $1;
+"#;
+
+        let row = sqlx::query_as::<_, (String, i16, String, i32)>(query)
+            .bind(bot_id.0 as i64)
+            .fetch_optional(&*self.db)
+            .await?;
+
+        let Some((status, status_type, presence, rotation_interval)) = row else {
+            return Ok(None);
+        };
+
+        let query = r#"
+SELECT "status", "status_type"
+FROM whitelabel_status_activities
+WHERE "bot_id" = $1
+ORDER BY "position";
+"#;
+
+        let rows = sqlx::query_as::<_, (String, i16)>(query)
+            .bind(bot_id.0 as i64)
+            .fetch_all(&*self.db)
+            .await?;
+
+        let activities = std::iter::once((status, status_type))
+            .chain(rows)
+            .filter_map(|(status, status_type)| {
+                ActivityType::from_i16(status_type).map(|status_type| (status, status_type))
+            })
+            .collect();
+
+        Ok(Some(BotStatus {
+            presence: StatusType::from_name(&presence).unwrap_or(StatusType::Online),
+            activities,
+            rotation_interval: Duration::from_secs(rotation_interval.max(0) as u64),
+        }))
+    }
+
+    /// Replaces the status of the bot. Setting no activities removes the status, so the default
+    /// is used.
+    pub async fn set_status(&self, bot_id: Snowflake, status: &BotStatus) -> Result<(), Error> {
+        let Some(((first, first_type), rest)) = status.activities.split_first() else {
+            return self.delete(bot_id).await;
+        };
+
+        let mut tx = self.db.begin().await?;
+
+        let query = r#"
+INSERT INTO whitelabel_statuses("bot_id", "status", "status_type", "presence", "rotation_interval")
+VALUES($1, $2, $3, $4, $5)
+ON CONFLICT("bot_id") DO UPDATE SET
+	"status" = EXCLUDED."status",
+	"status_type" = EXCLUDED."status_type",
+	"presence" = EXCLUDED."presence",
+	"rotation_interval" = EXCLUDED."rotation_interval";
+"#;
+
+        sqlx::query(query)
+            .bind(bot_id.0 as i64)
+            .bind(first)
+            .bind(*first_type as i16)
+            .bind(status.presence.name())
+            .bind(status.rotation_interval.as_secs().min(i32::MAX as u64) as i32)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(r#"DELETE FROM whitelabel_status_activities WHERE "bot_id" = $1;"#)
+            .bind(bot_id.0 as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        let query = r#"
+INSERT INTO whitelabel_status_activities("bot_id", "position", "status", "status_type")
+VALUES($1, $2, $3, $4);
+"#;
+
+        for (position, (status, status_type)) in rest.iter().enumerate() {
+            sqlx::query(query)
+                .bind(bot_id.0 as i64)
+                .bind(position as i16 + 1)
+                .bind(status)
+                .bind(*status_type as i16)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await
     }
 
     pub async fn delete(&self, bot_id: Snowflake) -> Result<(), Error> {
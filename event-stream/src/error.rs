@@ -5,6 +5,15 @@ pub enum StreamError {
     #[error("Kafka error: {0}")]
     KafkaError(#[from] rdkafka::error::KafkaError),
 
+    #[error("Redis error: {0}")]
+    RedisError(#[from] redis::RedisError),
+
+    #[error("Redis pool error: {0}")]
+    PoolError(#[from] deadpool_redis::PoolError),
+
+    #[error("Redis pool creation error: {0}")]
+    CreatePoolError(#[from] deadpool_redis::CreatePoolError),
+
     #[error("serde_json error: {0}")]
     JsonError(#[from] serde_json::Error),
 }
@@ -5,4 +5,7 @@ mod consumer;
 pub use consumer::Consumer;
 
 mod publisher;
-pub use publisher::Publisher;
+pub use publisher::{Publisher, UndeliveredEvent};
+
+mod redis_stream;
+pub use redis_stream::{stream_key, RedisStreamConsumer, RedisStreamPublisher};
@@ -1,30 +1,86 @@
-use std::{sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+use std::{sync::Mutex, time::Duration};
 
 use common::event_forwarding;
-use rdkafka::{error::KafkaError, producer::{BaseProducer, BaseRecord, Producer}, types::RDKafkaErrorCode, ClientConfig};
-use crate::Result;
+use rdkafka::{
+    error::KafkaError,
+    message::Message,
+    producer::{BaseRecord, DeliveryResult, Producer, ProducerContext, ThreadedProducer},
+    types::RDKafkaErrorCode,
+    ClientConfig, ClientContext,
+};
+use tokio::sync::mpsc;
+use tracing::error;
+
+use crate::{Result, StreamError};
 
 pub struct Publisher {
     topic: String,
-    producer: BaseProducer,
-    since_last_poll: AtomicUsize,
+    producer: ThreadedProducer<DeliveryContext>,
+}
+
+/// An event that was accepted by `Publisher::send`, but that Kafka then failed to deliver
+pub struct UndeliveredEvent {
+    pub event: event_forwarding::Event,
+    pub guild_id: u64,
+    pub error: StreamError,
+}
+
+// Receives delivery reports from the producer's polling thread
+#[derive(Default)]
+struct DeliveryContext {
+    undelivered: Mutex<Option<mpsc::UnboundedSender<UndeliveredEvent>>>,
 }
 
-const POLL_INTERVAL: usize = 100;
+impl ClientContext for DeliveryContext {}
+
+impl ProducerContext for DeliveryContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, delivery_result: &DeliveryResult<'_>, _: Self::DeliveryOpaque) {
+        let Err((e, message)) = delivery_result else {
+            return;
+        };
+
+        error!(error = %e, "Failed to deliver event to Kafka");
+
+        let Some(tx) = self.undelivered.lock().unwrap().clone() else {
+            return;
+        };
+
+        let payload = message.payload().unwrap_or_default();
+        let event = match serde_json::from_slice::<event_forwarding::Event>(payload) {
+            Ok(event) => event,
+            Err(e) => {
+                error!(error = %e, "Failed to deserialize undelivered event");
+                return;
+            }
+        };
+
+        // The key is the guild ID
+        let guild_id = message
+            .key()
+            .and_then(|key| std::str::from_utf8(key).ok()?.parse().ok())
+            .unwrap_or(0);
+
+        _ = tx.send(UndeliveredEvent {
+            event,
+            guild_id,
+            error: e.clone().into(),
+        });
+    }
+}
 
 impl Publisher {
     pub fn new(brokers: Vec<String>, topic: String) -> Result<Self> {
-        let producer: BaseProducer = ClientConfig::new()
+        let producer = ClientConfig::new()
             .set("bootstrap.servers", brokers.join(","))
-            .create()?;
+            .create_with_context(DeliveryContext::default())?;
 
-        Ok(Self { 
-            topic, 
-            producer,
-            since_last_poll: AtomicUsize::new(0),
-        })
+        Ok(Self { topic, producer })
     }
 
+    /// Sends an event in the background. An `Ok` only means that the event was queued: events
+    /// that then fail to be delivered are sent to the receiver returned by `undelivered`.
     pub fn send(&self, ev: &event_forwarding::Event, guild_id: u64) -> Result<()> {
         let marshalled = serde_json::to_vec(ev)?;
 
@@ -33,10 +89,6 @@ impl Publisher {
             .payload(&marshalled)
             .key(key.as_str());
 
-        // Err is infallible
-        _ = self.since_last_poll.compare_exchange(POLL_INTERVAL, 0, Ordering::Relaxed, Ordering::Relaxed);
-        self.since_last_poll.fetch_add(1, Ordering::Relaxed);
-
         match self.producer.send(record) {
             Ok(_) => Ok(()),
             Err((e@KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), _)) => {
@@ -47,6 +99,14 @@ impl Publisher {
         }
     }
 
+    /// Returns a receiver of the events that fail to be delivered from now on, replacing the
+    /// previous receiver. Until this is called, failed deliveries are only logged.
+    pub fn undelivered(&self) -> mpsc::UnboundedReceiver<UndeliveredEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        *self.producer.context().undelivered.lock().unwrap() = Some(tx);
+        rx
+    }
+
     pub fn flush(&self, timeout: Duration) {
         self.producer.flush(timeout);
     }
@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+
+use common::event_forwarding::Event;
+use deadpool_redis::{Config, Pool, Runtime};
+use redis::streams::{StreamMaxlen, StreamReadOptions, StreamReadReply};
+use redis::AsyncCommands;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::Result;
+
+const EVENT_FIELD: &str = "event";
+
+const READ_COUNT: usize = 100;
+const READ_BLOCK_MILLIS: usize = 5000;
+
+/// Returns the stream that events of the guild are written to. Partitions only spread events
+/// across streams: every consumer in a group reads from all of them, so events of a guild are not
+/// guaranteed to be handled in order. Events without a guild use guild ID 0.
+pub fn stream_key(key: &str, partitions: u16, guild_id: u64) -> String {
+    // The timestamp bits, as the lowest bits of a snowflake are not evenly distributed
+    partition_key(key, (guild_id >> 22) % partitions.max(1) as u64)
+}
+
+fn partition_key(key: &str, partition: u64) -> String {
+    format!("{}:{}", key, partition)
+}
+
+/// Writes events to Redis streams, split across `partitions` streams named `{key}:{partition}`.
+/// Each stream is trimmed to roughly `max_len` entries, so events are lost if consumers fall
+/// that far behind.
+pub struct RedisStreamPublisher {
+    pool: Pool,
+    key: String,
+    partitions: u16,
+    max_len: usize,
+}
+
+impl RedisStreamPublisher {
+    pub fn new(pool: Pool, key: String, partitions: u16, max_len: usize) -> Self {
+        Self {
+            pool,
+            key,
+            partitions,
+            max_len,
+        }
+    }
+
+    pub async fn send(&self, ev: &Event, guild_id: u64) -> Result<()> {
+        let marshalled = serde_json::to_vec(ev)?;
+
+        let mut conn = self.pool.get().await?;
+        conn.xadd_maxlen::<_, _, _, _, ()>(
+            stream_key(&self.key, self.partitions, guild_id),
+            // Trimming exactly is much slower, and the stream length doesn't need to be exact
+            StreamMaxlen::Approx(self.max_len),
+            "*",
+            &[(EVENT_FIELD, marshalled)],
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Reads events written by a `RedisStreamPublisher` as part of a consumer group, so that each
+/// event is handled by a single consumer in the group. Like the Kafka consumer, events are
+/// acknowledged as soon as they are read, so an event being handled when the consumer stops is
+/// not redelivered.
+pub struct RedisStreamConsumer {
+    pool: Pool,
+    keys: Vec<String>,
+    group: String,
+    consumer: String,
+    buffered: Mutex<VecDeque<Event>>,
+}
+
+impl RedisStreamConsumer {
+    /// Creates the consumer group if it doesn't exist yet, starting from new events.
+    /// `partitions` must match the publisher.
+    pub async fn connect(
+        redis_uri: &str,
+        key: &str,
+        partitions: u16,
+        group: String,
+        consumer: String,
+    ) -> Result<Self> {
+        let pool = Config::from_url(redis_uri).create_pool(Some(Runtime::Tokio1))?;
+
+        let keys: Vec<String> = (0..partitions.max(1))
+            .map(|partition| partition_key(key, partition as u64))
+            .collect();
+
+        let mut conn = pool.get().await?;
+        for key in &keys {
+            let res: std::result::Result<(), _> =
+                conn.xgroup_create_mkstream(key, &group, "$").await;
+
+            match res {
+                Ok(()) => {}
+                // The group already exists
+                Err(e) if e.code() == Some("BUSYGROUP") => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(Self {
+            pool,
+            keys,
+            group,
+            consumer,
+            buffered: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    pub async fn recv(&self) -> Result<Event> {
+        let mut buffered = self.buffered.lock().await;
+
+        loop {
+            if let Some(ev) = buffered.pop_front() {
+                return Ok(ev);
+            }
+
+            self.read(&mut buffered).await?;
+        }
+    }
+
+    async fn read(&self, buffered: &mut VecDeque<Event>) -> Result<()> {
+        let opts = StreamReadOptions::default()
+            .group(&self.group, &self.consumer)
+            .count(READ_COUNT)
+            .block(READ_BLOCK_MILLIS)
+            .noack();
+
+        let ids = vec![">"; self.keys.len()];
+
+        let mut conn = self.pool.get().await?;
+        let reply: Option<StreamReadReply> = conn.xread_options(&self.keys, &ids, &opts).await?;
+
+        // None if no events were written before the read timed out
+        let Some(reply) = reply else {
+            return Ok(());
+        };
+
+        for entry in reply.keys.into_iter().flat_map(|key| key.ids) {
+            let Some(json) = entry.get::<Vec<u8>>(EVENT_FIELD) else {
+                warn!(id = %entry.id, "Received stream entry with no event. Ignoring.");
+                continue;
+            };
+
+            match serde_json::from_slice(&json) {
+                Ok(ev) => buffered.push_back(ev),
+                Err(e) => {
+                    warn!(id = %entry.id, error = %e, "Failed to parse stream entry. Ignoring.")
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -17,6 +17,15 @@ pub struct Config {
 
     pub worker_svc_uri: Box<str>,
     pub shard_count: u16,
+
+    /// Whether interactions forwarded to the worker include the bot token, for workers that can't
+    /// resolve tokens from `token_key` yet
+    #[serde(default = "default_include_bot_token")]
+    pub include_bot_token: bool,
+}
+
+fn default_include_bot_token() -> bool {
+    true
 }
 
 // shim
@@ -1,7 +1,7 @@
 use crate::http::Server;
 use crate::Error;
 use cache::Cache;
-use common::event_forwarding::ForwardedInteraction;
+use common::event_forwarding::{token_key, ForwardedInteraction};
 use database::WhitelabelBot;
 use ed25519_dalek::{PublicKey, Signature, Verifier};
 use model::guild::Member;
@@ -152,8 +152,9 @@ pub async fn forward<T: Cache>(
     let is_whitelabel = bot_id != server.config.public_bot_id;
 
     let wrapped = ForwardedInteraction {
-        bot_token: token.as_str(),
+        bot_token: server.config.include_bot_token.then(|| token.as_str()),
         bot_id: bot_id.0,
+        token_key: token_key(&token),
         is_whitelabel,
         interaction_type,
         data: RawValue::from_string(json).map_err(Error::JsonError)?,
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum StatusType {
     Online,
@@ -9,3 +9,27 @@ pub enum StatusType {
     Invisible,
     Offline,
 }
+
+impl StatusType {
+    /// The name used by the gateway
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatusType::Online => "online",
+            StatusType::Dnd => "dnd",
+            StatusType::Idle => "idle",
+            StatusType::Invisible => "invisible",
+            StatusType::Offline => "offline",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<StatusType> {
+        match name {
+            "online" => Some(StatusType::Online),
+            "dnd" => Some(StatusType::Dnd),
+            "idle" => Some(StatusType::Idle),
+            "invisible" => Some(StatusType::Invisible),
+            "offline" => Some(StatusType::Offline),
+            _ => None,
+        }
+    }
+}
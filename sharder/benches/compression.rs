@@ -0,0 +1,133 @@
+//! Compares the zlib-stream and zstd-stream transport decoders, reporting the bytes received over
+//! the websocket and the CPU time spent decoding them.
+//!
+//! The payloads are read from `benches/data/payloads.jsonl` (one gateway payload per line), which
+//! is a small synthetic sample. Set `GATEWAY_PAYLOADS` to the path of a capture from a real
+//! connection for more representative numbers.
+//!
+//! cargo bench -p sharder --bench compression --features compression,compression-zstd
+
+use flate2::{Compress, Compression, FlushCompress};
+use sharder::compression::{ZlibStreamDecoder, ZstdStreamDecoder};
+use std::time::{Duration, Instant};
+use zstd::stream::raw::{Encoder, InBuffer, Operation, OutBuffer};
+
+const DEFAULT_PAYLOADS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/data/payloads.jsonl");
+const ITERATIONS: u32 = 200;
+
+fn main() {
+    let path = std::env::var("GATEWAY_PAYLOADS").unwrap_or_else(|_| DEFAULT_PAYLOADS.to_owned());
+    let raw = std::fs::read_to_string(&path).expect("Failed to read payloads");
+    let payloads: Vec<&str> = raw.lines().filter(|line| !line.is_empty()).collect();
+
+    let raw_bytes: usize = payloads.iter().map(|payload| payload.len()).sum();
+    println!(
+        "{} payloads, {raw_bytes} bytes uncompressed ({path})",
+        payloads.len()
+    );
+
+    let zlib = compress_zlib(&payloads);
+    report("zlib-stream", raw_bytes, &zlib, || {
+        let mut decoder = ZlibStreamDecoder::new();
+        for msg in &zlib {
+            decoder.push(msg).unwrap().expect("Incomplete payload");
+        }
+    });
+
+    let zstd = compress_zstd(&payloads);
+    report("zstd-stream", raw_bytes, &zstd, || {
+        let mut decoder = ZstdStreamDecoder::new();
+        for msg in &zstd {
+            decoder.push(msg).unwrap().expect("Incomplete payload");
+        }
+    });
+}
+
+fn report(name: &str, raw_bytes: usize, messages: &[Vec<u8>], decode: impl Fn()) {
+    // Warm up allocator and caches
+    decode();
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        decode();
+    }
+    let per_iteration = start.elapsed() / ITERATIONS;
+
+    let compressed: usize = messages.iter().map(|msg| msg.len()).sum();
+    println!(
+        "{name}: {compressed} bytes ({:.1}% of uncompressed), {:?} to decode, {:.1} MiB/s",
+        compressed as f64 / raw_bytes as f64 * 100.0,
+        per_iteration,
+        throughput(raw_bytes, per_iteration),
+    );
+}
+
+fn throughput(bytes: usize, elapsed: Duration) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64()
+}
+
+// Compresses each payload into its own message as Discord does, sharing one context
+fn compress_zlib(payloads: &[&str]) -> Vec<Vec<u8>> {
+    let mut deflater = Compress::new(Compression::default(), true);
+
+    payloads
+        .iter()
+        .map(|payload| {
+            let mut output = Vec::with_capacity(payload.len() + 64);
+            let mut offset = 0;
+
+            loop {
+                output.reserve(payload.len());
+                let before = deflater.total_in();
+                deflater
+                    .compress_vec(
+                        &payload.as_bytes()[offset..],
+                        &mut output,
+                        FlushCompress::Sync,
+                    )
+                    .unwrap();
+                offset += (deflater.total_in() - before) as usize;
+
+                if offset >= payload.len() && output.len() < output.capacity() {
+                    break;
+                }
+            }
+
+            output
+        })
+        .collect()
+}
+
+fn compress_zstd(payloads: &[&str]) -> Vec<Vec<u8>> {
+    let mut encoder = Encoder::new(zstd::DEFAULT_COMPRESSION_LEVEL).unwrap();
+
+    payloads
+        .iter()
+        .map(|payload| {
+            let mut output = Vec::with_capacity(payload.len() + 128);
+            let mut input = InBuffer::around(payload.as_bytes());
+
+            while input.pos() < payload.len() {
+                output.reserve(payload.len());
+                let pos = output.len();
+                encoder
+                    .run(&mut input, &mut OutBuffer::around_pos(&mut output, pos))
+                    .unwrap();
+            }
+
+            loop {
+                output.reserve(128);
+                let pos = output.len();
+                let remaining = encoder
+                    .flush(&mut OutBuffer::around_pos(&mut output, pos))
+                    .unwrap();
+
+                if remaining == 0 {
+                    break;
+                }
+            }
+
+            output
+        })
+        .collect()
+}
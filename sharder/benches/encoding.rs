@@ -0,0 +1,118 @@
+//! Compares the cost of parsing gateway payloads received as JSON against payloads received as
+//! ETF, which are converted to JSON before following the same path.
+//!
+//! The payloads are read from `benches/data/payloads.jsonl` and converted to ETF the way the
+//! gateway encodes them, with atom keys and snowflakes as integers. Set `GATEWAY_PAYLOADS` to the
+//! path of a capture from a real connection for more representative numbers.
+//!
+//! cargo bench -p sharder --bench encoding
+
+use serde_json::Value;
+use sharder::etf;
+use sharder::payloads::parser::{find_opcode, find_seq};
+use sharder::payloads::{Dispatch, Opcode};
+use std::time::Instant;
+
+const DEFAULT_PAYLOADS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/data/payloads.jsonl");
+const ITERATIONS: u32 = 200;
+
+fn main() {
+    let path = std::env::var("GATEWAY_PAYLOADS").unwrap_or_else(|_| DEFAULT_PAYLOADS.to_owned());
+    let raw = std::fs::read_to_string(&path).expect("Failed to read payloads");
+    let json: Vec<&str> = raw.lines().filter(|line| !line.is_empty()).collect();
+
+    let etf: Vec<Vec<u8>> = json
+        .iter()
+        .map(|payload| encode_as_gateway(&serde_json::from_str(payload).unwrap()))
+        .collect();
+
+    // Consumers must not be able to tell which encoding was used
+    for (json, etf) in json.iter().zip(&etf) {
+        let converted: Value = serde_json::from_str(&etf::to_json(etf).unwrap()).unwrap();
+        assert_eq!(converted, serde_json::from_str::<Value>(json).unwrap());
+    }
+
+    let json_bytes: usize = json.iter().map(|payload| payload.len()).sum();
+    let etf_bytes: usize = etf.iter().map(|payload| payload.len()).sum();
+    println!("{} payloads ({path})", json.len());
+
+    let per_iteration = measure(|| {
+        for payload in &json {
+            parse(payload.to_string());
+        }
+    });
+    println!("json: {json_bytes} bytes, {per_iteration:?} to parse");
+
+    let per_iteration = measure(|| {
+        for payload in &etf {
+            parse(etf::to_json(payload).unwrap());
+        }
+    });
+    println!("etf: {etf_bytes} bytes, {per_iteration:?} to convert and parse");
+
+    let per_iteration = measure(|| {
+        for payload in &etf {
+            etf::to_json(payload).unwrap();
+        }
+    });
+    println!("etf: {per_iteration:?} of which is conversion to json");
+}
+
+fn measure(f: impl Fn()) -> std::time::Duration {
+    // Warm up allocator and caches
+    f();
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    start.elapsed() / ITERATIONS
+}
+
+// Mirrors Shard::process_payload up to the point the payload is handed to handle_event
+fn parse(raw: String) -> Option<Dispatch> {
+    let opcode = find_opcode(&raw)?;
+    find_seq(&raw);
+
+    match opcode {
+        Opcode::Dispatch => serde_json::from_str(&raw).ok(),
+        _ => None,
+    }
+}
+
+fn encode_as_gateway(value: &Value) -> Vec<u8> {
+    let mut out = vec![131];
+    write_value(&mut out, value);
+    out
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Object(map) => {
+            out.push(116); // MAP_EXT
+            out.extend_from_slice(&(map.len() as u32).to_be_bytes());
+            for (key, value) in map {
+                out.push(119); // SMALL_ATOM_UTF8_EXT
+                out.push(key.len() as u8);
+                out.extend_from_slice(key.as_bytes());
+                write_value(out, value);
+            }
+        }
+        Value::Array(values) if !values.is_empty() => {
+            out.push(108); // LIST_EXT
+            out.extend_from_slice(&(values.len() as u32).to_be_bytes());
+            for value in values {
+                write_value(out, value);
+            }
+            out.push(106); // NIL_EXT
+        }
+        // Snowflakes are sent as integers
+        Value::String(s) if s.len() >= 17 && s.bytes().all(|b| b.is_ascii_digit()) => {
+            let value: u64 = s.parse().unwrap();
+            out.extend_from_slice(&[110, 8, 0]); // SMALL_BIG_EXT
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        // Everything else is encoded the same way as outbound payloads
+        _ => out.extend_from_slice(&etf::encode(value)[1..]),
+    }
+}
@@ -1,15 +1,15 @@
 use std::sync::Arc;
 
 use model::user::{ActivityType, StatusType, StatusUpdate};
+use sharder::{await_shutdown, setup_sentry, Config, PublicShardManager, ShardCount, ShardManager};
+
 use sharder::{
-    await_shutdown, setup_sentry, Config, PublicShardManager, RedisSessionStore, ShardCount,
-    ShardManager,
+    build_event_filters, build_event_forwarder, build_redis, build_session_store, metrics_server,
+    Result,
 };
 
-use sharder::{build_redis, metrics_server, Result};
-
 use deadpool_redis::redis::cmd;
-use sharder::event_forwarding::KafkaEventForwarder;
+use sharder::event_forwarding::SpillingEventForwarder;
 use tracing::info;
 
 #[cfg(feature = "use-jemalloc")]
@@ -44,17 +44,6 @@ fn main() -> Result<()> {
 
 #[tracing::instrument(skip(config))]
 async fn run(config: Config) -> Result<()> {
-    #[cfg(feature = "metrics")]
-    {
-        let metrics_addr = config.metrics_addr.clone();
-
-        tokio::spawn(async move {
-            metrics_server::start_server(metrics_addr.as_str())
-                .await
-                .expect("Failed to start metrics server");
-        });
-    }
-
     let shard_count = get_shard_count(&config);
 
     let options = sharder::Options {
@@ -90,17 +79,51 @@ async fn run(config: Config) -> Result<()> {
     assert_eq!(res, "PONG");
     info!(service = "redis", "Redis connection test successful");
 
-    let session_store =
-        RedisSessionStore::new(Arc::clone(&redis), "tickets:resume:public".to_string(), 300);
+    let session_store = build_session_store(&config, Arc::clone(&redis), "public").await;
 
-    info!(service = "kafka", "Connecting to Kafka");
-    let event_forwarder =
-        Arc::new(KafkaEventForwarder::new(&config).expect("Failed to connect to Kafka"));
+    info!(stream = ?config.event_stream, "Connecting to event stream");
+    let event_forwarder = build_event_forwarder(&config, Arc::clone(&redis))
+        .expect("Failed to connect to event stream");
+    let event_forwarder = SpillingEventForwarder::new(event_forwarder, &config)
+        .await
+        .expect("Failed to open event spill queue");
+    let event_forwarder = Arc::new(event_forwarder);
 
-    let sm = PublicShardManager::new(config, options, session_store, redis, event_forwarder).await;
+    #[cfg(feature = "metrics")]
+    let metrics_addr = config.metrics_addr.clone();
+    #[cfg(feature = "metrics")]
+    let admin_token = config.admin_token.clone();
+
+    let event_filters = build_event_filters(&config, Arc::clone(&redis)).await;
+
+    let sm = PublicShardManager::new(
+        config,
+        options,
+        session_store,
+        redis,
+        event_forwarder,
+        event_filters,
+    )
+    .await;
+    let sm = Arc::new(sm);
+
+    #[cfg(feature = "metrics")]
+    {
+        let sm = Arc::clone(&sm);
+
+        tokio::spawn(async move {
+            metrics_server::start_server(metrics_addr.as_str(), admin_token, sm)
+                .await
+                .expect("Failed to start metrics server");
+        });
+    }
+
+    Arc::clone(&sm)
+        .listen_presence_updates()
+        .await
+        .expect("Failed to subscribe to presence updates");
 
     info!("Starting shard manager");
-    let sm = Arc::new(sm);
     Arc::clone(&sm).connect().await;
 
     await_shutdown()
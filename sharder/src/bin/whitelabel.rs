@@ -1,7 +1,8 @@
 use std::sync::Arc;
 
 use sharder::{
-    await_shutdown, build_redis, Config, RedisSessionStore, ShardManager, WhitelabelShardManager,
+    await_shutdown, build_event_filters, build_event_forwarder, build_redis, build_session_store,
+    Config, ShardManager, WhitelabelShardManager,
 };
 
 #[cfg(feature = "use-sentry")]
@@ -9,7 +10,7 @@ use sharder::setup_sentry;
 
 use database::{sqlx::postgres::PgPoolOptions, Database};
 
-use sharder::event_forwarding::KafkaEventForwarder;
+use sharder::event_forwarding::SpillingEventForwarder;
 use tracing::info;
 
 #[cfg(feature = "use-jemalloc")]
@@ -46,19 +47,27 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .min_connections(1)
         .max_connections(config.database_threads);
     let database = Arc::new(Database::connect(&config.database_uri[..], db_opts).await?);
+    database.create_schema().await?;
 
     // init redis
     let redis = Arc::new(build_redis(&config));
 
-    let session_store = RedisSessionStore::new(
-        Arc::clone(&redis),
-        "tickets:resume:whitelabel".to_string(),
-        300,
-    );
+    let session_store = build_session_store(&config, Arc::clone(&redis), "whitelabel").await;
 
-    info!(service = "kafka", "Connecting to Kafka");
-    let event_forwarder =
-        Arc::new(KafkaEventForwarder::new(&config).expect("Failed to connect to Kafka"));
+    info!(stream = ?config.event_stream, "Connecting to event stream");
+    let event_forwarder = build_event_forwarder(&config, Arc::clone(&redis))
+        .expect("Failed to connect to event stream");
+    let event_forwarder = SpillingEventForwarder::new(event_forwarder, &config)
+        .await
+        .expect("Failed to open event spill queue");
+    let event_forwarder = Arc::new(event_forwarder);
+
+    let event_filters = build_event_filters(&config, Arc::clone(&redis)).await;
+
+    #[cfg(feature = "metrics")]
+    let metrics_addr = config.metrics_addr.clone();
+    #[cfg(feature = "metrics")]
+    let admin_token = config.admin_token.clone();
 
     let sm = Arc::new(WhitelabelShardManager::new(
         config,
@@ -66,13 +75,26 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         redis,
         session_store,
         event_forwarder,
+        event_filters,
     ));
 
+    #[cfg(feature = "metrics")]
+    {
+        let sm = Arc::clone(&sm);
+
+        tokio::spawn(async move {
+            sharder::metrics_server::start_server(metrics_addr.as_str(), admin_token, sm)
+                .await
+                .expect("Failed to start metrics server");
+        });
+    }
+
     Arc::clone(&sm).connect().await;
 
     Arc::clone(&sm).listen_status_updates().await.unwrap();
     Arc::clone(&sm).listen_new_tokens().await.unwrap();
     Arc::clone(&sm).listen_delete().await.unwrap();
+    Arc::clone(&sm).listen_retry().await.unwrap();
 
     await_shutdown()
         .await
@@ -1,7 +1,19 @@
-use crate::Config;
+use crate::event_forwarding::{
+    ChannelInterestFilter, CompositeEventForwarder, EventFilters, EventStreamKind,
+    HttpEventForwarder, KafkaEventForwarder, RedisStreamEventForwarder, Route,
+};
+#[cfg(not(feature = "whitelabel"))]
+use crate::event_forwarding::{FilterAction, WhitelabelGuildFilter};
+use crate::{
+    Config, MemorySessionStore, PostgresSessionStore, RedisSessionStore, Result, SessionStore,
+    SessionStoreKind,
+};
+use database::{sqlx::postgres::PgPoolOptions, Database, Table};
 use deadpool::managed::PoolConfig;
 use deadpool::Runtime;
 use deadpool_redis::{Config as RedisConfig, Pool};
+use std::sync::Arc;
+use std::time::Duration;
 use tracing::Level;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::EnvFilter;
@@ -16,6 +28,149 @@ pub fn build_redis(config: &Config) -> Pool {
         .expect("Failed to create Redis pool")
 }
 
+/// Sends events to the event stream chosen by EVENT_STREAM, and to the worker if WORKER_SVC_URI
+/// is set. If SHADOW_PERCENT is set, events are also mirrored to the shadow topic and worker.
+#[allow(clippy::result_large_err)] // Only called on startup
+pub fn build_event_forwarder(config: &Config, redis: Arc<Pool>) -> Result<CompositeEventForwarder> {
+    let mut forwarder = match config.event_stream {
+        EventStreamKind::Kafka => CompositeEventForwarder::new()
+            .with_route(Route::Kafka, KafkaEventForwarder::new(config)?),
+        // Route::Kafka is the event stream, so that forwarding rules don't depend on EVENT_STREAM
+        EventStreamKind::Redis => CompositeEventForwarder::new()
+            .with_route(Route::Kafka, RedisStreamEventForwarder::new(config, redis)),
+    };
+
+    if config.worker_svc_uri.is_some() {
+        forwarder = forwarder.with_route(Route::Http, HttpEventForwarder::default());
+    }
+
+    for (&guild_id, &route) in &config.guild_forwarding_rules {
+        forwarder = forwarder.with_guild_route(guild_id, route);
+    }
+
+    if config.shadow_percent > 0 {
+        let mut shadow = CompositeEventForwarder::new();
+
+        if let Some(topic) = &config.shadow_kafka_topic {
+            shadow = shadow.with_route(
+                Route::Kafka,
+                KafkaEventForwarder::with_topic(config, topic.clone())?,
+            );
+        }
+
+        if let Some(uri) = config.get_shadow_worker_svc_uri() {
+            let client = HttpEventForwarder::build_http_client();
+            shadow = shadow.with_route(Route::Http, HttpEventForwarder::with_uri(client, uri));
+        }
+
+        forwarder = forwarder.with_shadow(config, shadow, config.shadow_percent);
+    }
+
+    Ok(forwarder)
+}
+
+/// Builds the filters that events pass through before they are forwarded. MESSAGE_CREATE is only
+/// forwarded for ticket channels if CHANNEL_INTEREST_FILTER is set, and the public sharder
+/// filters worker events in guilds with a whitelabel bot if WHITELABEL_GUILD_EVENTS is set.
+///
+/// panics on err
+#[tracing::instrument(skip(config, redis))]
+pub async fn build_event_filters(config: &Config, redis: Arc<Pool>) -> EventFilters {
+    let mut filters = EventFilters::new();
+
+    if config.channel_interest_filter {
+        let filter = Arc::new(ChannelInterestFilter::new(
+            redis,
+            config.channel_interest_fail_open,
+            Duration::from_secs(config.channel_interest_resync_interval),
+        ));
+
+        Arc::clone(&filter)
+            .start(config.get_redis_uri())
+            .await
+            .expect("Failed to load ticket channels");
+
+        filters = filters.with(filter);
+    }
+
+    #[cfg(not(feature = "whitelabel"))]
+    if config.whitelabel_guild_events != FilterAction::Forward {
+        let uri = config
+            .database_uri
+            .as_deref()
+            .expect("DATABASE_URI is not set");
+
+        let db_opts = PgPoolOptions::new().min_connections(1).max_connections(2);
+        let database = Database::connect(uri, db_opts)
+            .await
+            .expect("Failed to connect to whitelabel guilds database");
+
+        let filter = Arc::new(WhitelabelGuildFilter::new(
+            Arc::new(database),
+            config.whitelabel_guild_events,
+        ));
+
+        Arc::clone(&filter)
+            .start(&config.get_redis_uri())
+            .await
+            .expect("Failed to subscribe to whitelabel guild updates");
+
+        filters = filters.with(filter);
+    }
+
+    filters
+}
+
+// Discord no longer allows a session to be resumed after a few minutes
+const SESSION_EXPIRY_SECONDS: usize = 300;
+
+/// Builds the store chosen by SESSION_STORE. Sessions of different sharder deployments are kept
+/// apart by namespace.
+///
+/// panics on err
+#[tracing::instrument(skip(config, redis))]
+pub async fn build_session_store(
+    config: &Config,
+    redis: Arc<Pool>,
+    namespace: &str,
+) -> Box<dyn SessionStore> {
+    match config.session_store {
+        SessionStoreKind::Redis => Box::new(RedisSessionStore::new(
+            redis,
+            format!("tickets:resume:{namespace}"),
+            SESSION_EXPIRY_SECONDS,
+        )),
+        SessionStoreKind::Memory => Box::new(MemorySessionStore::new()),
+        SessionStoreKind::Postgres => {
+            #[cfg(feature = "whitelabel")]
+            let uri = config.database_uri.as_str();
+
+            #[cfg(not(feature = "whitelabel"))]
+            let uri = config
+                .database_uri
+                .as_deref()
+                .expect("DATABASE_URI is not set");
+
+            let db_opts = PgPoolOptions::new().min_connections(1).max_connections(2);
+            let database = Database::connect(uri, db_opts)
+                .await
+                .expect("Failed to connect to session store database");
+
+            database
+                .shard_sessions
+                .create_schema()
+                .await
+                .expect("Failed to create shard_sessions table");
+
+            Box::new(PostgresSessionStore::new(
+                Arc::new(database),
+                namespace,
+                SESSION_EXPIRY_SECONDS as i64,
+            ))
+        }
+    }
+}
+
 #[tracing::instrument(skip(config))]
 pub fn setup_sentry(config: &Config) -> sentry::ClientInitGuard {
     let guard = sentry::init((
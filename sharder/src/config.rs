@@ -1,9 +1,16 @@
-use serde::Deserialize;
-
 #[cfg(not(feature = "whitelabel"))]
+use crate::gateway::event_forwarding::FilterAction;
+use crate::gateway::event_forwarding::{
+    parse_guild_routes, EventStreamKind, ForwardingRules, Route,
+};
+use crate::gateway::{Encoding, Intents, SessionStoreKind};
+#[cfg(feature = "whitelabel")]
+use crate::manager::BotAssignment;
 use model::Snowflake;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     // Required
     pub sharder_id: u16,
@@ -13,14 +20,56 @@ pub struct Config {
     pub redis_threads: usize,
     pub sentry_dsn: String,
     pub worker_svc_uri: Option<String>,
-    pub kafka_brokers: Vec<String>,
-    pub kafka_topic: String,
 
     #[cfg(feature = "metrics")]
     pub metrics_addr: String,
+    #[cfg(feature = "metrics")]
+    pub admin_token: Option<String>,
+
+    // Optional
+    pub gateway_url: Option<String>,
+    pub gateway_bot_url: Option<String>,
+    #[serde(default)]
+    pub event_stream: EventStreamKind,
+    #[serde(default)]
+    pub kafka_brokers: Vec<String>,
+    #[serde(default)]
+    pub kafka_topic: String,
+    #[serde(default = "default_redis_stream_partitions")]
+    pub redis_stream_partitions: u16,
+    #[serde(default = "default_redis_stream_max_len")]
+    pub redis_stream_max_len: usize,
+    #[serde(default)]
+    pub gateway_encoding: Encoding,
+    #[serde(default, deserialize_with = "deserialize_intents")]
+    pub gateway_intents: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_forwarding_rules")]
+    pub forwarding_rules: ForwardingRules,
+    #[serde(default, deserialize_with = "deserialize_guild_routes")]
+    pub guild_forwarding_rules: HashMap<Snowflake, Route>,
+    pub shadow_kafka_topic: Option<String>,
+    pub shadow_worker_svc_uri: Option<String>,
+    #[serde(default)]
+    pub shadow_percent: u8,
+    #[serde(default)]
+    pub session_store: SessionStoreKind,
+    #[serde(default = "default_include_bot_token")]
+    pub include_bot_token: bool,
+    #[serde(default = "default_session_checkpoint_interval")]
+    pub session_checkpoint_interval: u64,
+    pub spill_dir: Option<String>,
+    #[serde(default = "default_spill_max_bytes")]
+    pub spill_max_bytes: u64,
+    #[serde(default)]
+    pub channel_interest_filter: bool,
+    #[serde(default = "default_channel_interest_fail_open")]
+    pub channel_interest_fail_open: bool,
+    #[serde(default = "default_channel_interest_resync_interval")]
+    pub channel_interest_resync_interval: u64,
 
     // Public Sharder
     #[cfg(not(feature = "whitelabel"))]
+    #[serde(default = "default_large_sharding_buckets")]
     pub large_sharding_buckets: u16,
     #[cfg(not(feature = "whitelabel"))]
     pub sharder_token: String,
@@ -28,6 +77,14 @@ pub struct Config {
     pub sharder_cluster_size: u16,
     #[cfg(not(feature = "whitelabel"))]
     pub bot_id: Snowflake,
+    #[cfg(not(feature = "whitelabel"))]
+    pub database_uri: Option<String>,
+    #[cfg(not(feature = "whitelabel"))]
+    #[serde(default = "default_shard_ready_timeout")]
+    pub shard_ready_timeout: u64,
+    #[cfg(not(feature = "whitelabel"))]
+    #[serde(default)]
+    pub whitelabel_guild_events: FilterAction,
 
     // Whitelabel Sharder
     #[cfg(feature = "whitelabel")]
@@ -35,11 +92,97 @@ pub struct Config {
     #[cfg(feature = "whitelabel")]
     #[serde(default = "one")]
     pub database_threads: u32,
+    #[cfg(feature = "whitelabel")]
+    #[serde(default)]
+    pub bot_assignment: BotAssignment,
+    #[cfg(feature = "whitelabel")]
+    #[serde(default = "default_lease_ttl")]
+    pub lease_ttl: u64,
+    #[cfg(feature = "whitelabel")]
+    #[serde(default = "default_reconnect_backoff_base")]
+    pub reconnect_backoff_base: u64,
+    #[cfg(feature = "whitelabel")]
+    #[serde(default = "default_reconnect_backoff_max")]
+    pub reconnect_backoff_max: u64,
+    #[cfg(feature = "whitelabel")]
+    #[serde(default = "default_quarantine_threshold")]
+    pub quarantine_threshold: usize,
+    #[cfg(feature = "whitelabel")]
+    #[serde(default = "default_quarantine_window")]
+    pub quarantine_window: u64,
 }
 
 impl Config {
     pub fn from_envvar() -> Config {
-        envy::from_env::<Config>().expect("Parsing config failed")
+        let config = envy::from_env::<Config>().expect("Parsing config failed");
+
+        if config.forwarding_rules.uses(Route::Http) && config.worker_svc_uri.is_none() {
+            panic!("FORWARDING_RULES routes events to HTTP, but WORKER_SVC_URI is not set");
+        }
+
+        let guild_routes_use_http = config
+            .guild_forwarding_rules
+            .values()
+            .any(|&r| r == Route::Http);
+        if guild_routes_use_http && config.worker_svc_uri.is_none() {
+            panic!("GUILD_FORWARDING_RULES routes events to HTTP, but WORKER_SVC_URI is not set");
+        }
+
+        if config.event_stream == EventStreamKind::Kafka
+            && (config.kafka_brokers.is_empty() || config.kafka_topic.is_empty())
+        {
+            panic!("EVENT_STREAM is kafka, but KAFKA_BROKERS or KAFKA_TOPIC is not set");
+        }
+
+        if config.redis_stream_partitions == 0 {
+            panic!("REDIS_STREAM_PARTITIONS must be at least 1");
+        }
+
+        if config.shadow_percent > 100 {
+            panic!("SHADOW_PERCENT must be between 0 and 100");
+        }
+
+        // Every route must have a shadow, as events can't be mirrored otherwise
+        if config.shadow_percent > 0 {
+            if config.shadow_kafka_topic.is_none() {
+                panic!("SHADOW_PERCENT is set, but SHADOW_KAFKA_TOPIC is not set");
+            }
+
+            if config.kafka_brokers.is_empty() {
+                panic!("SHADOW_PERCENT is set, but KAFKA_BROKERS is not set");
+            }
+
+            if config.worker_svc_uri.is_some() && config.shadow_worker_svc_uri.is_none() {
+                panic!("SHADOW_PERCENT is set, but SHADOW_WORKER_SVC_URI is not set");
+            }
+        }
+
+        if config.channel_interest_resync_interval == 0 {
+            panic!("CHANNEL_INTEREST_RESYNC_INTERVAL must be at least 1");
+        }
+
+        #[cfg(not(feature = "whitelabel"))]
+        if config.session_store == SessionStoreKind::Postgres && config.database_uri.is_none() {
+            panic!("SESSION_STORE is postgres, but DATABASE_URI is not set");
+        }
+
+        #[cfg(not(feature = "whitelabel"))]
+        if config.whitelabel_guild_events != FilterAction::Forward && config.database_uri.is_none()
+        {
+            panic!("WHITELABEL_GUILD_EVENTS is set, but DATABASE_URI is not set");
+        }
+
+        #[cfg(feature = "whitelabel")]
+        if config.lease_ttl == 0 {
+            panic!("LEASE_TTL must be at least 1");
+        }
+
+        #[cfg(feature = "whitelabel")]
+        if config.quarantine_threshold == 0 {
+            panic!("QUARANTINE_THRESHOLD must be at least 1");
+        }
+
+        config
     }
 
     pub fn get_worker_svc_uri(&self) -> Option<String> {
@@ -48,6 +191,17 @@ impl Config {
             .map(|s| format!("http://{}/event", s))
     }
 
+    pub fn get_shadow_worker_svc_uri(&self) -> Option<String> {
+        self.shadow_worker_svc_uri
+            .clone()
+            .map(|s| format!("http://{}/event", s))
+    }
+
+    /// The configured intents, or the intents the worker needs if none were configured
+    pub fn get_gateway_intents(&self) -> u64 {
+        self.gateway_intents.unwrap_or_else(default_intents)
+    }
+
     pub fn get_redis_uri(&self) -> String {
         match &self.redis_password {
             Some(pwd) => format!("redis://:{}@{}/", pwd, self.redis_addr),
@@ -56,7 +210,102 @@ impl Config {
     }
 }
 
+fn default_intents() -> u64 {
+    Intents::build(vec![
+        Intents::Guilds,
+        Intents::GuildMembers,
+        Intents::GuildMessages,
+        Intents::MessageContent,
+    ])
+}
+
+fn deserialize_intents<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    Intents::parse(&s)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_forwarding_rules<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<ForwardingRules, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    ForwardingRules::parse(&s).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_guild_routes<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<Snowflake, Route>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse_guild_routes(&s).map_err(serde::de::Error::custom)
+}
+
+fn default_redis_stream_partitions() -> u16 {
+    1
+}
+
+fn default_redis_stream_max_len() -> usize {
+    100_000
+}
+
+fn default_include_bot_token() -> bool {
+    true
+}
+
+fn default_session_checkpoint_interval() -> u64 {
+    5
+}
+
+fn default_spill_max_bytes() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn default_channel_interest_fail_open() -> bool {
+    true
+}
+
+fn default_channel_interest_resync_interval() -> u64 {
+    30
+}
+
+#[cfg(not(feature = "whitelabel"))]
+fn default_large_sharding_buckets() -> u16 {
+    1
+}
+
+#[cfg(not(feature = "whitelabel"))]
+fn default_shard_ready_timeout() -> u64 {
+    120
+}
+
 #[cfg(feature = "whitelabel")]
 fn one() -> u32 {
     1
 }
+
+#[cfg(feature = "whitelabel")]
+fn default_lease_ttl() -> u64 {
+    30
+}
+
+#[cfg(feature = "whitelabel")]
+fn default_reconnect_backoff_base() -> u64 {
+    500
+}
+
+#[cfg(feature = "whitelabel")]
+fn default_reconnect_backoff_max() -> u64 {
+    5 * 60 * 1000
+}
+
+#[cfg(feature = "whitelabel")]
+fn default_quarantine_threshold() -> usize {
+    10
+}
+
+#[cfg(feature = "whitelabel")]
+fn default_quarantine_window() -> u64 {
+    15 * 60
+}
@@ -4,12 +4,33 @@ pub struct CloseEvent {
     pub error: String,
 }
 
+/// How a shard should handle the gateway closing the connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseKind {
+    Reconnect,
+    /// 4004, the token is invalid or has been reset
+    InvalidToken,
+    /// 4014, privileged intents were requested that aren't enabled for the bot
+    DisallowedIntents,
+    /// Any other close that reconnecting won't fix, such as an invalid shard or API version
+    Fatal,
+}
+
 impl CloseEvent {
     pub fn new(status_code: u16, error: String) -> Self {
         Self { status_code, error }
     }
 
+    pub fn kind(&self) -> CloseKind {
+        match self.status_code {
+            4004 => CloseKind::InvalidToken,
+            4014 => CloseKind::DisallowedIntents,
+            4010..=4013 => CloseKind::Fatal,
+            _ => CloseKind::Reconnect,
+        }
+    }
+
     pub fn should_reconnect(&self) -> bool {
-        !matches!(self.status_code, 4004 | 4010 | 4011 | 4012 | 4013 | 4014)
+        self.kind() == CloseKind::Reconnect
     }
 }
@@ -0,0 +1,17 @@
+#[cfg(feature = "compression")]
+mod zlib;
+#[cfg(feature = "compression")]
+pub use zlib::ZlibStreamDecoder;
+
+#[cfg(feature = "compression-zstd")]
+mod zstd_stream;
+#[cfg(feature = "compression-zstd")]
+pub use zstd_stream::ZstdStreamDecoder;
+
+/// The decoder for the negotiated transport compression. zstd-stream takes precedence if both
+/// compression features are enabled.
+#[cfg(feature = "compression-zstd")]
+pub type TransportDecoder = ZstdStreamDecoder;
+
+#[cfg(not(feature = "compression-zstd"))]
+pub type TransportDecoder = ZlibStreamDecoder;
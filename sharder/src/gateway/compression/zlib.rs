@@ -0,0 +1,138 @@
+use flate2::{Decompress, DecompressError, FlushDecompress, Status};
+
+// Every complete zlib-stream message ends with a sync flush marker
+const ZLIB_SUFFIX: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+const CHUNK_SIZE: usize = 16 * 1024; // 16KiB
+
+/// Decoder for zlib-stream transport compression. The same inflate context is shared by every
+/// message on a connection, so one decoder must be used per connection and reset on reconnect.
+pub struct ZlibStreamDecoder {
+    inflater: Decompress,
+    buffer: Vec<u8>,
+}
+
+impl ZlibStreamDecoder {
+    /// Value of the compress gateway query parameter
+    pub const COMPRESS: &'static str = "zlib-stream";
+
+    pub fn new() -> Self {
+        Self {
+            inflater: Decompress::new(true),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    /// Buffers a websocket message, returning the decompressed payload once a complete message
+    /// has been received. After an error, the stream can't be recovered and the decoder must be
+    /// reset along with the connection.
+    pub fn push(&mut self, data: &[u8]) -> Result<Option<Vec<u8>>, DecompressError> {
+        self.buffer.extend_from_slice(data);
+
+        if !self.buffer.ends_with(&ZLIB_SUFFIX) {
+            return Ok(None);
+        }
+
+        let mut output: Vec<u8> = Vec::with_capacity(self.buffer.len() * 4);
+        let mut offset: usize = 0;
+
+        loop {
+            output.reserve(CHUNK_SIZE);
+
+            let (before_in, before_out) = (self.inflater.total_in(), self.inflater.total_out());
+            let status = self.inflater.decompress_vec(
+                &self.buffer[offset..],
+                &mut output,
+                FlushDecompress::Sync,
+            )?;
+
+            let consumed = (self.inflater.total_in() - before_in) as usize;
+            let produced = (self.inflater.total_out() - before_out) as usize;
+            offset += consumed;
+
+            // If there was still space in the output buffer, there is no more pending output
+            let done = offset >= self.buffer.len() && output.len() < output.capacity();
+            if done || status == Status::StreamEnd || (consumed == 0 && produced == 0) {
+                break;
+            }
+        }
+
+        self.buffer.clear();
+
+        Ok(Some(output))
+    }
+
+    pub fn reset(&mut self) {
+        self.inflater.reset(true);
+        self.buffer.clear();
+    }
+}
+
+impl Default for ZlibStreamDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::{Compress, Compression, FlushCompress};
+
+    fn compress_messages(messages: &[&str]) -> Vec<Vec<u8>> {
+        let mut deflater = Compress::new(Compression::default(), true);
+
+        messages
+            .iter()
+            .map(|msg| {
+                let mut output = Vec::with_capacity(msg.len() + 64);
+                deflater
+                    .compress_vec(msg.as_bytes(), &mut output, FlushCompress::Sync)
+                    .unwrap();
+                output
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_shares_context_between_messages() {
+        let messages = [
+            r#"{"op":10,"d":{"heartbeat_interval":41250}}"#,
+            r#"{"op":11}"#,
+        ];
+        let compressed = compress_messages(&messages);
+
+        let mut decoder = ZlibStreamDecoder::new();
+        for (data, expected) in compressed.iter().zip(messages) {
+            assert_eq!(
+                decoder.push(data).unwrap().as_deref(),
+                Some(expected.as_bytes())
+            );
+        }
+    }
+
+    #[test]
+    fn test_buffers_split_messages() {
+        let message = r#"{"op":0,"s":1,"t":"RESUMED","d":{}}"#;
+        let compressed = compress_messages(&[message]).remove(0);
+        let (first, second) = compressed.split_at(compressed.len() / 2);
+
+        let mut decoder = ZlibStreamDecoder::new();
+        assert_eq!(decoder.push(first).unwrap(), None);
+        assert_eq!(
+            decoder.push(second).unwrap().as_deref(),
+            Some(message.as_bytes())
+        );
+    }
+
+    #[test]
+    fn test_large_message() {
+        let message = format!(r#"{{"op":0,"d":"{}"}}"#, "a".repeat(CHUNK_SIZE * 8));
+        let compressed = compress_messages(&[&message]).remove(0);
+
+        let mut decoder = ZlibStreamDecoder::new();
+        assert_eq!(
+            decoder.push(&compressed).unwrap(),
+            Some(message.into_bytes())
+        );
+    }
+}
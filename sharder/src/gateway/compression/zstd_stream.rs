@@ -0,0 +1,208 @@
+use std::io;
+use zstd::stream::raw::{Decoder, InBuffer, Operation, OutBuffer};
+
+const CHUNK_SIZE: usize = 16 * 1024; // 16KiB
+
+// Size of a zstd block header. When the decoder asks for exactly this many bytes, every block
+// received so far has been decoded, and the next block has not started yet.
+const BLOCK_HEADER_SIZE: usize = 3;
+
+/// Decoder for zstd-stream transport compression. The whole connection is a single zstd frame,
+/// which is flushed at the end of every payload, so one decoder must be used per connection and
+/// reset on reconnect.
+pub struct ZstdStreamDecoder {
+    decoder: Decoder<'static>,
+    output: Vec<u8>,
+}
+
+impl ZstdStreamDecoder {
+    /// Value of the compress gateway query parameter
+    pub const COMPRESS: &'static str = "zstd-stream";
+
+    pub fn new() -> Self {
+        Self {
+            decoder: Decoder::new().expect("Failed to create zstd decoder"),
+            output: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    /// Decodes a websocket message, returning the decompressed payload once a complete payload
+    /// has been received. Payloads split across websocket messages are buffered until the rest
+    /// arrives. After an error, the stream can't be recovered and the decoder must be reset
+    /// along with the connection.
+    pub fn push(&mut self, data: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let mut input = InBuffer::around(data);
+
+        let hint = loop {
+            self.output.reserve(CHUNK_SIZE);
+
+            let pos = self.output.len();
+            let hint = {
+                let mut output = OutBuffer::around_pos(&mut self.output, pos);
+                self.decoder.run(&mut input, &mut output)?
+            };
+
+            // If there was still space in the output buffer, there is no more pending output. At
+            // the end of a frame, everything has been flushed.
+            let flushed = hint == 0 || self.output.len() < self.output.capacity();
+            if input.pos() >= data.len() && flushed {
+                break hint;
+            }
+        };
+
+        // Every payload is flushed, which ends its last block, so each websocket message that
+        // ends between blocks ends a payload. If the decoder is part way through a block, the
+        // rest of the payload is still to come.
+        if hint != 0 && hint != BLOCK_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let payload = std::mem::replace(&mut self.output, Vec::with_capacity(CHUNK_SIZE));
+        Ok(Some(payload))
+    }
+
+    pub fn reset(&mut self) {
+        if let Err(e) = self.decoder.reinit() {
+            // Only fails if the context is invalid, so start again with a new one
+            tracing::warn!(error = %e, "Error resetting zstd decoder, recreating");
+            self.decoder = Decoder::new().expect("Failed to create zstd decoder");
+        }
+
+        self.output.clear();
+    }
+}
+
+impl Default for ZstdStreamDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zstd::stream::raw::Encoder;
+
+    // zstd starts a new block after every 128KiB of a payload
+    const MAX_BLOCK_SIZE: usize = 128 * 1024;
+
+    // Compresses data, then flushes it to the end of the block
+    fn compress(encoder: &mut Encoder, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(data.len() + 128);
+
+        let mut input = InBuffer::around(data);
+        while input.pos() < data.len() {
+            let pos = output.len();
+            let mut out = OutBuffer::around_pos(&mut output, pos);
+            encoder.run(&mut input, &mut out).unwrap();
+        }
+
+        loop {
+            output.reserve(128);
+            let pos = output.len();
+            let mut out = OutBuffer::around_pos(&mut output, pos);
+            if encoder.flush(&mut out).unwrap() == 0 {
+                break;
+            }
+        }
+
+        output
+    }
+
+    fn compress_messages(messages: &[&str]) -> Vec<Vec<u8>> {
+        let mut encoder = Encoder::new(0).unwrap();
+
+        messages
+            .iter()
+            .map(|msg| compress(&mut encoder, msg.as_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn test_shares_context_between_messages() {
+        let messages = [
+            r#"{"op":10,"d":{"heartbeat_interval":41250}}"#,
+            r#"{"op":11}"#,
+        ];
+        let compressed = compress_messages(&messages);
+
+        let mut decoder = ZstdStreamDecoder::new();
+        for (data, expected) in compressed.iter().zip(messages) {
+            assert_eq!(
+                decoder.push(data).unwrap().as_deref(),
+                Some(expected.as_bytes())
+            );
+        }
+    }
+
+    #[test]
+    fn test_buffers_split_messages() {
+        let messages = [r#"{"op":11}"#, r#"{"op":0,"s":1,"t":"RESUMED","d":{}}"#];
+        let compressed = compress_messages(&messages);
+        let (first, second) = compressed[1].split_at(compressed[1].len() / 2);
+
+        let mut decoder = ZstdStreamDecoder::new();
+        assert_eq!(
+            decoder.push(&compressed[0]).unwrap().as_deref(),
+            Some(messages[0].as_bytes())
+        );
+        assert_eq!(decoder.push(first).unwrap(), None);
+        assert_eq!(
+            decoder.push(second).unwrap().as_deref(),
+            Some(messages[1].as_bytes())
+        );
+    }
+
+    #[test]
+    fn test_large_message() {
+        let message = format!(r#"{{"op":0,"d":"{}"}}"#, "a".repeat(CHUNK_SIZE * 8));
+        let compressed = compress_messages(&[&message]).remove(0);
+
+        let mut decoder = ZstdStreamDecoder::new();
+        assert_eq!(
+            decoder.push(&compressed).unwrap(),
+            Some(message.into_bytes())
+        );
+    }
+
+    #[test]
+    fn test_payload_of_full_block() {
+        // Ends exactly on a block boundary, but is flushed without ending the frame
+        let message = format!(r#"{{"op":0,"d":"{}"}}"#, "a".repeat(MAX_BLOCK_SIZE - 15));
+        assert_eq!(message.len(), MAX_BLOCK_SIZE);
+
+        let messages = [message.as_str(), r#"{"op":11}"#];
+        let compressed = compress_messages(&messages);
+
+        let mut decoder = ZstdStreamDecoder::new();
+        for (data, expected) in compressed.iter().zip(messages) {
+            assert_eq!(
+                decoder.push(data).unwrap().as_deref(),
+                Some(expected.as_bytes())
+            );
+        }
+    }
+
+    #[test]
+    fn test_frame_end_completes_payload() {
+        let message = format!(r#"{{"op":0,"d":"{}"}}"#, "a".repeat(MAX_BLOCK_SIZE - 15));
+        assert_eq!(message.len(), MAX_BLOCK_SIZE);
+
+        let mut encoder = Encoder::new(0).unwrap();
+        let mut compressed = compress(&mut encoder, message.as_bytes());
+        loop {
+            compressed.reserve(128);
+            let pos = compressed.len();
+            let mut out = OutBuffer::around_pos(&mut compressed, pos);
+            if encoder.finish(&mut out, true).unwrap() == 0 {
+                break;
+            }
+        }
+
+        let mut decoder = ZstdStreamDecoder::new();
+        assert_eq!(
+            decoder.push(&compressed).unwrap(),
+            Some(message.into_bytes())
+        );
+    }
+}
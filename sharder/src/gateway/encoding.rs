@@ -0,0 +1,21 @@
+use serde::Deserialize;
+
+/// The payload encoding requested from the gateway. ETF payloads are converted to JSON as soon as
+/// they are received, so everything past the websocket sees the same payloads either way.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Json,
+    Etf,
+}
+
+impl Encoding {
+    /// Value of the encoding gateway query parameter
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::Etf => "etf",
+        }
+    }
+}
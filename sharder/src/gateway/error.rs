@@ -69,6 +69,9 @@ pub enum GatewayError {
     #[error("json was missing field {0}")]
     MissingFieldError(String),
 
+    #[error("error occurred while decoding ETF payload: {0}")]
+    EtfError(#[from] crate::gateway::etf::EtfError),
+
     #[error("error occurred while parsing utf8 bytes: {0}")]
     Utf8Error(#[from] std::str::Utf8Error),
 
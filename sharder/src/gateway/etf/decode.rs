@@ -0,0 +1,332 @@
+use super::*;
+use std::fmt::Write;
+
+// Integers larger than this can't be represented exactly by a double. The gateway sends
+// snowflakes as integers over ETF, but as strings over JSON for this reason, so integers this
+// large are converted to strings to match.
+const MAX_SAFE_INTEGER: u128 = (1 << 53) - 1;
+
+/// Converts an ETF payload to the JSON that the gateway would have sent with `encoding=json`.
+/// Keys of the top level object are reordered so that `d` comes last, as the opcode and sequence
+/// number are found by searching the payload.
+pub fn to_json(input: &[u8]) -> Result<String, EtfError> {
+    let mut decoder = Decoder { input, pos: 0 };
+
+    let version = decoder.read_u8()?;
+    if version != VERSION {
+        return Err(EtfError::UnsupportedVersion(version));
+    }
+
+    let mut out = String::with_capacity(input.len() * 2);
+    decoder.write_term(&mut out, true)?;
+    Ok(out)
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn write_term(&mut self, out: &mut String, top_level: bool) -> Result<(), EtfError> {
+        let tag = self.read_u8()?;
+        match tag {
+            SMALL_INTEGER_EXT => write_int(out, self.read_u8()?),
+            INTEGER_EXT => write_int(out, i32::from_be_bytes(self.read_array()?)),
+            NEW_FLOAT_EXT => write_float(out, f64::from_be_bytes(self.read_array()?)),
+            FLOAT_EXT => {
+                let raw = self.read_str(31)?.trim_end_matches('\0');
+                write_float(out, raw.parse().map_err(|_| EtfError::InvalidFloat)?);
+            }
+            SMALL_BIG_EXT => {
+                let len = self.read_u8()? as usize;
+                self.write_big(out, len)?;
+            }
+            LARGE_BIG_EXT => {
+                let len = self.read_u32()? as usize;
+                self.write_big(out, len)?;
+            }
+            ATOM_EXT | ATOM_UTF8_EXT | SMALL_ATOM_EXT | SMALL_ATOM_UTF8_EXT => {
+                match self.read_atom(tag)? {
+                    "nil" => out.push_str("null"),
+                    "true" => out.push_str("true"),
+                    "false" => out.push_str("false"),
+                    atom => write_str(out, atom),
+                }
+            }
+            BINARY_EXT => {
+                let len = self.read_u32()? as usize;
+                write_str(out, self.read_str(len)?);
+            }
+            // Erlang strings are lists of bytes
+            STRING_EXT => {
+                let len = self.read_u16()? as usize;
+
+                out.push('[');
+                for (i, b) in self.read_bytes(len)?.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_int(out, *b);
+                }
+                out.push(']');
+            }
+            NIL_EXT => out.push_str("[]"),
+            LIST_EXT => {
+                let len = self.read_u32()?;
+                self.write_array(out, len)?;
+
+                if self.read_u8()? != NIL_EXT {
+                    return Err(EtfError::ImproperList);
+                }
+            }
+            SMALL_TUPLE_EXT => {
+                let len = self.read_u8()? as u32;
+                self.write_array(out, len)?;
+            }
+            LARGE_TUPLE_EXT => {
+                let len = self.read_u32()?;
+                self.write_array(out, len)?;
+            }
+            MAP_EXT => {
+                let len = self.read_u32()?;
+                self.write_map(out, len, top_level)?;
+            }
+            _ => return Err(EtfError::UnsupportedTag(tag)),
+        }
+
+        Ok(())
+    }
+
+    fn write_array(&mut self, out: &mut String, len: u32) -> Result<(), EtfError> {
+        out.push('[');
+        for i in 0..len {
+            if i > 0 {
+                out.push(',');
+            }
+            self.write_term(out, false)?;
+        }
+        out.push(']');
+
+        Ok(())
+    }
+
+    fn write_map(&mut self, out: &mut String, len: u32, top_level: bool) -> Result<(), EtfError> {
+        let mut data = None;
+        let mut first = true;
+
+        out.push('{');
+        for _ in 0..len {
+            let key = self.read_key()?;
+
+            if top_level && key == "d" {
+                let mut buf = String::with_capacity(self.input.len() - self.pos);
+                self.write_term(&mut buf, false)?;
+                data = Some(buf);
+                continue;
+            }
+
+            if !first {
+                out.push(',');
+            }
+            first = false;
+
+            write_str(out, key);
+            out.push(':');
+            self.write_term(out, false)?;
+        }
+
+        if let Some(data) = data {
+            if !first {
+                out.push(',');
+            }
+            out.push_str(r#""d":"#);
+            out.push_str(&data);
+        }
+        out.push('}');
+
+        Ok(())
+    }
+
+    fn write_big(&mut self, out: &mut String, len: usize) -> Result<(), EtfError> {
+        let sign = self.read_u8()?;
+        let digits = self.read_bytes(len)?;
+
+        // Digits are stored little endian
+        if digits.iter().skip(16).any(|&b| b != 0) {
+            return Err(EtfError::IntegerTooLarge(len));
+        }
+        let value = digits
+            .iter()
+            .take(16)
+            .enumerate()
+            .fold(0u128, |acc, (i, &b)| acc | ((b as u128) << (8 * i)));
+
+        let sign = if sign == 0 { "" } else { "-" };
+        if value > MAX_SAFE_INTEGER {
+            _ = write!(out, r#""{sign}{value}""#);
+        } else {
+            _ = write!(out, "{sign}{value}");
+        }
+
+        Ok(())
+    }
+
+    // JSON keys must be strings. The gateway uses atoms for keys, but accept binaries too.
+    fn read_key(&mut self) -> Result<&'a str, EtfError> {
+        let tag = self.read_u8()?;
+        match tag {
+            ATOM_EXT | ATOM_UTF8_EXT | SMALL_ATOM_EXT | SMALL_ATOM_UTF8_EXT => self.read_atom(tag),
+            BINARY_EXT => {
+                let len = self.read_u32()? as usize;
+                self.read_str(len)
+            }
+            _ => Err(EtfError::UnsupportedKey(tag)),
+        }
+    }
+
+    fn read_atom(&mut self, tag: u8) -> Result<&'a str, EtfError> {
+        let len = match tag {
+            SMALL_ATOM_EXT | SMALL_ATOM_UTF8_EXT => self.read_u8()? as usize,
+            _ => self.read_u16()? as usize,
+        };
+
+        self.read_str(len)
+    }
+
+    fn read_str(&mut self, len: usize) -> Result<&'a str, EtfError> {
+        std::str::from_utf8(self.read_bytes(len)?).map_err(|_| EtfError::InvalidUtf8)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, EtfError> {
+        let [b] = self.read_array()?;
+        Ok(b)
+    }
+
+    fn read_u16(&mut self) -> Result<u16, EtfError> {
+        Ok(u16::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, EtfError> {
+        Ok(u32::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], EtfError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.read_bytes(N)?);
+        Ok(array)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], EtfError> {
+        let end = self.pos.checked_add(len).ok_or(EtfError::UnexpectedEof)?;
+        let bytes = self
+            .input
+            .get(self.pos..end)
+            .ok_or(EtfError::UnexpectedEof)?;
+
+        self.pos = end;
+        Ok(bytes)
+    }
+}
+
+fn write_int(out: &mut String, value: impl std::fmt::Display) {
+    _ = write!(out, "{value}");
+}
+
+fn write_float(out: &mut String, value: f64) {
+    if value.is_finite() {
+        // Debug always includes a decimal point or exponent, so the value stays a float
+        _ = write!(out, "{value:?}");
+    } else {
+        out.push_str("null");
+    }
+}
+
+fn write_str(out: &mut String, value: &str) {
+    out.push('"');
+
+    // Every byte that needs escaping is ASCII, so slicing at it is always on a char boundary
+    let mut start = 0;
+    for (i, &b) in value.as_bytes().iter().enumerate() {
+        let escaped = match b {
+            b'"' => "\\\"",
+            b'\\' => "\\\\",
+            b'\n' => "\\n",
+            b'\r' => "\\r",
+            b'\t' => "\\t",
+            0..=0x1f => "",
+            _ => continue,
+        };
+
+        out.push_str(&value[start..i]);
+        if escaped.is_empty() {
+            _ = write!(out, "\\u{b:04x}");
+        } else {
+            out.push_str(escaped);
+        }
+        start = i + 1;
+    }
+
+    out.push_str(&value[start..]);
+    out.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn atom(out: &mut Vec<u8>, name: &str) {
+        out.push(SMALL_ATOM_UTF8_EXT);
+        out.push(name.len() as u8);
+        out.extend_from_slice(name.as_bytes());
+    }
+
+    #[test]
+    fn test_converts_gateway_payload() {
+        // {d: {id: 1100000000000000000, name: <<"a\"b">>, roles: [], nick: nil}, op: 0, s: 1, t: 'MESSAGE_CREATE'}
+        let mut input = vec![VERSION, MAP_EXT, 0, 0, 0, 4];
+        atom(&mut input, "d");
+        input.extend_from_slice(&[MAP_EXT, 0, 0, 0, 4]);
+        atom(&mut input, "id");
+        input.extend_from_slice(&[SMALL_BIG_EXT, 8, 0]);
+        input.extend_from_slice(&1100000000000000000u64.to_le_bytes());
+        atom(&mut input, "name");
+        input.extend_from_slice(&[BINARY_EXT, 0, 0, 0, 3, b'a', b'"', b'b']);
+        atom(&mut input, "roles");
+        input.push(NIL_EXT);
+        atom(&mut input, "nick");
+        atom(&mut input, "nil");
+        atom(&mut input, "op");
+        input.extend_from_slice(&[SMALL_INTEGER_EXT, 0]);
+        atom(&mut input, "s");
+        input.extend_from_slice(&[INTEGER_EXT, 0, 0, 1, 0]);
+        atom(&mut input, "t");
+        atom(&mut input, "MESSAGE_CREATE");
+
+        let json = to_json(&input).unwrap();
+        assert!(json.starts_with(r#"{"op":0,"s":256,"t":"MESSAGE_CREATE","d":"#));
+
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "op": 0,
+                "s": 256,
+                "t": "MESSAGE_CREATE",
+                "d": {
+                    "id": "1100000000000000000",
+                    "name": "a\"b",
+                    "roles": [],
+                    "nick": null,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_rejects_truncated_payload() {
+        let input = [VERSION, BINARY_EXT, 0, 0, 0, 10, b'a'];
+        assert!(matches!(to_json(&input), Err(EtfError::UnexpectedEof)));
+    }
+}
@@ -0,0 +1,108 @@
+use super::*;
+use serde_json::Value;
+use std::convert::TryFrom;
+
+/// Encodes a payload to be sent to the gateway. Keys and strings are encoded as binaries, and
+/// null and booleans as atoms.
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut out = Vec::with_capacity(128);
+    out.push(VERSION);
+    write_value(&mut out, value);
+    out
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => write_atom(out, "nil"),
+        Value::Bool(true) => write_atom(out, "true"),
+        Value::Bool(false) => write_atom(out, "false"),
+        Value::Number(n) => {
+            if let Some(value) = n.as_u64() {
+                write_int(out, false, value);
+            } else if let Some(value) = n.as_i64() {
+                write_int(out, value < 0, value.unsigned_abs());
+            } else if let Some(value) = n.as_f64() {
+                out.push(NEW_FLOAT_EXT);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        Value::String(s) => write_binary(out, s),
+        Value::Array(values) => {
+            if !values.is_empty() {
+                out.push(LIST_EXT);
+                out.extend_from_slice(&(values.len() as u32).to_be_bytes());
+                for value in values {
+                    write_value(out, value);
+                }
+            }
+
+            out.push(NIL_EXT);
+        }
+        Value::Object(map) => {
+            out.push(MAP_EXT);
+            out.extend_from_slice(&(map.len() as u32).to_be_bytes());
+            for (key, value) in map {
+                write_binary(out, key);
+                write_value(out, value);
+            }
+        }
+    }
+}
+
+fn write_int(out: &mut Vec<u8>, negative: bool, magnitude: u64) {
+    if !negative && magnitude <= u8::MAX as u64 {
+        out.push(SMALL_INTEGER_EXT);
+        out.push(magnitude as u8);
+    } else if let Ok(value) = i32::try_from(magnitude) {
+        let value = if negative { -value } else { value };
+        out.push(INTEGER_EXT);
+        out.extend_from_slice(&value.to_be_bytes());
+    } else {
+        let digits = magnitude.to_le_bytes();
+        let len = 8 - magnitude.leading_zeros() as usize / 8;
+
+        out.push(SMALL_BIG_EXT);
+        out.push(len as u8);
+        out.push(negative as u8);
+        out.extend_from_slice(&digits[..len]);
+    }
+}
+
+fn write_atom(out: &mut Vec<u8>, name: &str) {
+    out.push(SMALL_ATOM_UTF8_EXT);
+    out.push(name.len() as u8);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn write_binary(out: &mut Vec<u8>, value: &str) {
+    out.push(BINARY_EXT);
+    out.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    out.extend_from_slice(value.as_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_round_trips_through_decoder() {
+        let value = json!({
+            "op": 8,
+            "d": {
+                "guild_id": "602230000000000000",
+                "query": "",
+                "limit": 0,
+                "presences": false,
+                "nonce": null,
+                "user_ids": [],
+                "offset": -70000,
+                "ratio": 0.5,
+                "large": 4294967296u64,
+            },
+        });
+
+        let json = to_json(&encode(&value)).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), value);
+    }
+}
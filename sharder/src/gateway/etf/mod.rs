@@ -0,0 +1,57 @@
+//! Erlang External Term Format, as used by the gateway when connecting with `encoding=etf`.
+//! Only the subset of terms that the gateway sends and accepts is supported.
+
+mod decode;
+pub use decode::to_json;
+
+mod encode;
+pub use encode::encode;
+
+use thiserror::Error;
+
+const VERSION: u8 = 131;
+
+const NEW_FLOAT_EXT: u8 = 70;
+const SMALL_INTEGER_EXT: u8 = 97;
+const INTEGER_EXT: u8 = 98;
+const FLOAT_EXT: u8 = 99;
+const ATOM_EXT: u8 = 100;
+const SMALL_TUPLE_EXT: u8 = 104;
+const LARGE_TUPLE_EXT: u8 = 105;
+const NIL_EXT: u8 = 106;
+const STRING_EXT: u8 = 107;
+const LIST_EXT: u8 = 108;
+const BINARY_EXT: u8 = 109;
+const SMALL_BIG_EXT: u8 = 110;
+const LARGE_BIG_EXT: u8 = 111;
+const SMALL_ATOM_EXT: u8 = 115;
+const MAP_EXT: u8 = 116;
+const ATOM_UTF8_EXT: u8 = 118;
+const SMALL_ATOM_UTF8_EXT: u8 = 119;
+
+#[derive(Error, Debug)]
+pub enum EtfError {
+    #[error("unsupported ETF version {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("unsupported ETF tag {0}")]
+    UnsupportedTag(u8),
+
+    #[error("map key with ETF tag {0} can't be converted to a JSON key")]
+    UnsupportedKey(u8),
+
+    #[error("list was not terminated by NIL_EXT")]
+    ImproperList,
+
+    #[error("integer of {0} bytes is too large")]
+    IntegerTooLarge(usize),
+
+    #[error("float could not be parsed")]
+    InvalidFloat,
+
+    #[error("string was not valid utf8")]
+    InvalidUtf8,
+
+    #[error("unexpected end of payload")]
+    UnexpectedEof,
+}
@@ -0,0 +1,320 @@
+use super::{EventFilter, FilterAction};
+use crate::gateway::payloads::event::Event;
+use crate::Result;
+use common::ticket_channels;
+use deadpool_redis::redis::{self, aio::PubSub, cmd};
+use deadpool_redis::Pool;
+use futures::StreamExt;
+use model::Snowflake;
+use parking_lot::RwLock;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::{interval, sleep};
+use tracing::{error, info, warn};
+
+#[cfg(feature = "metrics")]
+use lazy_static::lazy_static;
+
+#[cfg(feature = "metrics")]
+use prometheus::{register_int_counter_vec, register_int_gauge, IntCounterVec, IntGauge};
+
+#[cfg(feature = "metrics")]
+lazy_static! {
+    static ref CHECKED: IntCounterVec = register_int_counter_vec!(
+        "event_channel_interest_total",
+        "The number of MESSAGE_CREATE events checked against the ticket channels, by result",
+        &["result"]
+    )
+    .expect("Failed to create channel interest counter");
+    static ref CHANNELS: IntGauge = register_int_gauge!(
+        "event_channel_interest_channels",
+        "The number of ticket channels that MESSAGE_CREATE is forwarded for"
+    )
+    .expect("Failed to create channel interest gauge");
+}
+
+// Channel IDs fetched per SSCAN, so that Redis isn't blocked by large sets
+const SCAN_COUNT: usize = 1000;
+
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// Drops MESSAGE_CREATE in guild channels that aren't tickets, as the worker ignores them. The
+/// channels are loaded from `ticket_channels::KEY`, reloaded every `resync_interval`, and kept up
+/// to date in between through `ticket_channels::UPDATES_KEY`. Direct messages are always
+/// forwarded.
+///
+/// Messages in channels missing from the set are still forwarded while the set is unavailable,
+/// unless the filter fails closed. The set is unavailable until it is first loaded, while
+/// resubscribing, and after two reloads in a row fail.
+pub struct ChannelInterestFilter {
+    redis: Arc<Pool>,
+    fail_open: bool,
+    resync_interval: Duration,
+    state: RwLock<ChannelSet>,
+}
+
+#[derive(Default)]
+struct ChannelSet {
+    channels: HashSet<Snowflake>,
+    synced_at: Option<Instant>,
+}
+
+impl ChannelSet {
+    fn is_fresh(&self, now: Instant, max_age: Duration) -> bool {
+        self.synced_at
+            .is_some_and(|synced_at| now.saturating_duration_since(synced_at) <= max_age)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Interest {
+    Ticket,
+    Dropped,
+    FailOpen,
+}
+
+impl Interest {
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    fn as_str(&self) -> &'static str {
+        match self {
+            Interest::Ticket => "ticket",
+            Interest::Dropped => "dropped",
+            Interest::FailOpen => "fail_open",
+        }
+    }
+}
+
+impl ChannelInterestFilter {
+    pub fn new(redis: Arc<Pool>, fail_open: bool, resync_interval: Duration) -> Self {
+        Self {
+            redis,
+            fail_open,
+            resync_interval,
+            state: RwLock::new(ChannelSet::default()),
+        }
+    }
+
+    /// Loads the channels, and keeps them up to date in the background. If the channels can't
+    /// be loaded, the filter only starts if it fails open.
+    pub async fn start(self: Arc<Self>, redis_uri: String) -> Result<()> {
+        let pubsub = match self.subscribe(&redis_uri).await {
+            Ok(pubsub) => Some(pubsub),
+            Err(e) if self.fail_open => {
+                warn!(error = %e, "Failed to load ticket channels, forwarding every message");
+                None
+            }
+            Err(e) => return Err(e),
+        };
+
+        tokio::spawn(self.run(redis_uri, pubsub));
+        Ok(())
+    }
+
+    // Loads the channels once subscribed, so that no updates are missed
+    async fn subscribe(&self, redis_uri: &str) -> Result<PubSub> {
+        let mut pubsub = redis::Client::open(redis_uri)?
+            .get_async_connection()
+            .await?
+            .into_pubsub();
+
+        pubsub.subscribe(ticket_channels::UPDATES_KEY).await?;
+        self.resync().await?;
+
+        Ok(pubsub)
+    }
+
+    async fn run(self: Arc<Self>, redis_uri: String, mut pubsub: Option<PubSub>) {
+        loop {
+            let conn = match pubsub.take() {
+                Some(conn) => conn,
+                None => match self.subscribe(&redis_uri).await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        error!(error = %e, "Failed to subscribe to ticket channel updates");
+                        sleep(RESUBSCRIBE_DELAY).await;
+                        continue;
+                    }
+                },
+            };
+
+            self.listen(conn).await;
+
+            // Updates are missed until the channels are loaded again
+            self.state.write().synced_at = None;
+            warn!("Ticket channel subscription closed, resubscribing");
+            sleep(RESUBSCRIBE_DELAY).await;
+        }
+    }
+
+    // Returns once the subscription closes
+    async fn listen(&self, mut pubsub: PubSub) {
+        let mut resync = interval(self.resync_interval);
+        // The first tick completes immediately, and the channels have just been loaded
+        resync.tick().await;
+
+        let mut stream = pubsub.on_message();
+
+        loop {
+            tokio::select! {
+                m = stream.next() => match m {
+                    Some(m) => self.handle_update(m.get_payload_bytes()),
+                    None => return,
+                },
+                _ = resync.tick() => {
+                    if let Err(e) = self.resync().await {
+                        error!(error = %e, "Failed to reload ticket channels");
+                    }
+                }
+            }
+        }
+    }
+
+    async fn resync(&self) -> Result<()> {
+        let mut conn = self.redis.get().await?;
+
+        let mut channels = HashSet::new();
+        let mut cursor: u64 = 0;
+        loop {
+            let (next, channel_ids): (u64, Vec<u64>) = cmd("SSCAN")
+                .arg(ticket_channels::KEY)
+                .arg(cursor)
+                .arg("COUNT")
+                .arg(SCAN_COUNT)
+                .query_async(&mut conn)
+                .await?;
+
+            channels.extend(channel_ids.into_iter().map(Snowflake));
+
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+
+        #[cfg(feature = "metrics")]
+        CHANNELS.set(channels.len() as i64);
+
+        let mut state = self.state.write();
+        if state.synced_at.is_none() {
+            info!(channel_count = channels.len(), "Loaded ticket channels");
+        }
+
+        *state = ChannelSet {
+            channels,
+            synced_at: Some(Instant::now()),
+        };
+
+        Ok(())
+    }
+
+    fn handle_update(&self, payload: &[u8]) {
+        let payload = match serde_json::from_slice::<ticket_channels::Payload>(payload) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!(error = %e, "Failed to decode ticket channel update");
+                return;
+            }
+        };
+
+        let mut state = self.state.write();
+        if payload.active {
+            state.channels.insert(payload.channel_id);
+        } else {
+            state.channels.remove(&payload.channel_id);
+        }
+
+        #[cfg(feature = "metrics")]
+        CHANNELS.set(state.channels.len() as i64);
+    }
+
+    fn interest(&self, channel_id: Snowflake) -> Interest {
+        let state = self.state.read();
+        check_interest(&state, channel_id, self.fail_open, self.resync_interval * 2)
+    }
+}
+
+fn check_interest(
+    state: &ChannelSet,
+    channel_id: Snowflake,
+    fail_open: bool,
+    max_age: Duration,
+) -> Interest {
+    if state.channels.contains(&channel_id) {
+        Interest::Ticket
+    } else if fail_open && !state.is_fresh(Instant::now(), max_age) {
+        Interest::FailOpen
+    } else {
+        Interest::Dropped
+    }
+}
+
+impl EventFilter for ChannelInterestFilter {
+    fn filter(&self, event: &Event, guild_id: Option<Snowflake>) -> FilterAction {
+        let channel_id = match event {
+            Event::MessageCreate(message) if guild_id.is_some() => message.channel_id,
+            _ => return FilterAction::Forward,
+        };
+
+        let interest = self.interest(channel_id);
+
+        #[cfg(feature = "metrics")]
+        CHECKED.with_label_values(&[interest.as_str()]).inc();
+
+        match interest {
+            Interest::Dropped => FilterAction::Drop,
+            Interest::Ticket | Interest::FailOpen => FilterAction::Forward,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_interest() {
+        let max_age = Duration::from_secs(60);
+        let ticket = Snowflake(1);
+        let other = Snowflake(2);
+
+        let mut state = ChannelSet {
+            channels: std::iter::once(ticket).collect(),
+            synced_at: None,
+        };
+
+        // Never loaded
+        assert_eq!(
+            check_interest(&state, other, true, max_age),
+            Interest::FailOpen
+        );
+        assert_eq!(
+            check_interest(&state, other, false, max_age),
+            Interest::Dropped
+        );
+
+        state.synced_at = Some(Instant::now());
+        assert_eq!(
+            check_interest(&state, ticket, true, max_age),
+            Interest::Ticket
+        );
+        assert_eq!(
+            check_interest(&state, other, true, max_age),
+            Interest::Dropped
+        );
+    }
+
+    #[test]
+    fn test_is_fresh() {
+        let max_age = Duration::from_secs(60);
+        let synced_at = Instant::now();
+        let state = ChannelSet {
+            channels: HashSet::new(),
+            synced_at: Some(synced_at),
+        };
+
+        assert!(state.is_fresh(synced_at + Duration::from_secs(60), max_age));
+        assert!(!state.is_fresh(synced_at + Duration::from_secs(61), max_age));
+        assert!(!ChannelSet::default().is_fresh(synced_at, max_age));
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use common::event_forwarding;
+use event_stream::UndeliveredEvent;
+use model::Snowflake;
+use tokio::sync::{mpsc, Semaphore};
+use tracing::warn;
+
+use crate::{Config, GatewayError, Result};
+
+use super::{EventForwarder, Route};
+
+#[cfg(feature = "metrics")]
+use lazy_static::lazy_static;
+
+#[cfg(feature = "metrics")]
+use prometheus::{register_int_counter_vec, IntCounterVec};
+
+#[cfg(feature = "metrics")]
+lazy_static! {
+    static ref SHADOW_FORWARDED: IntCounterVec = register_int_counter_vec!(
+        "event_shadow_forwarded_total",
+        "The number of events mirrored to the shadow forwarder, by whether it succeeded",
+        &["route", "success"]
+    )
+    .expect("Failed to create shadow forwarded counter");
+}
+
+/// Sends each event to the forwarder registered for its route. The route is decided by the
+/// forwarding rules from the event type, unless the event's guild has a route of its own.
+///
+/// A percentage of events can also be mirrored to a shadow forwarder, for example a new Kafka
+/// topic during a migration. Guilds are either always or never mirrored, so that the shadow sees
+/// every event of the guilds it is given. Events are sent to the shadow in the background, so
+/// that a slow shadow never delays forwarding. Shadow errors are logged, and never fail the event.
+pub struct CompositeEventForwarder {
+    forwarders: HashMap<Route, Arc<dyn EventForwarder>>,
+    guild_routes: HashMap<Snowflake, Route>,
+    shadow: Option<Shadow>,
+}
+
+struct Shadow {
+    forwarder: Arc<dyn EventForwarder>,
+    config: Arc<Config>,
+    percent: u8,
+    // Used to sample events without a guild
+    counter: AtomicU64,
+    // Limits the events being sent to the shadow, so that tasks can't pile up if it stops
+    // responding
+    in_flight: Arc<Semaphore>,
+}
+
+const MAX_SHADOW_IN_FLIGHT: usize = 1000;
+
+impl CompositeEventForwarder {
+    pub fn new() -> Self {
+        Self {
+            forwarders: HashMap::new(),
+            guild_routes: HashMap::new(),
+            shadow: None,
+        }
+    }
+
+    /// Sends events with the given route to the forwarder, replacing any already registered
+    pub fn with_route(mut self, route: Route, forwarder: impl EventForwarder) -> Self {
+        self.forwarders.insert(route, Arc::new(forwarder));
+        self
+    }
+
+    /// Overrides the route of every forwarded event from the guild
+    pub fn with_guild_route(mut self, guild_id: Snowflake, route: Route) -> Self {
+        self.guild_routes.insert(guild_id, route);
+        self
+    }
+
+    /// Mirrors `percent`% of events to the shadow forwarder, with the same route. As events are
+    /// mirrored in the background, the shadow is given its own copy of the config.
+    pub fn with_shadow(
+        mut self,
+        config: &Config,
+        forwarder: impl EventForwarder,
+        percent: u8,
+    ) -> Self {
+        self.shadow = Some(Shadow {
+            forwarder: Arc::new(forwarder),
+            config: Arc::new(config.clone()),
+            percent: percent.min(100),
+            counter: AtomicU64::new(0),
+            in_flight: Arc::new(Semaphore::new(MAX_SHADOW_IN_FLIGHT)),
+        });
+        self
+    }
+
+    fn route_for(&self, guild_id: Option<Snowflake>, route: Route) -> Route {
+        guild_id
+            .and_then(|guild_id| self.guild_routes.get(&guild_id).copied())
+            .unwrap_or(route)
+    }
+
+    async fn forward_primary(
+        &self,
+        config: &Config,
+        event: event_forwarding::Event,
+        guild_id: Option<Snowflake>,
+        route: Route,
+    ) -> Result<()> {
+        match self.forwarders.get(&route) {
+            Some(forwarder) => {
+                forwarder
+                    .forward_event(config, event, guild_id, route)
+                    .await
+            }
+            None => GatewayError::custom(format!(
+                "event routed to {}, but no forwarder is set",
+                route.as_str()
+            ))
+            .into(),
+        }
+    }
+}
+
+impl Default for CompositeEventForwarder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shadow {
+    fn mirrors(&self, guild_id: Option<Snowflake>) -> bool {
+        let n = match guild_id {
+            // The timestamp bits, as the lowest bits of a snowflake are not evenly distributed
+            Some(guild_id) => guild_id.0 >> 22,
+            None => self.counter.fetch_add(1, Ordering::Relaxed),
+        };
+
+        n % 100 < self.percent as u64
+    }
+
+    fn forward_event(
+        &self,
+        event: event_forwarding::Event,
+        guild_id: Option<Snowflake>,
+        route: Route,
+    ) {
+        let Ok(permit) = Arc::clone(&self.in_flight).try_acquire_owned() else {
+            record_shadow_result(route, false);
+            warn!(
+                ?route,
+                "Too many events being sent to the shadow, skipping event"
+            );
+            return;
+        };
+
+        let forwarder = Arc::clone(&self.forwarder);
+        let config = Arc::clone(&self.config);
+
+        tokio::spawn(async move {
+            let res = forwarder
+                .forward_event(&config, event, guild_id, route)
+                .await;
+            drop(permit);
+
+            record_shadow_result(route, res.is_ok());
+
+            if let Err(e) = res {
+                warn!(error = %e, ?route, "Error forwarding event to shadow");
+            }
+        });
+    }
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn record_shadow_result(route: Route, success: bool) {
+    #[cfg(feature = "metrics")]
+    SHADOW_FORWARDED
+        .with_label_values(&[route.as_str(), if success { "true" } else { "false" }])
+        .inc();
+}
+
+#[async_trait]
+impl EventForwarder for CompositeEventForwarder {
+    async fn forward_event(
+        &self,
+        config: &Config,
+        event: event_forwarding::Event,
+        guild_id: Option<Snowflake>,
+        route: Route,
+    ) -> Result<()> {
+        let route = self.route_for(guild_id, route);
+        if route == Route::Drop {
+            return Ok(());
+        }
+
+        match &self.shadow {
+            Some(shadow) if shadow.mirrors(guild_id) => {
+                shadow.forward_event(event.clone(), guild_id, route);
+                self.forward_primary(config, event, guild_id, route).await
+            }
+            _ => self.forward_primary(config, event, guild_id, route).await,
+        }
+    }
+
+    /// Flushes every forwarder, returning the first error
+    async fn flush(&self) -> Result<()> {
+        let mut res = Ok(());
+
+        let shadow = self.shadow.as_ref().map(|shadow| &shadow.forwarder);
+        for forwarder in self.forwarders.values().chain(shadow) {
+            if let Err(e) = forwarder.flush().await {
+                warn!(error = %e, "Error flushing event forwarder");
+                res = res.and(Err(e));
+            }
+        }
+
+        res
+    }
+
+    /// Only events routed to Kafka are delivered in the background. Events mirrored to the shadow
+    /// are not reported, as shadow errors never fail the event.
+    fn undelivered_events(&self) -> Option<mpsc::UnboundedReceiver<UndeliveredEvent>> {
+        self.forwarders.get(&Route::Kafka)?.undelivered_events()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::{config, event};
+    use parking_lot::Mutex;
+
+    #[derive(Clone, Default)]
+    struct Recorder {
+        events: Arc<Mutex<Vec<(u16, Route)>>>,
+        flushes: Arc<AtomicU64>,
+    }
+
+    #[async_trait]
+    impl EventForwarder for Recorder {
+        async fn forward_event(
+            &self,
+            _config: &Config,
+            event: event_forwarding::Event,
+            _guild_id: Option<Snowflake>,
+            route: Route,
+        ) -> Result<()> {
+            self.events.lock().push((event.shard_id, route));
+            Ok(())
+        }
+
+        async fn flush(&self) -> Result<()> {
+            self.flushes.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routes_and_mirrors_events() {
+        let (kafka, http, shadow) = (
+            Recorder::default(),
+            Recorder::default(),
+            Recorder::default(),
+        );
+        let config = config(&[]);
+        let forwarder = CompositeEventForwarder::new()
+            .with_route(Route::Kafka, kafka.clone())
+            .with_route(Route::Http, http.clone())
+            .with_guild_route(Snowflake(1), Route::Http)
+            .with_guild_route(Snowflake(2), Route::Drop)
+            .with_shadow(&config, shadow.clone(), 100);
+
+        for (shard_id, guild_id) in [(0, None), (1, Some(Snowflake(1))), (2, Some(Snowflake(2)))] {
+            forwarder
+                .forward_event(&config, event(shard_id), guild_id, Route::Kafka)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(*kafka.events.lock(), [(0, Route::Kafka)]);
+        assert_eq!(*http.events.lock(), [(1, Route::Http)]);
+
+        // Mirrored in the background
+        while shadow.events.lock().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        shadow
+            .events
+            .lock()
+            .sort_unstable_by_key(|(shard_id, _)| *shard_id);
+        assert_eq!(*shadow.events.lock(), [(0, Route::Kafka), (1, Route::Http)]);
+
+        forwarder.flush().await.unwrap();
+        for recorder in [kafka, http, shadow] {
+            assert_eq!(recorder.flushes.load(Ordering::Relaxed), 1);
+        }
+
+        let forwarder = CompositeEventForwarder::new();
+        assert!(forwarder
+            .forward_event(&config, event(0), None, Route::Http)
+            .await
+            .is_err());
+    }
+
+    // Never finishes forwarding an event
+    struct Hung;
+
+    #[async_trait]
+    impl EventForwarder for Hung {
+        async fn forward_event(
+            &self,
+            _config: &Config,
+            _event: event_forwarding::Event,
+            _guild_id: Option<Snowflake>,
+            _route: Route,
+        ) -> Result<()> {
+            futures::future::pending().await
+        }
+
+        async fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hung_shadow_does_not_delay_forwarding() {
+        let kafka = Recorder::default();
+
+        let config = config(&[]);
+        let forwarder = CompositeEventForwarder::new()
+            .with_route(Route::Kafka, kafka.clone())
+            .with_shadow(&config, Hung, 100);
+
+        for shard_id in 0..3 {
+            forwarder
+                .forward_event(&config, event(shard_id), None, Route::Kafka)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(kafka.events.lock().len(), 3);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::gateway::payloads::event::Event;
+use model::Snowflake;
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// What happens to an event that the forwarding rules would forward. Ordered from weakest to
+/// strongest, so that the strongest action of all filters is taken.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    #[default]
+    Forward,
+    /// Forwarded with `served_by_whitelabel` set, so that consumers can skip the event
+    Tag,
+    Drop,
+}
+
+impl FilterAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FilterAction::Forward => "forward",
+            FilterAction::Tag => "tag",
+            FilterAction::Drop => "drop",
+        }
+    }
+}
+
+/// Decides whether an event is forwarded, after the forwarding rules have chosen its route.
+/// Filters are run on the shard's receive loop, so must not block.
+pub trait EventFilter: Send + Sync + 'static {
+    fn filter(&self, event: &Event, guild_id: Option<Snowflake>) -> FilterAction;
+}
+
+/// The filters shared by every shard
+#[derive(Default)]
+pub struct EventFilters {
+    filters: Vec<Arc<dyn EventFilter>>,
+}
+
+impl EventFilters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, filter: Arc<dyn EventFilter>) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    pub fn apply(&self, event: &Event, guild_id: Option<Snowflake>) -> FilterAction {
+        let mut action = FilterAction::Forward;
+
+        for filter in &self.filters {
+            action = action.max(filter.filter(event, guild_id));
+            if action == FilterAction::Drop {
+                break;
+            }
+        }
+
+        action
+    }
+}
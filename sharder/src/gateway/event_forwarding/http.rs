@@ -1,4 +1,4 @@
-use crate::event_forwarding::EventForwarder;
+use crate::event_forwarding::{EventForwarder, Route};
 use crate::gateway::worker_response::WorkerResponse;
 use crate::{Config, GatewayError, Result};
 use async_trait::async_trait;
@@ -8,11 +8,21 @@ use std::time::Duration;
 
 pub struct HttpEventForwarder {
     client: reqwest::Client,
+    // Overrides WORKER_SVC_URI
+    uri: Option<String>,
 }
 
 impl HttpEventForwarder {
     pub fn new(client: reqwest::Client) -> HttpEventForwarder {
-        HttpEventForwarder { client }
+        HttpEventForwarder { client, uri: None }
+    }
+
+    /// Sends events to the given URI rather than WORKER_SVC_URI
+    pub fn with_uri(client: reqwest::Client, uri: String) -> HttpEventForwarder {
+        HttpEventForwarder {
+            client,
+            uri: Some(uri),
+        }
     }
 
     pub fn build_http_client() -> reqwest::Client {
@@ -37,8 +47,12 @@ impl EventForwarder for HttpEventForwarder {
         config: &Config,
         event: event_forwarding::Event,
         _guild_id: Option<Snowflake>,
+        _route: Route,
     ) -> Result<()> {
-        let uri = config.get_worker_svc_uri().expect("worker_svc_uri not set");
+        let uri = match &self.uri {
+            Some(uri) => uri.clone(),
+            None => config.get_worker_svc_uri().expect("worker_svc_uri not set"),
+        };
 
         // reqwest::Client uses Arcs internally, meaning this method clones the same client but
         // allows us to make use of connection pooling
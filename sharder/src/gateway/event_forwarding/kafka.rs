@@ -2,12 +2,13 @@ use std::time::Duration;
 
 use async_trait::async_trait;
 use common::event_forwarding;
-use event_stream::Publisher;
+use event_stream::{Publisher, UndeliveredEvent};
 use model::Snowflake;
+use tokio::sync::mpsc;
 
 use crate::{Config, Result};
 
-use super::EventForwarder;
+use super::{EventForwarder, Route};
 
 pub struct KafkaEventForwarder {
     publisher: Publisher,
@@ -15,7 +16,13 @@ pub struct KafkaEventForwarder {
 
 impl KafkaEventForwarder {
     pub fn new(config: &Config) -> Result<Self> {
-        let publisher = Publisher::new(config.kafka_brokers.clone(), config.kafka_topic.clone())?;
+        Self::with_topic(config, config.kafka_topic.clone())
+    }
+
+    /// Publishes to a topic other than KAFKA_TOPIC, on the same brokers
+    #[allow(clippy::result_large_err)] // Only called on startup
+    pub fn with_topic(config: &Config, topic: String) -> Result<Self> {
+        let publisher = Publisher::new(config.kafka_brokers.clone(), topic)?;
 
         Ok(Self { publisher })
     }
@@ -29,6 +36,7 @@ impl EventForwarder for KafkaEventForwarder {
         _config: &Config,
         event: event_forwarding::Event,
         guild_id: Option<Snowflake>,
+        _route: Route,
     ) -> Result<()> {
         self.publisher
             .send(&event, guild_id.map(|s| s.0).unwrap_or(0))?;
@@ -39,4 +47,8 @@ impl EventForwarder for KafkaEventForwarder {
         self.publisher.flush(Duration::from_secs(5));
         Ok(())
     }
+
+    fn undelivered_events(&self) -> Option<mpsc::UnboundedReceiver<UndeliveredEvent>> {
+        Some(self.publisher.undelivered())
+    }
 }
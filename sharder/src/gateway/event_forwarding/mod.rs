@@ -6,20 +6,68 @@ pub use http::HttpEventForwarder;
 mod kafka;
 pub use kafka::KafkaEventForwarder;
 
+mod redis_stream;
+pub use redis_stream::RedisStreamEventForwarder;
+
+mod rules;
+pub use rules::{parse_guild_routes, ForwardingRules, Route};
+
+mod composite;
+pub use composite::CompositeEventForwarder;
+
+mod spill_queue;
+pub use spill_queue::{SpillQueue, SpilledEvent};
+
+mod spilling;
+pub use spilling::SpillingEventForwarder;
+
+mod filter;
+pub use filter::{EventFilter, EventFilters, FilterAction};
+
+mod channel_interest_filter;
+pub use channel_interest_filter::ChannelInterestFilter;
+
+#[cfg(not(feature = "whitelabel"))]
+mod whitelabel_guild_filter;
+#[cfg(not(feature = "whitelabel"))]
+pub use whitelabel_guild_filter::WhitelabelGuildFilter;
+
 mod util;
 use model::Snowflake;
-pub use util::{get_guild_id, is_whitelisted};
+pub use util::{get_guild_id, intent_warnings};
+
+use event_stream::UndeliveredEvent;
+use serde::Deserialize;
+use tokio::sync::mpsc;
 
 use crate::{Config, Result};
 
+/// Where events routed to Kafka are written, chosen with EVENT_STREAM
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EventStreamKind {
+    #[default]
+    Kafka,
+    Redis,
+}
+
 #[async_trait]
 pub trait EventForwarder: Sync + Send + 'static {
+    /// Dropped events are never forwarded. Forwarders with a single destination ignore the route.
     async fn forward_event(
         &self,
         config: &Config,
         event: event_forwarding::Event,
         guild_id: Option<Snowflake>,
+        route: Route,
     ) -> Result<()>;
 
     async fn flush(&self) -> Result<()>;
+
+    /// Returns a receiver of the events that were forwarded, but that then failed to be delivered,
+    /// for forwarders that deliver events in the background. Only the latest receiver is sent the
+    /// events.
+    fn undelivered_events(&self) -> Option<mpsc::UnboundedReceiver<UndeliveredEvent>> {
+        None
+    }
 }
@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use common::event_forwarding::{self, EVENT_KEY};
+use deadpool_redis::Pool;
+use event_stream::RedisStreamPublisher;
+use model::Snowflake;
+
+use crate::{Config, Result};
+
+use super::{EventForwarder, Route};
+
+/// Writes events to Redis streams, for deployments without Kafka. Events are read back by
+/// `event_stream::RedisStreamConsumer`.
+pub struct RedisStreamEventForwarder {
+    publisher: RedisStreamPublisher,
+}
+
+impl RedisStreamEventForwarder {
+    pub fn new(config: &Config, redis: Arc<Pool>) -> Self {
+        let publisher = RedisStreamPublisher::new(
+            Pool::clone(&redis),
+            EVENT_KEY.to_owned(),
+            config.redis_stream_partitions,
+            config.redis_stream_max_len,
+        );
+
+        Self { publisher }
+    }
+}
+
+#[async_trait]
+impl EventForwarder for RedisStreamEventForwarder {
+    #[tracing::instrument(skip(self, _config, event))]
+    async fn forward_event(
+        &self,
+        _config: &Config,
+        event: event_forwarding::Event,
+        guild_id: Option<Snowflake>,
+        _route: Route,
+    ) -> Result<()> {
+        self.publisher
+            .send(&event, guild_id.map(|s| s.0).unwrap_or(0))
+            .await?;
+        Ok(())
+    }
+
+    // Events are written as they are forwarded
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use crate::gateway::payloads::event::Event;
+use crate::{GatewayError, Result};
+use model::Snowflake;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Where an event is sent once it has been received
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Route {
+    /// The event stream chosen by EVENT_STREAM, which is a Redis stream rather than Kafka if it
+    /// is set to `redis`
+    Kafka,
+    Http,
+    Drop,
+}
+
+impl Route {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Route::Kafka => "kafka",
+            Route::Http => "http",
+            Route::Drop => "drop",
+        }
+    }
+}
+
+impl std::str::FromStr for Route {
+    type Err = GatewayError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "kafka" => Ok(Route::Kafka),
+            "http" => Ok(Route::Http),
+            "drop" => Ok(Route::Drop),
+            other => GatewayError::custom(format!("unknown route {other}")).into(),
+        }
+    }
+}
+
+// Events forwarded when no rules are configured
+const DEFAULT_RULES: &[(&str, Route)] = &[
+    // Cache events
+    ("CHANNEL_CREATE", Route::Kafka),
+    ("CHANNEL_UPDATE", Route::Kafka),
+    ("CHANNEL_DELETE", Route::Kafka),
+    ("THREAD_CREATE", Route::Kafka),
+    ("THREAD_UPDATE", Route::Kafka),
+    ("THREAD_DELETE", Route::Kafka),
+    ("GUILD_CREATE", Route::Kafka),
+    ("GUILD_UPDATE", Route::Kafka),
+    ("GUILD_DELETE", Route::Kafka),
+    ("GUILD_BAN_ADD", Route::Kafka),
+    ("GUILD_MEMBER_REMOVE", Route::Kafka),
+    ("GUILD_MEMBER_UPDATE", Route::Kafka),
+    ("GUILD_MEMBERS_CHUNK", Route::Kafka), // Only received in response to InternalCommand::RequestGuildMembers
+    ("GUILD_ROLE_CREATE", Route::Kafka),
+    ("GUILD_ROLE_UPDATE", Route::Kafka),
+    ("GUILD_ROLE_DELETE", Route::Kafka),
+    ("USER_UPDATE", Route::Kafka),
+    ("GUILD_EMOJIS_UPDATE", Route::Kafka),
+    // Worker events
+    ("MESSAGE_CREATE", Route::Kafka),
+    ("THREAD_MEMBERS_UPDATE", Route::Kafka),
+];
+
+/// Decides which events are forwarded, and where to. Events without a rule take the route of the
+/// `*` rule, and are dropped if there is none.
+#[derive(Clone, Debug)]
+pub struct ForwardingRules {
+    routes: HashMap<String, Route>,
+    fallback: Route,
+}
+
+impl ForwardingRules {
+    /// Parses a comma separated list of `EVENT=route` rules, which are applied on top of the
+    /// default rules. Events may be given either as sent by Discord (`GUILD_MEMBER_ADD`) or as
+    /// the name of the `Event` variant (`GuildMemberAdd`). `*` sets the route for every event
+    /// without a rule.
+    #[allow(clippy::result_large_err)] // Only used while parsing config
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut rules = Self::default();
+
+        for rule in s.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let (event, route) = rule
+                .split_once('=')
+                .ok_or_else(|| GatewayError::custom(format!("rule {rule} is not EVENT=route")))?;
+
+            let route = route.parse()?;
+            match event.trim() {
+                "*" => rules.fallback = route,
+                event => {
+                    rules.routes.insert(normalise_event_name(event)?, route);
+                }
+            }
+        }
+
+        Ok(rules)
+    }
+
+    pub fn route(&self, event: &Event) -> Route {
+        self.route_by_name(event.name())
+    }
+
+    pub fn route_by_name(&self, name: &str) -> Route {
+        self.routes.get(name).copied().unwrap_or(self.fallback)
+    }
+
+    /// Returns true if any event may be sent to the given route
+    pub fn uses(&self, route: Route) -> bool {
+        self.fallback == route || self.routes.values().any(|&r| r == route)
+    }
+}
+
+impl Default for ForwardingRules {
+    fn default() -> Self {
+        Self {
+            routes: DEFAULT_RULES
+                .iter()
+                .map(|&(event, route)| (event.to_owned(), route))
+                .collect(),
+            fallback: Route::Drop,
+        }
+    }
+}
+
+/// Parses a comma separated list of `GUILD_ID=route` rules, which override the route of every
+/// event from the guild
+#[allow(clippy::result_large_err)] // Only used while parsing config
+pub fn parse_guild_routes(s: &str) -> Result<HashMap<Snowflake, Route>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(|rule| {
+            let (guild_id, route) = rule.split_once('=').ok_or_else(|| {
+                GatewayError::custom(format!("rule {rule} is not GUILD_ID=route"))
+            })?;
+
+            let guild_id = guild_id
+                .trim()
+                .parse()
+                .map_err(|_| GatewayError::custom(format!("invalid guild ID {guild_id}")))?;
+
+            Ok((Snowflake(guild_id), route.parse()?))
+        })
+        .collect()
+}
+
+// Converts GuildMemberAdd to GUILD_MEMBER_ADD, leaving GUILD_MEMBER_ADD as it is. Names that
+// don't match any event are rejected, as a rule for them would never be used.
+#[allow(clippy::result_large_err)] // Only used while parsing config
+fn normalise_event_name(name: &str) -> Result<String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic() || c == '_') {
+        return GatewayError::custom(format!("invalid event name {name}")).into();
+    }
+
+    let normalised = if name.chars().any(|c| c.is_ascii_lowercase()) {
+        let mut normalised = String::with_capacity(name.len() + 8);
+        for (i, c) in name.chars().enumerate() {
+            if i > 0 && c.is_ascii_uppercase() {
+                normalised.push('_');
+            }
+            normalised.push(c.to_ascii_uppercase());
+        }
+        normalised
+    } else {
+        name.to_owned()
+    };
+
+    if !Event::NAMES.contains(&normalised.as_str()) {
+        return GatewayError::custom(format!("unknown event {name}")).into();
+    }
+
+    Ok(normalised)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let rules =
+            ForwardingRules::parse("GuildMemberAdd=kafka, MESSAGE_CREATE=http,GUILD_BAN_ADD=drop")
+                .unwrap();
+
+        assert_eq!(rules.route_by_name("GUILD_MEMBER_ADD"), Route::Kafka);
+        assert_eq!(rules.route_by_name("MESSAGE_CREATE"), Route::Http);
+        assert_eq!(rules.route_by_name("GUILD_BAN_ADD"), Route::Drop);
+        assert_eq!(rules.route_by_name("GUILD_CREATE"), Route::Kafka);
+        assert_eq!(rules.route_by_name("TYPING_START"), Route::Drop);
+
+        let rules = ForwardingRules::parse("*=http").unwrap();
+        assert_eq!(rules.route_by_name("TYPING_START"), Route::Http);
+
+        assert!(ForwardingRules::parse("GUILD_CREATE").is_err());
+        assert!(ForwardingRules::parse("GUILD_CREATE=redis").is_err());
+        assert!(ForwardingRules::parse("GUILD_MEMBER_ADDD=kafka").is_err());
+        assert!(ForwardingRules::parse("GuildMemberAd=kafka").is_err());
+    }
+
+    #[test]
+    fn test_default_rules_are_known_events() {
+        for (event, _) in DEFAULT_RULES {
+            assert!(Event::NAMES.contains(event), "{}", event);
+        }
+    }
+
+    #[test]
+    fn test_parse_guild_routes() {
+        let routes = parse_guild_routes("508391840525975553=http, 1=drop").unwrap();
+        assert_eq!(routes[&Snowflake(508391840525975553)], Route::Http);
+        assert_eq!(routes[&Snowflake(1)], Route::Drop);
+
+        assert!(parse_guild_routes("").unwrap().is_empty());
+        assert!(parse_guild_routes("guild=http").is_err());
+    }
+}
@@ -0,0 +1,574 @@
+use std::collections::VecDeque;
+#[cfg(unix)]
+use std::fs::Permissions;
+use std::io::SeekFrom;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use common::event_forwarding;
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use tokio::sync::{Mutex, Notify};
+use tracing::{error, info};
+
+use crate::{GatewayError, Result};
+
+use super::Route;
+
+#[cfg(feature = "metrics")]
+use lazy_static::lazy_static;
+
+#[cfg(feature = "metrics")]
+use prometheus::{register_int_counter, register_int_gauge, IntCounter, IntGauge};
+
+#[cfg(feature = "metrics")]
+lazy_static! {
+    static ref DEPTH: IntGauge = register_int_gauge!(
+        "event_spill_queue_depth",
+        "The number of events waiting in the spill queue to be replayed"
+    )
+    .expect("Failed to create spill queue depth gauge");
+    static ref BYTES: IntGauge = register_int_gauge!(
+        "event_spill_queue_bytes",
+        "The size on disk of the events waiting in the spill queue"
+    )
+    .expect("Failed to create spill queue bytes gauge");
+    static ref OLDEST_AGE: IntGauge = register_int_gauge!(
+        "event_spill_queue_oldest_age_seconds",
+        "Time since the oldest event in the spill queue failed to be forwarded"
+    )
+    .expect("Failed to create spill queue age gauge");
+    static ref SPILLED: IntCounter = register_int_counter!(
+        "events_spilled_total",
+        "The number of events written to the spill queue"
+    )
+    .expect("Failed to create spilled events counter");
+    static ref SPILL_DROPPED: IntCounter = register_int_counter!(
+        "events_spill_dropped_total",
+        "The number of events that could not be written to the spill queue"
+    )
+    .expect("Failed to create dropped spilled events counter");
+}
+
+// A new segment is started once the current one reaches this size
+const SEGMENT_BYTES: u64 = 16 * 1024 * 1024;
+const SEGMENT_EXTENSION: &str = "seg";
+const CURSOR_FILE: &str = "cursor";
+
+#[cfg(unix)]
+const DIR_MODE: u32 = 0o700;
+#[cfg(unix)]
+const SEGMENT_MODE: u32 = 0o600;
+
+/// An event that could not be forwarded, along with what is needed to forward it again
+#[derive(Serialize, Deserialize)]
+pub struct SpilledEvent {
+    /// Unix time in milliseconds
+    pub spilled_at: u64,
+    pub guild_id: Option<u64>,
+    pub route: Route,
+    pub event: event_forwarding::Event,
+}
+
+/// A bounded FIFO queue of events, stored on disk so that it survives restarts.
+///
+/// Events are appended as JSON lines to numbered segment files. The position of the oldest event
+/// is kept in a cursor file, and segments are deleted once every event in them has been read.
+/// Events are consumed with `next` followed by `ack`, which must only be called from one task.
+pub struct SpillQueue {
+    dir: PathBuf,
+    max_bytes: u64,
+    state: Mutex<State>,
+    depth: AtomicUsize,
+    pushed: Notify,
+}
+
+struct State {
+    // IDs of the segments in order. Events are read from the first and appended to the last.
+    segments: VecDeque<u64>,
+    next_segment: u64,
+    writer: Option<File>,
+    write_len: u64,
+    reader: Option<BufReader<File>>,
+    read_offset: u64,
+    // Length of the event returned by the last call to next, if it has not been acked
+    unacked_len: Option<u64>,
+    // Bytes of events that have not been acked
+    bytes: u64,
+}
+
+impl SpillQueue {
+    pub async fn open(dir: impl AsRef<Path>, max_bytes: u64) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+
+        // Only the sharder can read the events, as they include message content
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        builder.mode(DIR_MODE);
+        builder.create(&dir).await?;
+
+        #[cfg(unix)]
+        fs::set_permissions(&dir, Permissions::from_mode(DIR_MODE)).await?;
+
+        let mut segments = Vec::new();
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+
+            if let Some(id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str()?.parse().ok())
+            {
+                segments.push(id);
+            }
+        }
+        segments.sort_unstable();
+
+        let (cursor_segment, mut read_offset) = read_cursor(&dir).await?;
+
+        // Segments before the cursor have already been replayed
+        let mut segments: VecDeque<u64> = segments.into();
+        while segments.front().is_some_and(|id| *id < cursor_segment) {
+            let id = segments.pop_front().unwrap();
+            fs::remove_file(segment_path(&dir, id)).await?;
+        }
+
+        if segments.front() != Some(&cursor_segment) {
+            read_offset = 0;
+        }
+
+        // Segment IDs are never reused, as the cursor could otherwise point past the start of one
+        let next_segment = segments
+            .back()
+            .map(|id| id + 1)
+            .unwrap_or(cursor_segment)
+            .max(cursor_segment);
+
+        let mut bytes = 0;
+        let mut depth = 0;
+        let mut write_len = 0;
+        for (i, id) in segments.iter().enumerate() {
+            let data = fs::read(segment_path(&dir, *id)).await?;
+            let skip = if i == 0 { read_offset as usize } else { 0 };
+            let unread = data.get(skip..).unwrap_or_default();
+
+            bytes += unread.len() as u64;
+            depth += unread.iter().filter(|b| **b == b'\n').count();
+            write_len = data.len() as u64;
+        }
+
+        if depth > 0 {
+            info!(depth, bytes, "Opened spill queue with events to replay");
+        }
+
+        let cursor_segment = segments.front().copied().unwrap_or(next_segment);
+
+        let queue = Self {
+            dir,
+            max_bytes,
+            state: Mutex::new(State {
+                segments,
+                next_segment,
+                writer: None,
+                write_len,
+                reader: None,
+                read_offset,
+                unacked_len: None,
+                bytes,
+            }),
+            depth: AtomicUsize::new(depth),
+            pushed: Notify::new(),
+        };
+
+        queue.write_cursor(cursor_segment, read_offset).await?;
+        queue.update_metrics(bytes);
+        Ok(queue)
+    }
+
+    pub fn len(&self) -> usize {
+        self.depth.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends an event, failing if the queue is full
+    pub async fn push(
+        &self,
+        event: event_forwarding::Event,
+        guild_id: Option<u64>,
+        route: Route,
+    ) -> Result<()> {
+        let mut state = self.state.lock().await;
+        self.append(&mut state, event, guild_id, route).await?;
+
+        drop(state);
+        self.pushed.notify_one();
+
+        Ok(())
+    }
+
+    /// Appends the event if the queue is not empty, so that it is forwarded after the events
+    /// already in the queue, and otherwise returns it. As the queue is checked while holding the
+    /// same lock as `ack`, the last event can't be replayed between the check and the push.
+    pub async fn push_if_not_empty(
+        &self,
+        event: event_forwarding::Event,
+        guild_id: Option<u64>,
+        route: Route,
+    ) -> Result<Option<event_forwarding::Event>> {
+        let mut state = self.state.lock().await;
+        if self.is_empty() {
+            return Ok(Some(event));
+        }
+
+        self.append(&mut state, event, guild_id, route).await?;
+
+        drop(state);
+        self.pushed.notify_one();
+
+        Ok(None)
+    }
+
+    async fn append(
+        &self,
+        state: &mut State,
+        mut event: event_forwarding::Event,
+        guild_id: Option<u64>,
+        route: Route,
+    ) -> Result<()> {
+        // Tokens aren't written to disk. Consumers resolve them from `bot_id` and `token_key`.
+        event.bot_token = None;
+
+        let spilled = SpilledEvent {
+            spilled_at: now_millis(),
+            guild_id,
+            route,
+            event,
+        };
+
+        let mut line = serde_json::to_vec(&spilled)?;
+        line.push(b'\n');
+        let len = line.len() as u64;
+
+        if state.bytes + len > self.max_bytes {
+            #[cfg(feature = "metrics")]
+            SPILL_DROPPED.inc();
+
+            return GatewayError::custom(format!("spill queue is full ({} bytes)", self.max_bytes))
+                .into();
+        }
+
+        if state.segments.is_empty() || state.write_len + len > SEGMENT_BYTES {
+            let id = state.next_segment;
+            state.next_segment += 1;
+            state.segments.push_back(id);
+            state.writer = None;
+            state.write_len = 0;
+        }
+
+        if state.writer.is_none() {
+            let id = *state.segments.back().unwrap();
+            let mut options = OpenOptions::new();
+            options.create(true).append(true);
+            #[cfg(unix)]
+            options.mode(SEGMENT_MODE);
+
+            let file = options.open(segment_path(&self.dir, id)).await?;
+
+            state.writer = Some(file);
+        }
+
+        let writer = state.writer.as_mut().unwrap();
+        writer.write_all(&line).await?;
+        writer.flush().await?;
+
+        state.write_len += len;
+        state.bytes += len;
+        self.depth.fetch_add(1, Ordering::AcqRel);
+        self.update_metrics(state.bytes);
+
+        #[cfg(feature = "metrics")]
+        SPILLED.inc();
+
+        Ok(())
+    }
+
+    /// Returns the oldest event without removing it, which is done with `ack` once it has been
+    /// forwarded. Calling `next` again before `ack` returns the same event.
+    pub async fn next(&self) -> Result<Option<SpilledEvent>> {
+        let mut state = self.state.lock().await;
+
+        loop {
+            let Some(id) = state.segments.front().copied() else {
+                return Ok(None);
+            };
+
+            if state.reader.is_none() {
+                let file = File::open(segment_path(&self.dir, id)).await?;
+                state.reader = Some(BufReader::new(file));
+            }
+
+            let offset = state.read_offset;
+            let reader = state.reader.as_mut().unwrap();
+            reader.seek(SeekFrom::Start(offset)).await?;
+
+            let mut line = Vec::new();
+            reader.read_until(b'\n', &mut line).await?;
+
+            if line.last() != Some(&b'\n') {
+                // The segment being written to has been read to the end
+                if state.segments.len() == 1 {
+                    state.unacked_len = None;
+                    return Ok(None);
+                }
+
+                // A partial line is left if the sharder was killed while writing it
+                if !line.is_empty() {
+                    error!(
+                        segment = id,
+                        "Skipping truncated event at the end of spill segment"
+                    );
+                }
+
+                state.segments.pop_front();
+                state.reader = None;
+                state.read_offset = 0;
+                self.write_cursor(state.segments[0], 0).await?;
+                fs::remove_file(segment_path(&self.dir, id)).await?;
+                continue;
+            }
+
+            let len = line.len() as u64;
+            match serde_json::from_slice::<SpilledEvent>(&line) {
+                Ok(spilled) => {
+                    state.unacked_len = Some(len);
+
+                    #[cfg(feature = "metrics")]
+                    OLDEST_AGE.set((now_millis().saturating_sub(spilled.spilled_at) / 1000) as i64);
+
+                    return Ok(Some(spilled));
+                }
+                Err(e) => {
+                    error!(error = %e, segment = id, offset, "Skipping malformed event in spill queue");
+                    state.unacked_len = Some(len);
+                    self.advance(&mut state).await?;
+                }
+            }
+        }
+    }
+
+    /// Removes the event returned by the last call to `next`
+    pub async fn ack(&self) -> Result<()> {
+        let mut state = self.state.lock().await;
+        self.advance(&mut state).await
+    }
+
+    /// Waits until an event is pushed, returning immediately if one has been pushed since the
+    /// last call
+    pub async fn wait_for_push(&self) {
+        self.pushed.notified().await
+    }
+
+    async fn advance(&self, state: &mut State) -> Result<()> {
+        let Some(len) = state.unacked_len.take() else {
+            return Ok(());
+        };
+
+        state.read_offset += len;
+        state.bytes = state.bytes.saturating_sub(len);
+        self.depth.fetch_sub(1, Ordering::AcqRel);
+        self.update_metrics(state.bytes);
+
+        self.write_cursor(state.segments[0], state.read_offset)
+            .await
+    }
+
+    // Written to a temporary file and renamed, so that the cursor is never left half-written
+    async fn write_cursor(&self, segment: u64, offset: u64) -> Result<()> {
+        let tmp = self.dir.join(format!("{CURSOR_FILE}.tmp"));
+        fs::write(&tmp, format!("{segment} {offset}")).await?;
+        fs::rename(&tmp, self.dir.join(CURSOR_FILE)).await?;
+
+        Ok(())
+    }
+
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn update_metrics(&self, bytes: u64) {
+        #[cfg(feature = "metrics")]
+        {
+            let depth = self.len();
+            DEPTH.set(depth as i64);
+            BYTES.set(bytes as i64);
+
+            if depth == 0 {
+                OLDEST_AGE.set(0);
+            }
+        }
+    }
+}
+
+fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{id:020}.{SEGMENT_EXTENSION}"))
+}
+
+async fn read_cursor(dir: &Path) -> Result<(u64, u64)> {
+    let data = match fs::read_to_string(dir.join(CURSOR_FILE)).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((0, 0)),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut parts = data.split_whitespace().map(str::parse::<u64>);
+    match (parts.next(), parts.next()) {
+        (Some(Ok(segment)), Some(Ok(offset))) => Ok((segment, offset)),
+        _ => GatewayError::custom(format!("invalid spill queue cursor: {data}")).into(),
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::value::RawValue;
+
+    fn event(n: usize) -> event_forwarding::Event {
+        event_forwarding::Event {
+            bot_token: None,
+            bot_id: 1,
+            token_key: event_forwarding::token_key("token"),
+            is_whitelabel: false,
+            served_by_whitelabel: false,
+            shard_id: 0,
+            event: RawValue::from_string(format!(r#"{{"n":{n}}}"#)).unwrap(),
+        }
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("spill-{}-{name}", std::process::id()));
+        _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    async fn pop(queue: &SpillQueue) -> Option<String> {
+        let spilled = queue.next().await.unwrap()?;
+        queue.ack().await.unwrap();
+        Some(spilled.event.event.get().to_owned())
+    }
+
+    #[tokio::test]
+    async fn test_replays_in_order_after_reopening() {
+        let dir = test_dir("reopen");
+
+        let queue = SpillQueue::open(&dir, 1024 * 1024).await.unwrap();
+        for n in 0..3 {
+            queue
+                .push(event(n), Some(n as u64), Route::Kafka)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(pop(&queue).await.as_deref(), Some(r#"{"n":0}"#));
+        drop(queue);
+
+        let queue = SpillQueue::open(&dir, 1024 * 1024).await.unwrap();
+        assert_eq!(queue.len(), 2);
+
+        // Not acked, so returned again
+        let spilled = queue.next().await.unwrap().unwrap();
+        assert_eq!(spilled.guild_id, Some(1));
+        assert_eq!(pop(&queue).await.as_deref(), Some(r#"{"n":1}"#));
+        assert_eq!(pop(&queue).await.as_deref(), Some(r#"{"n":2}"#));
+        assert!(queue.next().await.unwrap().is_none());
+        assert!(queue.is_empty());
+
+        _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_does_not_store_tokens() {
+        let dir = test_dir("tokens");
+
+        let queue = SpillQueue::open(&dir, 1024 * 1024).await.unwrap();
+        let mut ev = event(0);
+        ev.bot_token = Some("token".to_owned());
+        queue.push(ev, None, Route::Kafka).await.unwrap();
+
+        let segment = segment_path(&dir, queue.state.lock().await.segments[0]);
+        let data = std::fs::read_to_string(&segment).unwrap();
+        assert!(!data.contains(r#""token""#));
+
+        let spilled = queue.next().await.unwrap().unwrap();
+        assert!(spilled.event.bot_token.is_none());
+        assert_eq!(
+            spilled.event.token_key,
+            event_forwarding::token_key("token")
+        );
+
+        #[cfg(unix)]
+        {
+            let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&dir), DIR_MODE);
+            assert_eq!(mode(&segment), SEGMENT_MODE);
+        }
+
+        _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_events_when_full() {
+        let dir = test_dir("full");
+
+        let queue = SpillQueue::open(&dir, 200).await.unwrap();
+        queue.push(event(0), None, Route::Kafka).await.unwrap();
+        assert!(queue.push(event(1), None, Route::Kafka).await.is_err());
+
+        // Space is freed once events are replayed
+        assert!(pop(&queue).await.is_some());
+        queue.push(event(2), None, Route::Http).await.unwrap();
+        assert_eq!(pop(&queue).await.as_deref(), Some(r#"{"n":2}"#));
+
+        _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_push_if_not_empty() {
+        let dir = test_dir("not-empty");
+
+        let queue = SpillQueue::open(&dir, 1024 * 1024).await.unwrap();
+        let returned = queue
+            .push_if_not_empty(event(0), None, Route::Kafka)
+            .await
+            .unwrap();
+        assert_eq!(returned.unwrap().event.get(), r#"{"n":0}"#);
+        assert!(queue.is_empty());
+
+        queue.push(event(1), None, Route::Kafka).await.unwrap();
+        let returned = queue
+            .push_if_not_empty(event(2), None, Route::Kafka)
+            .await
+            .unwrap();
+        assert!(returned.is_none());
+
+        assert_eq!(pop(&queue).await.as_deref(), Some(r#"{"n":1}"#));
+        assert_eq!(pop(&queue).await.as_deref(), Some(r#"{"n":2}"#));
+        assert!(queue.is_empty());
+
+        _ = std::fs::remove_dir_all(&dir);
+    }
+}
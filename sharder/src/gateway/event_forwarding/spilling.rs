@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use common::event_forwarding;
+use event_stream::UndeliveredEvent;
+use model::Snowflake;
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+use crate::{Config, Result};
+
+use super::{EventForwarder, Route, SpillQueue};
+
+const MIN_REPLAY_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_REPLAY_BACKOFF: Duration = Duration::from_secs(10);
+
+// Routes that events are spilled for, each with a queue in a subdirectory of SPILL_DIR
+const SPILLED_ROUTES: [Route; 2] = [Route::Kafka, Route::Http];
+
+/// Writes events that the inner forwarder fails to forward, or that it reports as undelivered
+/// afterwards, to a spill queue, and replays them in order in the background once it recovers.
+/// While a route's queue is not empty, new events for the route are queued behind the spilled
+/// ones rather than being forwarded straight away. Each route has its own queue, so that events
+/// for other routes aren't held up. Events still in the queues on shutdown are replayed when the
+/// sharder next starts.
+///
+/// Without SPILL_DIR set, events are passed to the inner forwarder as they are.
+pub struct SpillingEventForwarder<F: EventForwarder> {
+    inner: Arc<F>,
+    queues: HashMap<Route, Arc<SpillQueue>>,
+}
+
+impl<F: EventForwarder> SpillingEventForwarder<F> {
+    pub async fn new(inner: F, config: &Config) -> Result<Self> {
+        let inner = Arc::new(inner);
+
+        let Some(dir) = &config.spill_dir else {
+            return Ok(Self {
+                inner,
+                queues: HashMap::new(),
+            });
+        };
+
+        let mut queues = HashMap::new();
+        for route in SPILLED_ROUTES {
+            let route_dir = Path::new(dir).join(route.as_str());
+            let queue = Arc::new(SpillQueue::open(&route_dir, config.spill_max_bytes).await?);
+            info!(dir = %route_dir.display(), depth = queue.len(), "Opened event spill queue");
+
+            tokio::spawn(replay(
+                Arc::clone(&inner),
+                Arc::clone(&queue),
+                config.clone(),
+            ));
+
+            queues.insert(route, queue);
+        }
+
+        if let Some(undelivered) = inner.undelivered_events() {
+            tokio::spawn(spill_undelivered(
+                undelivered,
+                Arc::clone(&queues[&Route::Kafka]),
+            ));
+        }
+
+        Ok(Self { inner, queues })
+    }
+}
+
+#[async_trait]
+impl<F: EventForwarder> EventForwarder for SpillingEventForwarder<F> {
+    async fn forward_event(
+        &self,
+        config: &Config,
+        event: event_forwarding::Event,
+        guild_id: Option<Snowflake>,
+        route: Route,
+    ) -> Result<()> {
+        let Some(queue) = self.queues.get(&route) else {
+            return self
+                .inner
+                .forward_event(config, event, guild_id, route)
+                .await;
+        };
+
+        let Some(event) = queue
+            .push_if_not_empty(event, guild_id.map(|id| id.0), route)
+            .await?
+        else {
+            return Ok(());
+        };
+
+        // Kept in case forwarding fails
+        let copy = event.clone();
+
+        let Err(e) = self
+            .inner
+            .forward_event(config, event, guild_id, route)
+            .await
+        else {
+            return Ok(());
+        };
+
+        warn!(error = %e, ?route, "Error forwarding event, writing it to the spill queue");
+
+        if let Err(spill_err) = queue.push(copy, guild_id.map(|id| id.0), route).await {
+            error!(error = %spill_err, "Error writing event to the spill queue, dropping it");
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.inner.flush().await
+    }
+}
+
+async fn spill_undelivered(
+    mut undelivered: mpsc::UnboundedReceiver<UndeliveredEvent>,
+    queue: Arc<SpillQueue>,
+) {
+    while let Some(undelivered) = undelivered.recv().await {
+        warn!(error = %undelivered.error, "Event was not delivered, writing it to the spill queue");
+
+        // Events without a guild are sent with a key of 0
+        let guild_id = Some(undelivered.guild_id).filter(|id| *id != 0);
+        if let Err(e) = queue.push(undelivered.event, guild_id, Route::Kafka).await {
+            error!(error = %e, "Error writing event to the spill queue, dropping it");
+        }
+    }
+}
+
+async fn replay<F: EventForwarder>(inner: Arc<F>, queue: Arc<SpillQueue>, config: Config) {
+    let mut backoff = MIN_REPLAY_BACKOFF;
+
+    loop {
+        let spilled = match queue.next().await {
+            Ok(Some(spilled)) => spilled,
+            Ok(None) => {
+                queue.wait_for_push().await;
+                continue;
+            }
+            Err(e) => {
+                error!(error = %e, "Error reading from the spill queue");
+                sleep(MAX_REPLAY_BACKOFF).await;
+                continue;
+            }
+        };
+
+        let guild_id = spilled.guild_id.map(Snowflake);
+        let res = inner
+            .forward_event(&config, spilled.event, guild_id, spilled.route)
+            .await;
+
+        match res {
+            Ok(()) => {
+                backoff = MIN_REPLAY_BACKOFF;
+
+                if let Err(e) = queue.ack().await {
+                    error!(error = %e, "Error removing replayed event from the spill queue");
+                }
+            }
+            Err(e) => {
+                warn!(error = %e, depth = queue.len(), ?backoff, "Error replaying spilled event");
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_REPLAY_BACKOFF);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::{self, event};
+    use crate::GatewayError;
+    use parking_lot::Mutex;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // Fails events routed to Kafka while `fail_kafka` is set
+    #[derive(Default)]
+    struct Flaky {
+        fail_kafka: Arc<AtomicBool>,
+        forwarded: Arc<Mutex<Vec<(u16, Route)>>>,
+        undelivered: Arc<Mutex<Option<mpsc::UnboundedSender<UndeliveredEvent>>>>,
+    }
+
+    #[async_trait]
+    impl EventForwarder for Flaky {
+        async fn forward_event(
+            &self,
+            _config: &Config,
+            event: event_forwarding::Event,
+            _guild_id: Option<Snowflake>,
+            route: Route,
+        ) -> Result<()> {
+            if route == Route::Kafka && self.fail_kafka.load(Ordering::Relaxed) {
+                return GatewayError::custom("kafka is down").into();
+            }
+
+            self.forwarded.lock().push((event.shard_id, route));
+            Ok(())
+        }
+
+        async fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn undelivered_events(&self) -> Option<mpsc::UnboundedReceiver<UndeliveredEvent>> {
+            let (tx, rx) = mpsc::unbounded_channel();
+            *self.undelivered.lock() = Some(tx);
+            Some(rx)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spills_per_route() {
+        let dir = std::env::temp_dir().join(format!("spilling-{}", std::process::id()));
+        _ = std::fs::remove_dir_all(&dir);
+        let config = test_support::config(&[("SPILL_DIR", dir.to_str().unwrap())]);
+
+        let flaky = Flaky::default();
+        let (fail_kafka, forwarded, undelivered) = (
+            Arc::clone(&flaky.fail_kafka),
+            Arc::clone(&flaky.forwarded),
+            Arc::clone(&flaky.undelivered),
+        );
+        fail_kafka.store(true, Ordering::Relaxed);
+
+        let forwarder = SpillingEventForwarder::new(flaky, &config).await.unwrap();
+
+        // Spilled, and the next Kafka event is queued behind it, but HTTP events are unaffected
+        for (shard_id, route) in [(0, Route::Kafka), (1, Route::Http), (2, Route::Kafka)] {
+            forwarder
+                .forward_event(&config, event(shard_id), None, route)
+                .await
+                .unwrap();
+        }
+        assert_eq!(*forwarded.lock(), [(1, Route::Http)]);
+
+        // Accepted by Kafka, but not delivered
+        let error = serde_json::from_str::<()>("").unwrap_err().into();
+        undelivered
+            .lock()
+            .as_ref()
+            .unwrap()
+            .send(UndeliveredEvent {
+                event: event(3),
+                guild_id: 0,
+                error,
+            })
+            .unwrap();
+
+        while forwarder.queues[&Route::Kafka].len() < 3 {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        fail_kafka.store(false, Ordering::Relaxed);
+        while !forwarder.queues[&Route::Kafka].is_empty() {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(
+            *forwarded.lock(),
+            [
+                (1, Route::Http),
+                (0, Route::Kafka),
+                (2, Route::Kafka),
+                (3, Route::Kafka)
+            ]
+        );
+
+        _ = std::fs::remove_dir_all(&dir);
+    }
+}
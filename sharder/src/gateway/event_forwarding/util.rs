@@ -1,4 +1,7 @@
 use crate::gateway::payloads::event::Event;
+use crate::gateway::Intents;
+
+use super::{ForwardingRules, Route};
 use model::Snowflake;
 
 pub fn get_guild_id(event: &Event) -> Option<Snowflake> {
@@ -45,32 +48,158 @@ pub fn get_guild_id(event: &Event) -> Option<Snowflake> {
     }
 }
 
-// TODO: Don't hardcode, use feature flags or something
-pub fn is_whitelisted(event: &Event) -> bool {
-    matches!(
-        event,
-        // Cache events
-        Event::ChannelCreate(_)
-            | Event::ChannelUpdate(_)
-            | Event::ChannelDelete(_)
-            | Event::ThreadCreate(_)
-            | Event::ThreadUpdate(_)
-            | Event::ThreadDelete(_)
-            | Event::GuildCreate(_)
-            | Event::GuildUpdate(_)
-            | Event::GuildDelete(_)
-            | Event::GuildBanAdd(_)
-            | Event::GuildMemberRemove(_)
-            | Event::GuildMemberUpdate(_)
-            | Event::GuildMembersChunk(_) // We never receive these
-            | Event::GuildRoleCreate(_)
-            | Event::GuildRoleUpdate(_)
-            | Event::GuildRoleDelete(_)
-            | Event::UserUpdate(_)
-            | Event::GuildEmojisUpdate(_)
+// The intents that events are received under, any of which is enough. Events that are received
+// regardless of intents are omitted.
+const EVENT_INTENTS: &[(&str, &[Intents])] = &[
+    ("CHANNEL_CREATE", &[Intents::Guilds]),
+    ("CHANNEL_UPDATE", &[Intents::Guilds]),
+    ("CHANNEL_DELETE", &[Intents::Guilds]),
+    (
+        "CHANNEL_PINS_UPDATE",
+        &[Intents::Guilds, Intents::DirectMessages],
+    ),
+    ("THREAD_CREATE", &[Intents::Guilds]),
+    ("THREAD_UPDATE", &[Intents::Guilds]),
+    ("THREAD_DELETE", &[Intents::Guilds]),
+    ("THREAD_LIST_SYNC", &[Intents::Guilds]),
+    ("THREAD_MEMBER_UPDATE", &[Intents::Guilds]),
+    ("THREAD_MEMBERS_UPDATE", &[Intents::Guilds]),
+    ("GUILD_CREATE", &[Intents::Guilds]),
+    ("GUILD_UPDATE", &[Intents::Guilds]),
+    ("GUILD_DELETE", &[Intents::Guilds]),
+    ("GUILD_ROLE_CREATE", &[Intents::Guilds]),
+    ("GUILD_ROLE_UPDATE", &[Intents::Guilds]),
+    ("GUILD_ROLE_DELETE", &[Intents::Guilds]),
+    ("STAGE_INSTANCE_CREATE", &[Intents::Guilds]),
+    ("STAGE_INSTANCE_UPDATE", &[Intents::Guilds]),
+    ("STAGE_INSTANCE_DELETE", &[Intents::Guilds]),
+    ("GUILD_MEMBER_ADD", &[Intents::GuildMembers]),
+    ("GUILD_MEMBER_UPDATE", &[Intents::GuildMembers]),
+    ("GUILD_MEMBER_REMOVE", &[Intents::GuildMembers]),
+    ("GUILD_BAN_ADD", &[Intents::GuildModeration]),
+    ("GUILD_BAN_REMOVE", &[Intents::GuildModeration]),
+    ("GUILD_EMOJIS_UPDATE", &[Intents::GuildEmojis]),
+    ("GUILD_INTEGRATIONS_UPDATE", &[Intents::GuildIntegrations]),
+    ("WEBHOOK_UPDATE", &[Intents::GuildWebhooks]),
+    ("INVITE_CREATE", &[Intents::GuildInvites]),
+    ("INVITE_DELETE", &[Intents::GuildInvites]),
+    ("VOICE_STATE_UPDATE", &[Intents::GuildVoiceStates]),
+    ("PRESENCE_UPDATE", &[Intents::GuildPresences]),
+    (
+        "MESSAGE_CREATE",
+        &[Intents::GuildMessages, Intents::DirectMessages],
+    ),
+    (
+        "MESSAGE_UPDATE",
+        &[Intents::GuildMessages, Intents::DirectMessages],
+    ),
+    (
+        "MESSAGE_DELETE",
+        &[Intents::GuildMessages, Intents::DirectMessages],
+    ),
+    ("MESSAGE_DELETE_BULK", &[Intents::GuildMessages]),
+    (
+        "MESSAGE_REACTION_ADD",
+        &[
+            Intents::GuildMessageReactions,
+            Intents::DirectMessageReaction,
+        ],
+    ),
+    (
+        "MESSAGE_REACTION_REMOVE",
+        &[
+            Intents::GuildMessageReactions,
+            Intents::DirectMessageReaction,
+        ],
+    ),
+    (
+        "MESSAGE_REACTION_REMOVE_ALL",
+        &[
+            Intents::GuildMessageReactions,
+            Intents::DirectMessageReaction,
+        ],
+    ),
+    (
+        "MESSAGE_REACTION_REMOVE_EMOJI",
+        &[
+            Intents::GuildMessageReactions,
+            Intents::DirectMessageReaction,
+        ],
+    ),
+    (
+        "TYPING_START",
+        &[Intents::GuildMessageTyping, Intents::DirectMessageTyping],
+    ),
+];
+
+/// Describes any mismatch between the configured intents and the forwarding rules: forwarded
+/// events that will never be received, and intents for events that are never forwarded
+pub fn intent_warnings(intents: u64, rules: &ForwardingRules) -> Vec<String> {
+    let forwarded = || {
+        EVENT_INTENTS
+            .iter()
+            .filter(|(event, _)| rules.route_by_name(event) != Route::Drop)
+    };
+
+    let mut warnings = Vec::new();
+
+    for (event, required) in forwarded() {
+        if !required.iter().any(|intent| intent.is_set(intents)) {
+            warnings.push(format!(
+                "{event} events are forwarded, but will not be received without one of the intents {required:?}"
+            ));
+        }
+    }
+
+    for intent in Intents::ALL.iter().filter(|intent| intent.is_set(intents)) {
+        let used = match intent {
+            // Only affects the content of messages
+            Intents::MessageContent => {
+                Intents::GuildMessages.is_set(intents) || Intents::DirectMessages.is_set(intents)
+            }
+            _ => forwarded().any(|(_, required)| required.contains(intent)),
+        };
+
+        if !used {
+            warnings.push(format!(
+                "Intent {intent:?} is enabled, but none of its events are forwarded"
+            ));
+        }
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
 
-            // Worker events
-            | Event::MessageCreate(_)
-            | Event::ThreadMembersUpdate(_)
-    )
+    #[test]
+    fn test_intent_warnings() {
+        let intents = Intents::build(vec![
+            Intents::Guilds,
+            Intents::GuildMembers,
+            Intents::GuildModeration,
+            Intents::GuildEmojis,
+            Intents::GuildMessages,
+            Intents::MessageContent,
+        ]);
+        assert!(intent_warnings(intents, &ForwardingRules::default()).is_empty());
+
+        // Missing GUILD_BAN_ADD and GUILD_EMOJIS_UPDATE, and nothing needs presences
+        let intents = Intents::build(vec![
+            Intents::Guilds,
+            Intents::GuildMembers,
+            Intents::GuildMessages,
+            Intents::GuildPresences,
+        ]);
+        assert_eq!(
+            intent_warnings(intents, &ForwardingRules::default()).len(),
+            3
+        );
+
+        // Presences are used once PRESENCE_UPDATE is forwarded
+        let rules = ForwardingRules::parse("PresenceUpdate=kafka").unwrap();
+        assert_eq!(intent_warnings(intents, &rules).len(), 2);
+    }
 }
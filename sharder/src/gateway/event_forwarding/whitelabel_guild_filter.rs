@@ -0,0 +1,314 @@
+use super::{EventFilter, FilterAction};
+use crate::gateway::payloads::event::Event;
+use crate::Result;
+use common::whitelabel_guilds;
+use common::whitelabel_health::{self, BotState};
+use database::Database;
+use deadpool_redis::redis;
+use futures::StreamExt;
+use model::Snowflake;
+use parking_lot::RwLock;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+use tracing::{debug, error, info};
+
+// Events that the worker also receives from the whitelabel bot of the guild
+const WORKER_EVENTS: &[&str] = &["MESSAGE_CREATE", "THREAD_MEMBERS_UPDATE"];
+
+// Catches up on any updates missed while disconnected from Redis
+const RESYNC_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Filters the worker events of guilds that a whitelabel bot is in, so that the worker doesn't
+/// receive them from both the public bot and the whitelabel bot. The guilds are loaded from
+/// `whitelabel_guilds`, and kept up to date by the whitelabel sharder through
+/// `whitelabel_guilds::KEY`. Until the guilds are loaded, every event is forwarded.
+///
+/// Events are only dropped while one of the guild's whitelabel bots is connected with the Message
+/// Content intent, according to `whitelabel_health`, which is refreshed within
+/// `whitelabel_health::CONNECTED_TTL`. Otherwise the whitelabel bot may not be receiving them, so
+/// they are tagged instead.
+pub struct WhitelabelGuildFilter {
+    database: Arc<Database>,
+    action: FilterAction,
+    state: RwLock<State>,
+}
+
+#[derive(Default)]
+struct State {
+    // The whitelabel bots in each guild
+    guilds: HashMap<Snowflake, HashSet<Snowflake>>,
+    // The whitelabel bots that are connected with the Message Content intent, and when their
+    // health was last refreshed
+    connected: HashMap<Snowflake, Instant>,
+}
+
+impl WhitelabelGuildFilter {
+    pub fn new(database: Arc<Database>, action: FilterAction) -> Self {
+        Self {
+            database,
+            action,
+            state: RwLock::new(State::default()),
+        }
+    }
+
+    /// Loads the guilds, and keeps them up to date in the background
+    pub async fn start(self: Arc<Self>, redis_uri: &str) -> Result<()> {
+        // Subscribe before loading, so that no updates are missed
+        let mut conn = redis::Client::open(redis_uri)?
+            .get_async_connection()
+            .await?
+            .into_pubsub();
+
+        conn.subscribe(&[whitelabel_guilds::KEY, whitelabel_health::KEY])
+            .await?;
+
+        if let Err(e) = self.resync().await {
+            error!(error = %e, "Failed to load whitelabel guilds, forwarding their events");
+        }
+
+        tokio::spawn(Arc::clone(&self).resync_loop());
+
+        tokio::spawn(async move {
+            let mut stream = conn.on_message();
+
+            while let Some(m) = stream.next().await {
+                let payload = m.get_payload_bytes();
+
+                if m.get_channel_name() == whitelabel_health::KEY {
+                    match serde_json::from_slice::<whitelabel_health::Payload>(payload) {
+                        Ok(payload) => self.state.write().update_health(payload, Instant::now()),
+                        Err(e) => error!(error = %e, "Failed to decode whitelabel health update"),
+                    }
+                } else {
+                    match serde_json::from_slice::<whitelabel_guilds::Payload>(payload) {
+                        Ok(payload) => self.state.write().update_guild(payload),
+                        Err(e) => error!(error = %e, "Failed to decode whitelabel guild update"),
+                    }
+                }
+            }
+
+            error!("Whitelabel guild update subscription closed");
+        });
+
+        Ok(())
+    }
+
+    async fn resync(&self) -> Result<()> {
+        let rows = self.database.whitelabel_guilds.get_all().await?;
+        let connected = self
+            .database
+            .whitelabel_health
+            .get_message_content_bots(whitelabel_health::CONNECTED_TTL)
+            .await?;
+
+        let mut guilds: HashMap<Snowflake, HashSet<Snowflake>> = HashMap::new();
+        for (bot_id, guild_id) in rows {
+            guilds.entry(guild_id).or_default().insert(bot_id);
+        }
+
+        info!(
+            guild_count = guilds.len(),
+            connected_bots = connected.len(),
+            "Loaded whitelabel guilds"
+        );
+
+        let now = Instant::now();
+        *self.state.write() = State {
+            guilds,
+            connected: connected
+                .into_iter()
+                .map(|(bot_id, age)| (bot_id, now.checked_sub(age).unwrap_or(now)))
+                .collect(),
+        };
+        Ok(())
+    }
+
+    async fn resync_loop(self: Arc<Self>) {
+        loop {
+            sleep(RESYNC_INTERVAL).await;
+
+            if let Err(e) = self.resync().await {
+                error!(error = %e, "Failed to reload whitelabel guilds");
+            }
+        }
+    }
+}
+
+impl State {
+    fn update_guild(&mut self, payload: whitelabel_guilds::Payload) {
+        let (bot_id, guild_id) = (payload.bot_id, payload.guild_id);
+        debug!(%bot_id, %guild_id, joined = payload.joined, "Whitelabel guild update");
+
+        if payload.joined {
+            self.guilds.entry(guild_id).or_default().insert(bot_id);
+        } else if let Some(bots) = self.guilds.get_mut(&guild_id) {
+            // The guild may have more than one whitelabel bot
+            bots.remove(&bot_id);
+            if bots.is_empty() {
+                self.guilds.remove(&guild_id);
+            }
+        }
+    }
+
+    fn update_health(&mut self, payload: whitelabel_health::Payload, now: Instant) {
+        let bot_id = payload.bot_id;
+
+        if payload.state == BotState::Connected && payload.message_content {
+            self.connected.insert(bot_id, now);
+        } else {
+            self.connected.remove(&bot_id);
+        }
+
+        // The guilds of a deleted bot are deleted along with it, without a leave being published
+        if payload.state == BotState::Removed {
+            self.guilds.retain(|_, bots| {
+                bots.remove(&bot_id);
+                !bots.is_empty()
+            });
+        }
+    }
+
+    fn is_connected(&self, bot_id: Snowflake, now: Instant) -> bool {
+        self.connected.get(&bot_id).is_some_and(|refreshed_at| {
+            now.saturating_duration_since(*refreshed_at) < whitelabel_health::CONNECTED_TTL
+        })
+    }
+
+    fn action(&self, guild_id: Snowflake, action: FilterAction, now: Instant) -> FilterAction {
+        match self.guilds.get(&guild_id) {
+            Some(bots) if bots.iter().any(|bot_id| self.is_connected(*bot_id, now)) => action,
+            Some(_) => action.min(FilterAction::Tag),
+            None => FilterAction::Forward,
+        }
+    }
+}
+
+impl EventFilter for WhitelabelGuildFilter {
+    fn filter(&self, event: &Event, guild_id: Option<Snowflake>) -> FilterAction {
+        match guild_id {
+            Some(guild_id) if WORKER_EVENTS.contains(&event.name()) => {
+                self.state
+                    .read()
+                    .action(guild_id, self.action, Instant::now())
+            }
+            _ => FilterAction::Forward,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BOT: Snowflake = Snowflake(1);
+    const GUILD: Snowflake = Snowflake(2);
+
+    fn health(state: BotState, message_content: bool) -> whitelabel_health::Payload {
+        whitelabel_health::Payload {
+            bot_id: BOT,
+            state,
+            last_connected: None,
+            reconnect_count: 0,
+            guild_count: 1,
+            message_content,
+            last_error: None,
+        }
+    }
+
+    fn joined(state: &mut State, joined: bool) {
+        state.update_guild(whitelabel_guilds::Payload {
+            bot_id: BOT,
+            guild_id: GUILD,
+            joined,
+        });
+    }
+
+    #[test]
+    fn test_drops_only_while_connected_with_message_content() {
+        let mut state = State::default();
+        let now = Instant::now();
+        assert_eq!(
+            state.action(GUILD, FilterAction::Drop, now),
+            FilterAction::Forward
+        );
+
+        joined(&mut state, true);
+        assert_eq!(
+            state.action(GUILD, FilterAction::Drop, now),
+            FilterAction::Tag
+        );
+
+        state.update_health(health(BotState::Connected, true), now);
+        assert_eq!(
+            state.action(GUILD, FilterAction::Drop, now),
+            FilterAction::Drop
+        );
+        assert_eq!(
+            state.action(GUILD, FilterAction::Tag, now),
+            FilterAction::Tag
+        );
+
+        for bot_state in [
+            BotState::Reconnecting,
+            BotState::Quarantined,
+            BotState::Stopped,
+        ] {
+            state.update_health(health(bot_state, true), now);
+            assert_eq!(
+                state.action(GUILD, FilterAction::Drop, now),
+                FilterAction::Tag
+            );
+        }
+
+        // Connected again after Discord rejected the privileged intents
+        state.update_health(health(BotState::Connected, false), now);
+        assert_eq!(
+            state.action(GUILD, FilterAction::Drop, now),
+            FilterAction::Tag
+        );
+
+        state.update_health(health(BotState::Connected, true), now);
+        joined(&mut state, false);
+        assert_eq!(
+            state.action(GUILD, FilterAction::Drop, now),
+            FilterAction::Forward
+        );
+    }
+
+    #[test]
+    fn test_removed_bot_leaves_its_guilds() {
+        let mut state = State::default();
+        let now = Instant::now();
+        joined(&mut state, true);
+        state.update_health(health(BotState::Connected, true), now);
+
+        state.update_health(health(BotState::Removed, false), now);
+        assert_eq!(
+            state.action(GUILD, FilterAction::Drop, now),
+            FilterAction::Forward
+        );
+    }
+
+    #[test]
+    fn test_stale_bot_falls_back_to_tag() {
+        let mut state = State::default();
+        let now = Instant::now();
+        joined(&mut state, true);
+        state.update_health(health(BotState::Connected, true), now);
+
+        // The sharder running the bot stopped without recording it
+        let stale = now + whitelabel_health::CONNECTED_TTL;
+        assert_eq!(
+            state.action(GUILD, FilterAction::Drop, stale),
+            FilterAction::Tag
+        );
+
+        state.update_health(health(BotState::Connected, true), stale);
+        assert_eq!(
+            state.action(GUILD, FilterAction::Drop, stale),
+            FilterAction::Drop
+        );
+    }
+}
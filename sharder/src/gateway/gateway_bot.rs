@@ -0,0 +1,45 @@
+use crate::Result;
+use serde::Deserialize;
+use std::time::Duration;
+
+pub static DEFAULT_GATEWAY_BOT_URL: &str = "https://discord.com/api/v10/gateway/bot";
+
+/// Response of GET /gateway/bot
+#[derive(Deserialize, Debug)]
+pub struct GatewayBot {
+    pub url: String,
+    /// Recommended number of shards
+    pub shards: u16,
+    pub session_start_limit: SessionStartLimit,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SessionStartLimit {
+    pub total: u32,
+    pub remaining: u32,
+    /// Milliseconds until the limit resets
+    pub reset_after: u64,
+    /// Number of identify buckets. Shards are placed in bucket `shard_id % max_concurrency`, and
+    /// each bucket may identify once every 5 seconds.
+    pub max_concurrency: u16,
+}
+
+impl GatewayBot {
+    pub async fn fetch(url: &str, token: &str) -> Result<GatewayBot> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .use_rustls_tls()
+            .build()?;
+
+        let gateway_bot = client
+            .get(url)
+            .header("Authorization", format!("Bot {token}"))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(gateway_bot)
+    }
+}
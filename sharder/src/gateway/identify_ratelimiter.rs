@@ -0,0 +1,139 @@
+use crate::{Config, Result, SessionStoreKind};
+use deadpool_redis::redis::{self, cmd, AsyncCommands};
+use deadpool_redis::Pool;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+// Held while connecting, so that other shards in the bucket wait until the IDENTIFY is sent
+const LOCK_DURATION: Duration = Duration::from_millis(8000);
+
+// Each bucket may identify once every 5 seconds
+const IDENTIFY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Spaces out the IDENTIFYs of shards in the same bucket. Sharders share the ratelimit through
+/// Redis, unless sessions are kept in memory, as then there is only a single sharder.
+pub enum IdentifyRatelimiter {
+    Redis(Arc<Pool>),
+    Memory(Mutex<HeldBuckets>),
+}
+
+/// When each bucket may next identify
+#[derive(Default)]
+pub struct HeldBuckets(HashMap<String, Instant>);
+
+impl HeldBuckets {
+    /// Holds the bucket if it is free, otherwise returns how long until it will be
+    fn try_hold(&mut self, key: &str, now: Instant) -> Result<(), Duration> {
+        match self.0.get(key) {
+            Some(&until) if until > now => Err(until - now),
+            _ => {
+                self.0.insert(key.to_owned(), now + LOCK_DURATION);
+                Ok(())
+            }
+        }
+    }
+
+    fn identified(&mut self, key: &str, now: Instant) {
+        self.0.insert(key.to_owned(), now + IDENTIFY_INTERVAL);
+    }
+}
+
+impl IdentifyRatelimiter {
+    pub fn new(config: &Config, redis: Arc<Pool>) -> Self {
+        match config.session_store {
+            SessionStoreKind::Memory => Self::memory(),
+            SessionStoreKind::Redis | SessionStoreKind::Postgres => Self::Redis(redis),
+        }
+    }
+
+    pub fn memory() -> Self {
+        Self::Memory(Mutex::new(HeldBuckets::default()))
+    }
+
+    /// Waits until the bucket may identify, and holds it until `identified` is called or the lock
+    /// expires
+    pub async fn wait(&self, key: &str) -> Result<()> {
+        match self {
+            Self::Redis(redis) => Self::wait_redis(redis, key).await,
+            Self::Memory(buckets) => loop {
+                let res = buckets.lock().try_hold(key, Instant::now());
+                match res {
+                    Ok(()) => return Ok(()),
+                    Err(wait) => sleep(wait).await,
+                }
+            },
+        }
+    }
+
+    async fn wait_redis(redis: &Pool, key: &str) -> Result<()> {
+        let mut res = redis::Value::Nil;
+        while res == redis::Value::Nil {
+            let mut conn = redis.get().await?;
+
+            res = cmd("SET")
+                .arg(key)
+                .arg("1") // some arbitrary value
+                .arg("NX")
+                .arg("PX")
+                .arg(LOCK_DURATION.as_millis() as u64)
+                .query_async(&mut conn)
+                .await?;
+
+            if res == redis::Value::Nil {
+                // get time to delay
+                let ttl = cmd("PTTL").arg(key).query_async(&mut conn).await?;
+
+                if let redis::Value::Int(ttl) = ttl {
+                    // if number is negative, we can go ahead and identify
+                    // -1 = no expire, -2 = doesn't exist
+                    if ttl > 0 {
+                        let ttl = Duration::from_millis(ttl as u64);
+                        sleep(ttl).await
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Keeps the bucket held until the next IDENTIFY is allowed
+    pub async fn identified(&self, key: &str) -> Result<()> {
+        match self {
+            Self::Redis(redis) => {
+                let mut conn = redis.get().await?;
+                conn.set_ex(key, "1", IDENTIFY_INTERVAL.as_secs() as usize)
+                    .await?;
+            }
+            Self::Memory(buckets) => buckets.lock().identified(key, Instant::now()),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_held_buckets() {
+        let now = Instant::now();
+        let mut buckets = HeldBuckets::default();
+
+        assert!(buckets.try_hold("bucket", now).is_ok());
+        assert_eq!(buckets.try_hold("bucket", now), Err(LOCK_DURATION));
+        assert!(buckets.try_hold("other", now).is_ok());
+
+        buckets.identified("bucket", now);
+        assert_eq!(buckets.try_hold("bucket", now), Err(IDENTIFY_INTERVAL));
+        assert!(buckets.try_hold("bucket", now + IDENTIFY_INTERVAL).is_ok());
+
+        // Freed once the lock expires, if the shard never identifies
+        let later = now + IDENTIFY_INTERVAL + LOCK_DURATION;
+        assert!(buckets.try_hold("bucket", later).is_ok());
+    }
+}
@@ -1,4 +1,6 @@
-#[derive(Copy, Clone)]
+use crate::{GatewayError, Result};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[allow(dead_code)]
 pub enum Intents {
     Guilds = 1 << 0,
@@ -23,9 +25,119 @@ pub enum Intents {
 }
 
 impl Intents {
+    pub const ALL: [Intents; 19] = [
+        Intents::Guilds,
+        Intents::GuildMembers,
+        Intents::GuildModeration,
+        Intents::GuildEmojis,
+        Intents::GuildIntegrations,
+        Intents::GuildWebhooks,
+        Intents::GuildInvites,
+        Intents::GuildVoiceStates,
+        Intents::GuildPresences,
+        Intents::GuildMessages,
+        Intents::GuildMessageReactions,
+        Intents::GuildMessageTyping,
+        Intents::DirectMessages,
+        Intents::DirectMessageReaction,
+        Intents::DirectMessageTyping,
+        Intents::MessageContent,
+        Intents::GuildScheduledEvents,
+        Intents::AutoModerationConfiguration,
+        Intents::AutoModerationExecution,
+    ];
+
+    /// Intents that must be enabled for the bot in the developer portal before they can be used
+    pub const PRIVILEGED: [Intents; 3] = [
+        Intents::GuildMembers,
+        Intents::GuildPresences,
+        Intents::MessageContent,
+    ];
+
     pub fn build(intents: Vec<Intents>) -> u64 {
         let mut sum = 0;
         intents.into_iter().for_each(|i| sum |= i as u64);
         sum
     }
+
+    /// Parses either a raw bitmask, or a comma separated list of intent names. Names are matched
+    /// case insensitively, ignoring underscores, so both `GuildMessages` and `GUILD_MESSAGES` are
+    /// accepted.
+    #[allow(clippy::result_large_err)] // Only used while parsing config
+    pub fn parse(s: &str) -> Result<u64> {
+        let s = s.trim();
+
+        let intents = if s.bytes().all(|b| b.is_ascii_digit()) {
+            s.parse()?
+        } else {
+            s.split(',')
+                .map(|name| {
+                    let name = name.trim().replace('_', "");
+                    Intents::ALL
+                        .iter()
+                        .find(|intent| format!("{intent:?}").eq_ignore_ascii_case(&name))
+                        .map(|&intent| intent as u64)
+                        .ok_or_else(|| GatewayError::custom(format!("unknown intent {name}")))
+                })
+                .collect::<Result<Vec<_>>>()?
+                .into_iter()
+                .fold(0, |acc, intent| acc | intent)
+        };
+
+        let unknown = intents & !Intents::build(Intents::ALL.to_vec());
+        if unknown != 0 {
+            return GatewayError::custom(format!("unknown intent bits {unknown:#b}")).into();
+        }
+
+        if intents == 0 {
+            return GatewayError::custom("no intents were given").into();
+        }
+
+        Ok(intents)
+    }
+
+    pub fn is_set(&self, intents: u64) -> bool {
+        intents & *self as u64 != 0
+    }
+
+    pub fn without_privileged(intents: u64) -> u64 {
+        intents & !Intents::build(Intents::PRIVILEGED.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Intents::parse("33283").unwrap(), 33283);
+        assert_eq!(
+            Intents::parse("Guilds, GUILD_MEMBERS,guildmessages").unwrap(),
+            Intents::build(vec![
+                Intents::Guilds,
+                Intents::GuildMembers,
+                Intents::GuildMessages
+            ])
+        );
+
+        assert!(Intents::parse("Guilds,GuildTypos").is_err());
+        assert!(Intents::parse(&(1u64 << 17).to_string()).is_err());
+        assert!(Intents::parse("0").is_err());
+    }
+
+    #[test]
+    fn test_without_privileged() {
+        let intents = Intents::build(vec![
+            Intents::Guilds,
+            Intents::GuildMembers,
+            Intents::GuildMessages,
+            Intents::MessageContent,
+        ]);
+
+        assert_eq!(
+            Intents::without_privileged(intents),
+            Intents::build(vec![Intents::Guilds, Intents::GuildMessages])
+        );
+    }
 }
@@ -1,6 +1,27 @@
+use crate::{GuildMembers, Result};
 use model::user::StatusUpdate;
+use model::Snowflake;
+use tokio::sync::oneshot;
 
+#[allow(clippy::large_enum_variant)] // Commands are infrequent
 pub enum InternalCommand {
-    StatusUpdate { status: StatusUpdate },
+    StatusUpdate {
+        status: StatusUpdate,
+    },
+    /// Sends a REQUEST_GUILD_MEMBERS payload, replying once every chunk with a matching nonce
+    /// has been received. Only one of query and user_ids may be Some: if both are None, every
+    /// member of the guild is requested. The nonce must be at most 32 bytes.
+    RequestGuildMembers {
+        guild_id: Snowflake,
+        query: Option<String>,
+        user_ids: Option<Vec<Snowflake>>,
+        nonce: String,
+        reply: oneshot::Sender<Result<GuildMembers>>,
+    },
+    /// Closes the connection so that the manager starts a new one. If resume is false, the session
+    /// is dropped and the new connection will IDENTIFY.
+    Reconnect {
+        resume: bool,
+    },
     Shutdown,
 }
@@ -0,0 +1,153 @@
+use crate::gateway::payloads::event::GuildMembersChunk;
+use crate::{GatewayError, Result};
+use model::guild::Member;
+use model::Snowflake;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// How long a member request may take to receive every chunk before it fails
+pub(crate) const MEMBER_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The aggregated result of every GUILD_MEMBERS_CHUNK received in response to a single
+/// REQUEST_GUILD_MEMBERS payload
+#[derive(Debug)]
+pub struct GuildMembers {
+    pub guild_id: Snowflake,
+    pub members: Vec<Member>,
+    pub not_found: Vec<Snowflake>,
+}
+
+pub(crate) struct PendingMemberRequest {
+    result: GuildMembers,
+    received_chunks: u32,
+    reply: MemberRequestReply,
+    expires_at: Instant,
+}
+
+/// Where the result of a member request is sent. It is shared with the task waiting for the
+/// request to be written, so that the request fails if it can't be.
+#[derive(Clone)]
+pub(crate) struct MemberRequestReply(Arc<Mutex<Option<oneshot::Sender<Result<GuildMembers>>>>>);
+
+impl MemberRequestReply {
+    /// Returns false if a result has already been sent, or the requester has hung up
+    pub fn send(&self, result: Result<GuildMembers>) -> bool {
+        match self.0.lock().take() {
+            Some(reply) => reply.send(result).is_ok(),
+            None => false,
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.0.lock().as_ref().is_none_or(|reply| reply.is_closed())
+    }
+}
+
+impl PendingMemberRequest {
+    pub fn new(guild_id: Snowflake, reply: oneshot::Sender<Result<GuildMembers>>) -> Self {
+        Self {
+            result: GuildMembers {
+                guild_id,
+                members: Vec::new(),
+                not_found: Vec::new(),
+            },
+            received_chunks: 0,
+            reply: MemberRequestReply(Arc::new(Mutex::new(Some(reply)))),
+            expires_at: Instant::now() + MEMBER_REQUEST_TIMEOUT,
+        }
+    }
+
+    /// Returns true once every chunk for the request has been received
+    pub fn push(&mut self, chunk: GuildMembersChunk) -> bool {
+        self.received_chunks += 1;
+
+        self.result.members.extend(chunk.members);
+        if let Some(not_found) = chunk.not_found {
+            self.result.not_found.extend(not_found);
+        }
+
+        self.received_chunks >= chunk.chunk_count
+    }
+
+    pub fn reply(&self) -> MemberRequestReply {
+        self.reply.clone()
+    }
+
+    /// Returns false if the requester has hung up, or the request has already failed
+    pub fn complete(self) -> bool {
+        self.reply.send(Ok(self.result))
+    }
+
+    pub fn fail(self, error: GatewayError) {
+        self.reply.send(Err(error));
+    }
+
+    /// Returns true if the request has timed out or failed, or the requester has stopped waiting
+    /// for it
+    pub fn is_abandoned(&self, now: Instant) -> bool {
+        now >= self.expires_at || self.reply.is_closed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn chunk(chunk_index: u32, chunk_count: u32, not_found: Vec<u64>) -> GuildMembersChunk {
+        GuildMembersChunk {
+            guild_id: Snowflake(1),
+            members: Vec::new(),
+            chunk_index,
+            chunk_count,
+            not_found: Some(not_found.into_iter().map(Snowflake).collect()),
+            presences: None,
+            nonce: Some("nonce".to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_aggregates_all_chunks() {
+        let (tx, mut rx) = oneshot::channel();
+        let mut pending = PendingMemberRequest::new(Snowflake(1), tx);
+
+        assert!(!pending.push(chunk(0, 3, vec![2])));
+        assert!(!pending.push(chunk(1, 3, vec![])));
+        assert!(pending.push(chunk(2, 3, vec![3])));
+
+        assert!(pending.complete());
+
+        let res = rx.try_recv().unwrap().unwrap();
+        assert_eq!(res.guild_id, Snowflake(1));
+        assert_eq!(res.not_found, vec![Snowflake(2), Snowflake(3)]);
+    }
+
+    #[test]
+    fn test_is_abandoned() {
+        let (tx, rx) = oneshot::channel();
+        let pending = PendingMemberRequest::new(Snowflake(1), tx);
+
+        let now = Instant::now();
+        assert!(!pending.is_abandoned(now));
+        assert!(pending.is_abandoned(now + MEMBER_REQUEST_TIMEOUT));
+
+        drop(rx);
+        assert!(pending.is_abandoned(now));
+    }
+
+    #[test]
+    fn test_fails_through_reply() {
+        let (tx, mut rx) = oneshot::channel();
+        let pending = PendingMemberRequest::new(Snowflake(1), tx);
+
+        assert!(pending
+            .reply()
+            .send(Err(GatewayError::custom("write failed"))));
+        assert!(rx.try_recv().unwrap().is_err());
+
+        // The request can't complete once it has failed
+        assert!(pending.is_abandoned(Instant::now()));
+        assert!(!pending.complete());
+    }
+}
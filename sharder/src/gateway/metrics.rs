@@ -0,0 +1,154 @@
+use crate::event_forwarding::{FilterAction, Route};
+use model::Snowflake;
+use std::time::Duration;
+
+#[cfg(feature = "metrics")]
+use lazy_static::lazy_static;
+
+#[cfg(feature = "metrics")]
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, Histogram, HistogramVec, IntCounter,
+    IntCounterVec,
+};
+
+#[cfg(feature = "metrics")]
+lazy_static! {
+    static ref HEARTBEAT_RTT: HistogramVec = register_histogram_vec!(
+        "gateway_heartbeat_rtt_seconds",
+        "Time between sending a heartbeat and receiving its ACK",
+        &["bot_id", "shard_id"],
+        vec![0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]
+    )
+    .expect("Failed to create heartbeat RTT histogram");
+    static ref IDENTIFIES: IntCounterVec = register_int_counter_vec!(
+        "gateway_identifies_total",
+        "The number of IDENTIFY payloads sent",
+        &["bot_id", "shard_id"]
+    )
+    .expect("Failed to create identify counter");
+    static ref RESUMES: IntCounterVec = register_int_counter_vec!(
+        "gateway_resumes_total",
+        "The number of RESUME payloads sent",
+        &["bot_id", "shard_id"]
+    )
+    .expect("Failed to create resume counter");
+    static ref RECONNECTS: IntCounterVec = register_int_counter_vec!(
+        "gateway_reconnects_total",
+        "The number of times a connection was closed so that the shard would reconnect",
+        &["bot_id", "shard_id", "reason"]
+    )
+    .expect("Failed to create reconnect counter");
+    static ref EVENTS_RECEIVED: IntCounterVec = register_int_counter_vec!(
+        "gateway_events_received_total",
+        "The number of dispatches received, by event type",
+        &["bot_id", "shard_id", "event"]
+    )
+    .expect("Failed to create received events counter");
+    static ref EVENTS_FILTERED: IntCounterVec = register_int_counter_vec!(
+        "gateway_events_filtered_total",
+        "The number of events dropped or tagged by event filters, by event type",
+        &["bot_id", "shard_id", "event", "action"]
+    )
+    .expect("Failed to create filtered events counter");
+    static ref FORWARD_DURATION: HistogramVec = register_histogram_vec!(
+        "event_forward_duration_seconds",
+        "Time taken to forward an event",
+        &["bot_id", "shard_id", "route"],
+        vec![0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5]
+    )
+    .expect("Failed to create forward duration histogram");
+    static ref FORWARD_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "event_forward_errors_total",
+        "The number of events that could not be forwarded",
+        &["bot_id", "shard_id", "route"]
+    )
+    .expect("Failed to create forward error counter");
+}
+
+/// Metrics of a single shard. Without the metrics feature, recording does nothing.
+#[derive(Clone)]
+pub(crate) struct ShardMetrics {
+    #[cfg(feature = "metrics")]
+    bot_id: String,
+    #[cfg(feature = "metrics")]
+    shard_id: String,
+    #[cfg(feature = "metrics")]
+    heartbeat_rtt: Histogram,
+    #[cfg(feature = "metrics")]
+    identifies: IntCounter,
+    #[cfg(feature = "metrics")]
+    resumes: IntCounter,
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+impl ShardMetrics {
+    pub fn new(bot_id: Snowflake, shard_id: u16) -> Self {
+        #[cfg(feature = "metrics")]
+        {
+            let (bot_id, shard_id) = (bot_id.to_string(), shard_id.to_string());
+            let labels = [bot_id.as_str(), shard_id.as_str()];
+
+            Self {
+                heartbeat_rtt: HEARTBEAT_RTT.with_label_values(&labels),
+                identifies: IDENTIFIES.with_label_values(&labels),
+                resumes: RESUMES.with_label_values(&labels),
+                bot_id,
+                shard_id,
+            }
+        }
+
+        #[cfg(not(feature = "metrics"))]
+        Self {}
+    }
+
+    pub fn heartbeat_acked(&self, rtt: Duration) {
+        #[cfg(feature = "metrics")]
+        self.heartbeat_rtt.observe(rtt.as_secs_f64());
+    }
+
+    pub fn identified(&self) {
+        #[cfg(feature = "metrics")]
+        self.identifies.inc();
+    }
+
+    pub fn resumed(&self) {
+        #[cfg(feature = "metrics")]
+        self.resumes.inc();
+    }
+
+    pub fn reconnecting(&self, reason: &str) {
+        #[cfg(feature = "metrics")]
+        RECONNECTS
+            .with_label_values(&[&self.bot_id, &self.shard_id, reason])
+            .inc();
+    }
+
+    pub fn event_received(&self, event: &str) {
+        #[cfg(feature = "metrics")]
+        EVENTS_RECEIVED
+            .with_label_values(&[&self.bot_id, &self.shard_id, event])
+            .inc();
+    }
+
+    pub fn event_filtered(&self, event: &str, action: FilterAction) {
+        #[cfg(feature = "metrics")]
+        EVENTS_FILTERED
+            .with_label_values(&[&self.bot_id, &self.shard_id, event, action.as_str()])
+            .inc();
+    }
+
+    pub fn event_forwarded(&self, route: Route, elapsed: Duration, success: bool) {
+        #[cfg(feature = "metrics")]
+        {
+            let labels = [self.bot_id.as_str(), self.shard_id.as_str(), route.as_str()];
+
+            FORWARD_DURATION
+                .with_label_values(&labels)
+                .observe(elapsed.as_secs_f64());
+
+            if !success {
+                FORWARD_ERRORS.with_label_values(&labels).inc();
+            }
+        }
+    }
+}
@@ -4,10 +4,13 @@ pub use shard::Shard;
 pub mod payloads;
 
 mod session_store;
-pub use session_store::{RedisSessionStore, SessionData, SessionStore};
+pub use session_store::{
+    CheckpointSender, MemorySessionStore, PostgresSessionStore, RedisSessionStore,
+    SessionCheckpointer, SessionData, SessionStore, SessionStoreKind,
+};
 
 mod close_event;
-pub use close_event::CloseEvent;
+pub use close_event::{CloseEvent, CloseKind};
 
 mod error;
 pub use error::*;
@@ -15,6 +18,25 @@ pub use error::*;
 mod outbound_message;
 use outbound_message::OutboundMessage;
 
+mod metrics;
+
+mod ratelimiter;
+use ratelimiter::Ratelimiter;
+
+mod identify_ratelimiter;
+pub use identify_ratelimiter::{HeldBuckets, IdentifyRatelimiter};
+
+#[cfg(feature = "transport-compression")]
+pub mod compression;
+
+mod encoding;
+pub use encoding::Encoding;
+
+pub mod etf;
+
+mod gateway_bot;
+pub use gateway_bot::{GatewayBot, SessionStartLimit, DEFAULT_GATEWAY_BOT_URL};
+
 mod shardinfo;
 pub use shardinfo::ShardInfo;
 
@@ -30,8 +52,16 @@ pub use timer::timer;
 
 pub mod event_forwarding;
 
+mod shard_status;
+pub use shard_status::{ShardRegistry, ShardState, ShardStatus, ShardStatusHandle};
+
 mod shard_identifier;
 pub use shard_identifier::ShardIdentifier;
 
 mod internal_command;
 pub use internal_command::InternalCommand;
+
+mod member_request;
+pub use member_request::GuildMembers;
+#[cfg(not(feature = "whitelabel"))]
+pub(crate) use member_request::MEMBER_REQUEST_TIMEOUT;
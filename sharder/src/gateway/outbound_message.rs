@@ -1,22 +1,33 @@
+use crate::gateway::{etf, Encoding};
 use crate::Result;
 use serde::Serialize;
 use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::Message;
 
 #[derive(Debug)]
 pub struct OutboundMessage {
-    pub message: String,
+    pub message: Message,
+    /// Priority messages may use the ratelimit capacity reserved for heartbeats, IDENTIFY and
+    /// RESUME, and skip the queue of other commands
+    pub priority: bool,
     pub tx: oneshot::Sender<Result<()>>,
 }
 
 impl OutboundMessage {
     pub fn new<T: Serialize>(
         msg: T,
+        priority: bool,
+        encoding: Encoding,
         tx: oneshot::Sender<Result<()>>,
     ) -> Result<OutboundMessage, serde_json::Error> {
-        let serialized = serde_json::to_string(&msg)?;
+        let message = match encoding {
+            Encoding::Json => Message::Text(serde_json::to_string(&msg)?),
+            Encoding::Etf => Message::Binary(etf::encode(&serde_json::to_value(&msg)?)),
+        };
 
         Ok(OutboundMessage {
-            message: serialized,
+            message,
+            priority,
             tx,
         })
     }
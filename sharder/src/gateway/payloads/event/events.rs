@@ -68,68 +68,129 @@ pub enum Event {
     Unknown(serde_json::Value),
 }
 
-impl fmt::Display for Event {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl Event {
+    /// The name of every event that has a variant, as returned by `name`
+    pub const NAMES: &'static [&'static str] = &[
+        "READY",
+        "RESUMED",
+        "APPLICATION_COMMAND_CREATE",
+        "APPLICATION_COMMAND_UPDATE",
+        "APPLICATION_COMMAND_DELETE",
+        "APPLICATION_COMMAND_PERMISSIONS_UPDATE",
+        "CHANNEL_CREATE",
+        "CHANNEL_UPDATE",
+        "CHANNEL_DELETE",
+        "CHANNEL_PINS_UPDATE",
+        "THREAD_CREATE",
+        "THREAD_UPDATE",
+        "THREAD_DELETE",
+        "THREAD_LIST_SYNC",
+        "THREAD_MEMBER_UPDATE",
+        "THREAD_MEMBERS_UPDATE",
+        "GUILD_CREATE",
+        "GUILD_UPDATE",
+        "GUILD_DELETE",
+        "GUILD_BAN_ADD",
+        "GUILD_BAN_REMOVE",
+        "GUILD_EMOJIS_UPDATE",
+        "GUILD_INTEGRATIONS_UPDATE",
+        "GUILD_JOIN_REQUEST_UPDATE",
+        "GUILD_JOIN_REQUEST_DELETE",
+        "GUILD_MEMBER_ADD",
+        "GUILD_MEMBER_REMOVE",
+        "GUILD_MEMBER_UPDATE",
+        "GUILD_MEMBERS_CHUNK",
+        "GUILD_ROLE_CREATE",
+        "GUILD_ROLE_UPDATE",
+        "GUILD_ROLE_DELETE",
+        "INVITE_CREATE",
+        "INVITE_DELETE",
+        "MESSAGE_CREATE",
+        "MESSAGE_UPDATE",
+        "MESSAGE_DELETE",
+        "MESSAGE_DELETE_BULK",
+        "MESSAGE_REACTION_ADD",
+        "MESSAGE_REACTION_REMOVE",
+        "MESSAGE_REACTION_REMOVE_ALL",
+        "MESSAGE_REACTION_REMOVE_EMOJI",
+        "PRESENCE_UPDATE",
+        "STAGE_INSTANCE_CREATE",
+        "STAGE_INSTANCE_UPDATE",
+        "STAGE_INSTANCE_DELETE",
+        "TYPING_START",
+        "USER_UPDATE",
+        "VOICE_CHANNEL_STATUS_UPDATE",
+        "VOICE_STATE_UPDATE",
+        "VOICE_SERVER_UPDATE",
+        "WEBHOOK_UPDATE",
+    ];
+
+    /// The name of the event, as sent in the t field of the dispatch
+    pub fn name(&self) -> &'static str {
         match self {
-            Event::Ready(_) => write!(f, "READY"),
-            Event::Resumed(_) => write!(f, "RESUMED"),
-            Event::ApplicationCommandCreate(_) => write!(f, "APPLICATION_COMMAND_CREATE"),
-            Event::ApplicationCommandUpdate(_) => write!(f, "APPLICATION_COMMAND_UPDATE"),
-            Event::ApplicationCommandDelete(_) => write!(f, "APPLICATION_COMMAND_DELETE"),
-            Event::ApplicationCommandPermissionsUpdate(_) => {
-                write!(f, "APPLICATION_COMMAND_PERMISSIONS_UPDATE")
-            }
-            Event::ChannelCreate(_) => write!(f, "CHANNEL_CREATE"),
-            Event::ChannelUpdate(_) => write!(f, "CHANNEL_UPDATE"),
-            Event::ChannelDelete(_) => write!(f, "CHANNEL_DELETE"),
-            Event::ChannelPinsUpdate(_) => write!(f, "CHANNEL_PINS_UPDATE"),
-            Event::ThreadCreate(_) => write!(f, "THREAD_CREATE"),
-            Event::ThreadUpdate(_) => write!(f, "THREAD_UPDATE"),
-            Event::ThreadDelete(_) => write!(f, "THREAD_DELETE"),
-            Event::ThreadListSync(_) => write!(f, "THREAD_LIST_SYNC"),
-            Event::ThreadMemberUpdate(_) => write!(f, "THREAD_MEMBER_UPDATE"),
-            Event::ThreadMembersUpdate(_) => write!(f, "THREAD_MEMBERS_UPDATE"),
-            Event::GuildCreate(_) => write!(f, "GUILD_CREATE"),
-            Event::GuildUpdate(_) => write!(f, "GUILD_UPDATE"),
-            Event::GuildDelete(_) => write!(f, "GUILD_DELETE"),
-            Event::GuildBanAdd(_) => write!(f, "GUILD_BAN_ADD"),
-            Event::GuildBanRemove(_) => write!(f, "GUILD_BAN_REMOVE"),
-            Event::GuildEmojisUpdate(_) => write!(f, "GUILD_EMOJIS_UPDATE"),
-            Event::GuildIntegrationsUpdate(_) => write!(f, "GUILD_INTEGRATIONS_UPDATE"),
-            Event::GuildJoinRequestUpdate(_) => write!(f, "GUILD_JOIN_REQUEST_UPDATE"),
-            Event::GuildJoinRequestDelete(_) => write!(f, "GUILD_JOIN_REQUEST_DELETE"),
-            Event::GuildMemberAdd(_) => write!(f, "GUILD_MEMBER_ADD"),
-            Event::GuildMemberRemove(_) => write!(f, "GUILD_MEMBER_REMOVE"),
-            Event::GuildMemberUpdate(_) => write!(f, "GUILD_MEMBER_UPDATE"),
-            Event::GuildMembersChunk(_) => write!(f, "GUILD_MEMBERS_CHUNK"),
-            Event::GuildRoleCreate(_) => write!(f, "GUILD_ROLE_CREATE"),
-            Event::GuildRoleUpdate(_) => write!(f, "GUILD_ROLE_UPDATE"),
-            Event::GuildRoleDelete(_) => write!(f, "GUILD_ROLE_DELETE"),
-            Event::InviteCreate(_) => write!(f, "INVITE_CREATE"),
-            Event::InviteDelete(_) => write!(f, "INVITE_DELETE"),
-            Event::MessageCreate(_) => write!(f, "MESSAGE_CREATE"),
-            Event::MessageUpdate(_) => write!(f, "MESSAGE_UPDATE"),
-            Event::MessageDelete(_) => write!(f, "MESSAGE_DELETE"),
-            Event::MessageDeleteBulk(_) => write!(f, "MESSAGE_DELETE_BULK"),
-            Event::MessageReactionAdd(_) => write!(f, "MESSAGE_REACTION_ADD"),
-            Event::MessageReactionRemove(_) => write!(f, "MESSAGE_REACTION_REMOVE"),
-            Event::MessageReactionRemoveAll(_) => write!(f, "MESSAGE_REACTION_REMOVE_ALL"),
-            Event::MessageReactionRemoveEmoji(_) => write!(f, "MESSAGE_REACTION_REMOVE_EMOJI"),
-            Event::PresenceUpdate(_) => write!(f, "PRESENCE_UPDATE"),
-            Event::StageInstanceCreate(_) => write!(f, "STAGE_INSTANCE_CREATE"),
-            Event::StageInstanceUpdate(_) => write!(f, "STAGE_INSTANCE_UPDATE"),
-            Event::StageInstanceDelete(_) => write!(f, "STAGE_INSTANCE_DELETE"),
-            Event::TypingStart(_) => write!(f, "TYPING_START"),
-            Event::UserUpdate(_) => write!(f, "USER_UPDATE"),
-            Event::VoiceChannelStatusUpdate(_) => write!(f, "VOICE_CHANNEL_STATUS_UPDATE"),
-            Event::VoiceStateUpdate(_) => write!(f, "VOICE_STATE_UPDATE"),
-            Event::VoiceServerUpdate(_) => write!(f, "VOICE_SERVER_UPDATE"),
-            Event::WebhookUpdate(_) => write!(f, "WEBHOOK_UPDATE"),
-            Event::Unknown(_) => write!(f, "UNKNOWN"),
+            Event::Ready(_) => "READY",
+            Event::Resumed(_) => "RESUMED",
+            Event::ApplicationCommandCreate(_) => "APPLICATION_COMMAND_CREATE",
+            Event::ApplicationCommandUpdate(_) => "APPLICATION_COMMAND_UPDATE",
+            Event::ApplicationCommandDelete(_) => "APPLICATION_COMMAND_DELETE",
+            Event::ApplicationCommandPermissionsUpdate(_) => "APPLICATION_COMMAND_PERMISSIONS_UPDATE",
+            Event::ChannelCreate(_) => "CHANNEL_CREATE",
+            Event::ChannelUpdate(_) => "CHANNEL_UPDATE",
+            Event::ChannelDelete(_) => "CHANNEL_DELETE",
+            Event::ChannelPinsUpdate(_) => "CHANNEL_PINS_UPDATE",
+            Event::ThreadCreate(_) => "THREAD_CREATE",
+            Event::ThreadUpdate(_) => "THREAD_UPDATE",
+            Event::ThreadDelete(_) => "THREAD_DELETE",
+            Event::ThreadListSync(_) => "THREAD_LIST_SYNC",
+            Event::ThreadMemberUpdate(_) => "THREAD_MEMBER_UPDATE",
+            Event::ThreadMembersUpdate(_) => "THREAD_MEMBERS_UPDATE",
+            Event::GuildCreate(_) => "GUILD_CREATE",
+            Event::GuildUpdate(_) => "GUILD_UPDATE",
+            Event::GuildDelete(_) => "GUILD_DELETE",
+            Event::GuildBanAdd(_) => "GUILD_BAN_ADD",
+            Event::GuildBanRemove(_) => "GUILD_BAN_REMOVE",
+            Event::GuildEmojisUpdate(_) => "GUILD_EMOJIS_UPDATE",
+            Event::GuildIntegrationsUpdate(_) => "GUILD_INTEGRATIONS_UPDATE",
+            Event::GuildJoinRequestUpdate(_) => "GUILD_JOIN_REQUEST_UPDATE",
+            Event::GuildJoinRequestDelete(_) => "GUILD_JOIN_REQUEST_DELETE",
+            Event::GuildMemberAdd(_) => "GUILD_MEMBER_ADD",
+            Event::GuildMemberRemove(_) => "GUILD_MEMBER_REMOVE",
+            Event::GuildMemberUpdate(_) => "GUILD_MEMBER_UPDATE",
+            Event::GuildMembersChunk(_) => "GUILD_MEMBERS_CHUNK",
+            Event::GuildRoleCreate(_) => "GUILD_ROLE_CREATE",
+            Event::GuildRoleUpdate(_) => "GUILD_ROLE_UPDATE",
+            Event::GuildRoleDelete(_) => "GUILD_ROLE_DELETE",
+            Event::InviteCreate(_) => "INVITE_CREATE",
+            Event::InviteDelete(_) => "INVITE_DELETE",
+            Event::MessageCreate(_) => "MESSAGE_CREATE",
+            Event::MessageUpdate(_) => "MESSAGE_UPDATE",
+            Event::MessageDelete(_) => "MESSAGE_DELETE",
+            Event::MessageDeleteBulk(_) => "MESSAGE_DELETE_BULK",
+            Event::MessageReactionAdd(_) => "MESSAGE_REACTION_ADD",
+            Event::MessageReactionRemove(_) => "MESSAGE_REACTION_REMOVE",
+            Event::MessageReactionRemoveAll(_) => "MESSAGE_REACTION_REMOVE_ALL",
+            Event::MessageReactionRemoveEmoji(_) => "MESSAGE_REACTION_REMOVE_EMOJI",
+            Event::PresenceUpdate(_) => "PRESENCE_UPDATE",
+            Event::StageInstanceCreate(_) => "STAGE_INSTANCE_CREATE",
+            Event::StageInstanceUpdate(_) => "STAGE_INSTANCE_UPDATE",
+            Event::StageInstanceDelete(_) => "STAGE_INSTANCE_DELETE",
+            Event::TypingStart(_) => "TYPING_START",
+            Event::UserUpdate(_) => "USER_UPDATE",
+            Event::VoiceChannelStatusUpdate(_) => "VOICE_CHANNEL_STATUS_UPDATE",
+            Event::VoiceStateUpdate(_) => "VOICE_STATE_UPDATE",
+            Event::VoiceServerUpdate(_) => "VOICE_SERVER_UPDATE",
+            Event::WebhookUpdate(_) => "WEBHOOK_UPDATE",
+            Event::Unknown(_) => "UNKNOWN",
         }
     }
 }
 
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 impl<'de> serde::Deserialize<'de> for Event {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
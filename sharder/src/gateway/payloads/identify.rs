@@ -31,7 +31,8 @@ impl Identify {
             data: IdentifyData {
                 token,
                 properties: ConnectionProperties::new(),
-                compress: Some(cfg!(feature = "compression")),
+                // Per-payload compression can't be combined with transport compression
+                compress: None,
                 large_threshold,
                 shard_info,
                 presence,
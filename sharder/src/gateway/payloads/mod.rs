@@ -20,6 +20,9 @@ pub use presence_update::PresenceUpdate;
 mod resume;
 pub use resume::Resume;
 
+mod request_guild_members;
+pub use request_guild_members::RequestGuildMembers;
+
 mod reconnect;
 pub use reconnect::Reconnect;
 
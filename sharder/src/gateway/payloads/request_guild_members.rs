@@ -0,0 +1,61 @@
+use super::Opcode;
+use model::Snowflake;
+use serde::Serialize;
+
+#[derive(Serialize, Debug)]
+pub struct RequestGuildMembers {
+    #[serde(rename = "op")]
+    opcode: Opcode,
+
+    #[serde(rename = "d")]
+    data: RequestGuildMembersData,
+}
+
+impl RequestGuildMembers {
+    /// Only one of query and user_ids may be Some. If both are None, all members are requested
+    pub fn new(
+        guild_id: Snowflake,
+        query: Option<String>,
+        user_ids: Option<Vec<Snowflake>>,
+        nonce: String,
+    ) -> RequestGuildMembers {
+        // A limit of 0 may only be used with an empty query, which requests every member
+        let (query, limit) = match (query, &user_ids) {
+            (Some(query), _) if !query.is_empty() => (Some(query), Some(100)),
+            (_, Some(_)) => (None, None),
+            (_, None) => (Some(String::new()), Some(0)),
+        };
+
+        RequestGuildMembers {
+            opcode: Opcode::RequestGuildMembers,
+            data: RequestGuildMembersData {
+                guild_id,
+                query,
+                limit,
+                presences: None,
+                user_ids,
+                nonce: Some(nonce),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct RequestGuildMembersData {
+    guild_id: Snowflake,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<u16>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presences: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_ids: Option<Vec<Snowflake>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<String>,
+}
@@ -0,0 +1,119 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Sliding window ratelimiter used to keep each connection under Discord's gateway send limit.
+/// No window of `period` admits more than `capacity` payloads. A number of these are reserved for
+/// priority payloads (heartbeats, IDENTIFY and RESUME), so that queued commands can never starve
+/// a heartbeat.
+#[derive(Debug)]
+pub struct Ratelimiter {
+    capacity: usize,
+    reserved: usize,
+    period: Duration,
+    // When each payload in the current window was sent, oldest first
+    sent: VecDeque<Instant>,
+}
+
+impl Ratelimiter {
+    pub fn new(capacity: u32, period: Duration, reserved: u32) -> Self {
+        assert!(reserved < capacity, "reserved must be less than capacity");
+
+        Self {
+            capacity: capacity as usize,
+            reserved: reserved as usize,
+            period,
+            sent: VecDeque::with_capacity(capacity as usize),
+        }
+    }
+
+    /// Takes a slot if one is available, otherwise returns how long until one will be
+    pub fn try_acquire(&mut self, priority: bool, now: Instant) -> Result<(), Duration> {
+        while let Some(&sent_at) = self.sent.front() {
+            if now.saturating_duration_since(sent_at) < self.period {
+                break;
+            }
+
+            self.sent.pop_front();
+        }
+
+        let limit = if priority {
+            self.capacity
+        } else {
+            self.capacity - self.reserved
+        };
+
+        if self.sent.len() < limit {
+            self.sent.push_back(now);
+            Ok(())
+        } else {
+            // Enough payloads have to leave the window to bring it under the limit
+            let sent_at = self.sent[self.sent.len() - limit];
+            Err((sent_at + self.period).saturating_duration_since(now))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reserves_priority_tokens() {
+        let now = Instant::now();
+        let mut ratelimiter = Ratelimiter::new(10, Duration::from_secs(10), 2);
+
+        for _ in 0..8 {
+            assert!(ratelimiter.try_acquire(false, now).is_ok());
+        }
+
+        // Only the reserved slots are left
+        assert_eq!(
+            ratelimiter.try_acquire(false, now),
+            Err(Duration::from_secs(10))
+        );
+        assert!(ratelimiter.try_acquire(true, now).is_ok());
+        assert!(ratelimiter.try_acquire(true, now).is_ok());
+        assert!(ratelimiter.try_acquire(true, now).is_err());
+
+        // Slots are freed once the payloads leave the window
+        let later = now + Duration::from_secs(10);
+        for _ in 0..8 {
+            assert!(ratelimiter.try_acquire(false, later).is_ok());
+        }
+        assert!(ratelimiter.try_acquire(false, later).is_err());
+    }
+
+    #[test]
+    fn test_never_exceeds_limit_in_any_window() {
+        let period = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut ratelimiter = Ratelimiter::new(120, period, 4);
+
+        // Send as much as possible every 100ms for five minutes
+        let mut sent = Vec::new();
+        for step in 0..3000u32 {
+            let now = start + Duration::from_millis(100) * step;
+            let priority = step % 7 == 0;
+
+            while ratelimiter.try_acquire(priority, now).is_ok() {
+                sent.push(now);
+            }
+        }
+
+        assert!(sent.len() >= 500);
+
+        for (i, &window_start) in sent.iter().enumerate() {
+            let in_window = sent[i..]
+                .iter()
+                .take_while(|&&sent_at| sent_at < window_start + period)
+                .count();
+
+            assert!(
+                in_window <= 120,
+                "{} sends from {:?}",
+                in_window,
+                window_start
+            );
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use super::{SessionData, SessionStore};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, warn};
+
+enum Checkpoint {
+    Save(u64, SessionData),
+    Invalidate(u64),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Writes the sessions of every shard run by a manager to the session store, so that shards can
+/// resume after the sharder crashes. Checkpoints are buffered, and only the latest checkpoint of
+/// each shard is written, in a single `set_bulk` call per interval.
+pub struct SessionCheckpointer {
+    tx: mpsc::UnboundedSender<Checkpoint>,
+}
+
+impl SessionCheckpointer {
+    pub fn start<S: SessionStore>(store: Arc<S>, flush_interval: Duration) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(run(store, flush_interval, rx));
+
+        Self { tx }
+    }
+
+    /// Returns the sender for a shard, keyed by the same ID as its session data
+    pub fn sender(&self, id: u64) -> CheckpointSender {
+        CheckpointSender {
+            id,
+            tx: self.tx.clone(),
+        }
+    }
+
+    /// Writes every buffered checkpoint, waiting until the store has been updated
+    pub async fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+
+        if self.tx.send(Checkpoint::Flush(tx)).is_ok() {
+            _ = rx.await;
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CheckpointSender {
+    id: u64,
+    tx: mpsc::UnboundedSender<Checkpoint>,
+}
+
+impl CheckpointSender {
+    pub fn save(&self, session_data: SessionData) {
+        _ = self.tx.send(Checkpoint::Save(self.id, session_data));
+    }
+
+    /// Removes the stored session, so that it isn't resumed after a restart
+    pub fn invalidate(&self) {
+        _ = self.tx.send(Checkpoint::Invalidate(self.id));
+    }
+}
+
+async fn run<S: SessionStore>(
+    store: Arc<S>,
+    flush_interval: Duration,
+    mut rx: mpsc::UnboundedReceiver<Checkpoint>,
+) {
+    // None if the session has been invalidated
+    let mut pending: HashMap<u64, Option<SessionData>> = HashMap::new();
+
+    let mut ticker = interval(flush_interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            checkpoint = rx.recv() => match checkpoint {
+                Some(Checkpoint::Save(id, session_data)) => {
+                    pending.insert(id, Some(session_data));
+                }
+                Some(Checkpoint::Invalidate(id)) => {
+                    pending.insert(id, None);
+                }
+                Some(Checkpoint::Flush(done)) => {
+                    write(store.as_ref(), &mut pending).await;
+                    _ = done.send(());
+                }
+                None => {
+                    write(store.as_ref(), &mut pending).await;
+                    break;
+                }
+            },
+
+            _ = ticker.tick() => write(store.as_ref(), &mut pending).await,
+        }
+    }
+}
+
+// Failed writes are not retried, as shards checkpoint again on their next heartbeat
+async fn write<S: SessionStore>(store: &S, pending: &mut HashMap<u64, Option<SessionData>>) {
+    if pending.is_empty() {
+        return;
+    }
+
+    let mut sessions = HashMap::new();
+    let mut invalidated = Vec::new();
+    for (id, session_data) in pending.drain() {
+        match session_data {
+            Some(session_data) => {
+                sessions.insert(id, session_data);
+            }
+            None => invalidated.push(id),
+        }
+    }
+
+    debug!(
+        saved = sessions.len(),
+        invalidated = invalidated.len(),
+        "Writing session checkpoints"
+    );
+
+    if let Err(e) = store.set_bulk(sessions).await {
+        warn!(error = %e, "Failed to checkpoint session data");
+    }
+
+    if !invalidated.is_empty() {
+        if let Err(e) = store.invalidate_bulk(&invalidated).await {
+            warn!(error = %e, "Failed to invalidate checkpointed session data");
+        }
+    }
+}
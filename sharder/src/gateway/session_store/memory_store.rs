@@ -0,0 +1,58 @@
+use super::{SessionData, SessionStore};
+use crate::Result;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+
+/// Keeps sessions in memory, so they are lost when the sharder exits. For tests, and running a
+/// single sharder locally without Redis.
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: Mutex<HashMap<u64, SessionData>>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn get(&self, shard_id: u64) -> Result<Option<SessionData>> {
+        Ok(self.sessions.lock().get(&shard_id).cloned())
+    }
+
+    async fn get_bulk(&self, shard_ids: &[u64]) -> Result<HashMap<u64, SessionData>> {
+        let sessions = self.sessions.lock();
+
+        Ok(shard_ids
+            .iter()
+            .filter_map(|shard_id| Some((*shard_id, sessions.get(shard_id)?.clone())))
+            .collect())
+    }
+
+    async fn set(&self, shard_id: u64, info: SessionData) -> Result<()> {
+        self.sessions.lock().insert(shard_id, info);
+        Ok(())
+    }
+
+    async fn set_bulk(&self, data: HashMap<u64, SessionData>) -> Result<()> {
+        self.sessions.lock().extend(data);
+        Ok(())
+    }
+
+    async fn invalidate(&self, shard_id: u64) -> Result<()> {
+        self.sessions.lock().remove(&shard_id);
+        Ok(())
+    }
+
+    async fn invalidate_bulk(&self, shard_ids: &[u64]) -> Result<()> {
+        let mut sessions = self.sessions.lock();
+        for shard_id in shard_ids {
+            sessions.remove(shard_id);
+        }
+
+        Ok(())
+    }
+}
@@ -2,6 +2,7 @@ use crate::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 #[async_trait]
 pub trait SessionStore: Send + Sync + 'static {
@@ -13,6 +14,70 @@ pub trait SessionStore: Send + Sync + 'static {
     async fn invalidate_bulk(&self, shard_ids: &[u64]) -> Result<()>;
 }
 
+#[async_trait]
+impl<S: SessionStore + ?Sized> SessionStore for Box<S> {
+    async fn get(&self, shard_id: u64) -> Result<Option<SessionData>> {
+        (**self).get(shard_id).await
+    }
+
+    async fn get_bulk(&self, shard_ids: &[u64]) -> Result<HashMap<u64, SessionData>> {
+        (**self).get_bulk(shard_ids).await
+    }
+
+    async fn set(&self, shard_id: u64, info: SessionData) -> Result<()> {
+        (**self).set(shard_id, info).await
+    }
+
+    async fn set_bulk(&self, data: HashMap<u64, SessionData>) -> Result<()> {
+        (**self).set_bulk(data).await
+    }
+
+    async fn invalidate(&self, shard_id: u64) -> Result<()> {
+        (**self).invalidate(shard_id).await
+    }
+
+    async fn invalidate_bulk(&self, shard_ids: &[u64]) -> Result<()> {
+        (**self).invalidate_bulk(shard_ids).await
+    }
+}
+
+#[async_trait]
+impl<S: SessionStore + ?Sized> SessionStore for Arc<S> {
+    async fn get(&self, shard_id: u64) -> Result<Option<SessionData>> {
+        (**self).get(shard_id).await
+    }
+
+    async fn get_bulk(&self, shard_ids: &[u64]) -> Result<HashMap<u64, SessionData>> {
+        (**self).get_bulk(shard_ids).await
+    }
+
+    async fn set(&self, shard_id: u64, info: SessionData) -> Result<()> {
+        (**self).set(shard_id, info).await
+    }
+
+    async fn set_bulk(&self, data: HashMap<u64, SessionData>) -> Result<()> {
+        (**self).set_bulk(data).await
+    }
+
+    async fn invalidate(&self, shard_id: u64) -> Result<()> {
+        (**self).invalidate(shard_id).await
+    }
+
+    async fn invalidate_bulk(&self, shard_ids: &[u64]) -> Result<()> {
+        (**self).invalidate_bulk(shard_ids).await
+    }
+}
+
+/// The backend used to store sessions, chosen with SESSION_STORE
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStoreKind {
+    #[default]
+    Redis,
+    Memory,
+    Postgres,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SessionData {
     pub seq: usize,
@@ -22,3 +87,12 @@ pub struct SessionData {
 
 mod redis_store;
 pub use redis_store::RedisSessionStore;
+
+mod memory_store;
+pub use memory_store::MemorySessionStore;
+
+mod postgres_store;
+pub use postgres_store::PostgresSessionStore;
+
+mod checkpoint;
+pub use checkpoint::{CheckpointSender, SessionCheckpointer};
@@ -0,0 +1,113 @@
+use super::{SessionData, SessionStore};
+use crate::Result;
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use database::{Database, ShardSession};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub struct PostgresSessionStore {
+    database: Arc<Database>,
+    namespace: Box<str>,
+    expiry_seconds: i64,
+}
+
+impl PostgresSessionStore {
+    pub fn new(
+        database: Arc<Database>,
+        namespace: impl Into<Box<str>>,
+        expiry_seconds: i64,
+    ) -> Self {
+        Self {
+            database,
+            namespace: namespace.into(),
+            expiry_seconds,
+        }
+    }
+
+    fn updated_after(&self) -> chrono::DateTime<Utc> {
+        Utc::now() - Duration::seconds(self.expiry_seconds)
+    }
+}
+
+impl From<ShardSession> for SessionData {
+    fn from(session: ShardSession) -> Self {
+        SessionData {
+            seq: session.seq as usize,
+            session_id: session.session_id,
+            resume_url: session.resume_url,
+        }
+    }
+}
+
+impl From<SessionData> for ShardSession {
+    fn from(info: SessionData) -> Self {
+        ShardSession {
+            session_id: info.session_id,
+            seq: info.seq as u64,
+            resume_url: info.resume_url,
+        }
+    }
+}
+
+#[async_trait]
+impl SessionStore for PostgresSessionStore {
+    async fn get(&self, shard_id: u64) -> Result<Option<SessionData>> {
+        let session = self
+            .database
+            .shard_sessions
+            .get(&self.namespace, shard_id, self.updated_after())
+            .await?;
+
+        Ok(session.map(SessionData::from))
+    }
+
+    async fn get_bulk(&self, shard_ids: &[u64]) -> Result<HashMap<u64, SessionData>> {
+        let sessions = self
+            .database
+            .shard_sessions
+            .get_bulk(&self.namespace, shard_ids, self.updated_after())
+            .await?;
+
+        Ok(sessions
+            .into_iter()
+            .map(|(shard_id, session)| (shard_id, session.into()))
+            .collect())
+    }
+
+    async fn set(&self, shard_id: u64, info: SessionData) -> Result<()> {
+        self.database
+            .shard_sessions
+            .set_bulk(&self.namespace, vec![(shard_id, info.into())])
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_bulk(&self, data: HashMap<u64, SessionData>) -> Result<()> {
+        let sessions = data
+            .into_iter()
+            .map(|(shard_id, info)| (shard_id, info.into()))
+            .collect();
+
+        self.database
+            .shard_sessions
+            .set_bulk(&self.namespace, sessions)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn invalidate(&self, shard_id: u64) -> Result<()> {
+        self.invalidate_bulk(&[shard_id]).await
+    }
+
+    async fn invalidate_bulk(&self, shard_ids: &[u64]) -> Result<()> {
+        self.database
+            .shard_sessions
+            .delete_bulk(&self.namespace, shard_ids)
+            .await?;
+
+        Ok(())
+    }
+}
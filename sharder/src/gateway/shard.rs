@@ -1,3 +1,4 @@
+use std::collections::{HashMap, VecDeque};
 #[cfg(feature = "use-sentry")]
 use std::default::Default;
 use std::str;
@@ -7,11 +8,8 @@ use std::time::Instant;
 
 #[cfg(feature = "whitelabel")]
 use database::Database;
-use deadpool_redis::redis;
-use deadpool_redis::{redis::cmd, Pool};
-#[cfg(feature = "compression")]
-use flate2::{Decompress, FlushDecompress, Status};
-use futures::future;
+#[cfg(feature = "whitelabel")]
+use deadpool_redis::Pool;
 use futures::StreamExt;
 use futures_util::SinkExt;
 use parking_lot::Mutex;
@@ -28,23 +26,31 @@ use model::Snowflake;
 use crate::config::Config;
 use crate::gateway::whitelabel_utils::is_whitelabel;
 use crate::gateway::{GatewayError, Result};
-#[cfg(feature = "whitelabel")]
-use crate::payloads::PresenceUpdate;
+use crate::payloads::{PresenceUpdate, RequestGuildMembers};
+use crate::GuildMembers;
 use crate::InternalCommand;
 use crate::ShardIdentifier;
 
+#[cfg(feature = "transport-compression")]
+use super::compression::TransportDecoder;
+use super::member_request::PendingMemberRequest;
+use super::metrics::ShardMetrics;
 use super::payloads;
 use super::payloads::event::Event;
+use super::payloads::event::GuildMembersChunk;
 use super::payloads::parser::find_opcode;
 use super::payloads::parser::find_seq;
 use super::payloads::{Dispatch, Opcode};
-use super::session_store::SessionData;
+use super::session_store::{CheckpointSender, SessionData};
+use super::shard_status::{ShardState, ShardStatusHandle};
 use super::timer;
+use super::IdentifyRatelimiter;
 use super::OutboundMessage;
-use crate::gateway::event_forwarding::{is_whitelisted, EventForwarder};
+use super::Ratelimiter;
+use super::{etf, Encoding};
+use crate::gateway::event_forwarding::{EventFilters, EventForwarder, FilterAction, Route};
 use crate::CloseEvent;
 use futures_util::stream::{SplitSink, SplitStream};
-use redis::AsyncCommands;
 use tokio::net::TcpStream;
 use tokio_tungstenite::{
     connect_async,
@@ -58,24 +64,34 @@ use tracing::{debug, error, info, warn};
 use lazy_static::lazy_static;
 
 #[cfg(feature = "metrics")]
-use prometheus::{register_int_gauge, IntGauge};
+use prometheus::{register_int_gauge_vec, IntGaugeVec};
 
 #[cfg(feature = "metrics")]
 lazy_static! {
-    static ref CACHE_BUFFER_GUAGE: IntGauge = register_int_gauge!(
-        "cache_buffer",
-        "The number of payload that are currently waiting to be stored in the cache"
+    static ref WRITE_QUEUE_GAUGE: IntGaugeVec = register_int_gauge_vec!(
+        "gateway_write_queue",
+        "The number of payloads waiting for gateway ratelimit capacity before being written",
+        &["bot_id", "shard_id"]
     )
-    .expect("Failed to create cache buffer gauge");
+    .expect("Failed to create gateway write queue gauge");
 }
 
 const GATEWAY_VERSION: u8 = 10;
 
+// Discord closes the connection with 4008 if more than 120 payloads are sent in 60 seconds
+const GATEWAY_SEND_LIMIT: u32 = 120;
+const GATEWAY_SEND_PERIOD: Duration = Duration::from_secs(60);
+// Sends kept back for heartbeats, IDENTIFY and RESUME
+const GATEWAY_SEND_RESERVED: u32 = 4;
+// Commands queued beyond this are rejected
+const MAX_QUEUED_WRITES: usize = 256;
+
 pub struct Shard<T: EventForwarder> {
     pub(crate) config: Arc<Config>,
     pub(crate) identify: payloads::Identify,
+    token_key: String,
     large_sharding_buckets: u16,
-    redis: Arc<Pool>,
+    identify_ratelimiter: Arc<IdentifyRatelimiter>,
     pub(crate) user_id: Snowflake,
     pub(crate) session_data: Option<SessionData>,
     writer: mpsc::Sender<OutboundMessage>,
@@ -94,17 +110,20 @@ pub struct Shard<T: EventForwarder> {
     is_ready: bool,
     #[cfg(feature = "resume-after-identify")]
     used_resume: bool,
+    pending_member_requests: HashMap<String, PendingMemberRequest>,
     shutdown_rx: broadcast::Receiver<mpsc::Sender<(ShardIdentifier, Option<SessionData>)>>,
-    #[cfg(feature = "whitelabel")]
     command_rx: Arc<TokioMutex<mpsc::Receiver<InternalCommand>>>,
+    status: ShardStatusHandle,
+    checkpoint: CheckpointSender,
+    metrics: ShardMetrics,
+    #[cfg(feature = "whitelabel")]
+    pub(crate) redis: Arc<Pool>,
     #[cfg(feature = "whitelabel")]
     pub(crate) database: Arc<Database>,
     pub(crate) event_forwarder: Arc<T>,
+    event_filters: Arc<EventFilters>,
 }
 
-#[cfg(feature = "compression")]
-const CHUNK_SIZE: usize = 16 * 1024; // 16KiB
-
 static DEFAULT_GATEWAY_URL: &str = "wss://gateway.discord.gg";
 
 impl<T: EventForwarder> Shard<T> {
@@ -112,23 +131,30 @@ impl<T: EventForwarder> Shard<T> {
         config: Arc<Config>,
         identify: payloads::Identify,
         large_sharding_buckets: u16,
-        redis: Arc<Pool>,
+        identify_ratelimiter: Arc<IdentifyRatelimiter>,
         user_id: Snowflake,
         event_forwarder: Arc<T>,
+        event_filters: Arc<EventFilters>,
         ready_tx: Option<oneshot::Sender<()>>,
         shutdown_rx: broadcast::Receiver<mpsc::Sender<(ShardIdentifier, Option<SessionData>)>>,
+        command_rx: mpsc::Receiver<InternalCommand>,
+        status: ShardStatusHandle,
+        checkpoint: CheckpointSender,
+        #[cfg(feature = "whitelabel")] redis: Arc<Pool>,
         #[cfg(feature = "whitelabel")] database: Arc<Database>,
-        #[cfg(feature = "whitelabel")] command_rx: mpsc::Receiver<InternalCommand>,
     ) -> Shard<T> {
         let (kill_shard_tx, kill_shard_rx) = oneshot::channel();
         let (writer_tx, writer_rx) = mpsc::channel(4);
         let (heartbeat_tx, heartbeat_rx) = mpsc::channel(1);
+        let metrics = ShardMetrics::new(user_id, identify.data.shard_info.shard_id);
+        let token_key = event_forwarding::token_key(&identify.data.token);
 
         Shard {
             config,
             identify,
+            token_key,
             large_sharding_buckets,
-            redis,
+            identify_ratelimiter,
             user_id,
             session_data: None,
             writer: writer_tx,
@@ -147,12 +173,18 @@ impl<T: EventForwarder> Shard<T> {
             is_ready: false,
             #[cfg(feature = "resume-after-identify")]
             used_resume: false,
+            pending_member_requests: HashMap::new(),
             shutdown_rx,
-            #[cfg(feature = "whitelabel")]
             command_rx: Arc::new(TokioMutex::new(command_rx)),
+            status,
+            checkpoint,
+            metrics,
+            #[cfg(feature = "whitelabel")]
+            redis,
             #[cfg(feature = "whitelabel")]
             database,
             event_forwarder,
+            event_filters,
         }
     }
 
@@ -163,26 +195,33 @@ impl<T: EventForwarder> Shard<T> {
     ) -> Result<Option<SessionData>> {
         // TODO: Move to constructor
         self.session_data = resume_data;
+        self.status.set_state(ShardState::Connecting);
 
         // Build gateway URL
+        let default_url = self
+            .config
+            .gateway_url
+            .clone()
+            .unwrap_or_else(|| DEFAULT_GATEWAY_URL.to_string());
+
         let gateway_url = self
             .session_data
             .as_ref()
             .and_then(|s| s.resume_url.clone())
-            .unwrap_or_else(|| DEFAULT_GATEWAY_URL.to_string());
+            .unwrap_or_else(|| default_url.clone());
 
-        let mut uri = format!("{gateway_url}?v={GATEWAY_VERSION}&encoding=json");
-        if cfg!(feature = "compression") {
-            uri.push_str("&compress=zlib-stream");
-        }
+        let encoding = self.config.gateway_encoding.as_str();
+        let uri = format!("{gateway_url}?v={GATEWAY_VERSION}&encoding={encoding}");
+
+        #[cfg(feature = "transport-compression")]
+        let uri = format!("{uri}&compress={}", TransportDecoder::COMPRESS);
 
         let uri = match Url::parse(&uri[..]) {
             Ok(uri) => uri,
             Err(e) => {
                 error!(error = %e, uri = %uri, "Error parsing gateway URI");
 
-                let fallback_url =
-                    format!("{DEFAULT_GATEWAY_URL}?v={GATEWAY_VERSION}&encoding=json");
+                let fallback_url = format!("{default_url}?v={GATEWAY_VERSION}&encoding={encoding}");
                 Url::parse(fallback_url.as_str()).expect("Error parsing fallback gateway URI")
             }
         };
@@ -207,21 +246,57 @@ impl<T: EventForwarder> Shard<T> {
         tokio::spawn(handle_writes(
             ws_tx,
             self.writer_rx.take().expect("writer_rx is None"),
+            self.user_id,
+            self.get_shard_id(),
         ));
 
         // start read loop
-        self.listen(ws_rx).await?;
+        let res = self.listen(ws_rx).await;
+        self.status.set_state(ShardState::Disconnected);
+
+        // Chunks for requests made on this connection won't be received on the next
+        self.fail_member_requests("shard disconnected before every chunk was received");
+        res?;
 
         Ok(self.session_data.take())
     }
 
     // helper function
     async fn write<U: Serialize>(&self, msg: U, tx: oneshot::Sender<Result<()>>) -> Result<()> {
-        let message = OutboundMessage::new(msg, tx)?;
+        let message = OutboundMessage::new(msg, false, self.config.gateway_encoding, tx)?;
+        self.writer.send(message).await?;
+        Ok(())
+    }
+
+    // helper function, for heartbeats, IDENTIFY and RESUME
+    async fn write_priority<U: Serialize>(
+        &self,
+        msg: U,
+        tx: oneshot::Sender<Result<()>>,
+    ) -> Result<()> {
+        let message = OutboundMessage::new(msg, true, self.config.gateway_encoding, tx)?;
         self.writer.send(message).await?;
         Ok(())
     }
 
+    /// Queues a payload without waiting for it to be written, as it may be held back by the
+    /// ratelimiter. Errors writing the payload are logged.
+    async fn write_detached<U: Serialize>(&self, msg: U, name: &'static str) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.write(msg, tx).await?;
+
+        let shard_id = self.get_shard_id();
+        tokio::spawn(async move {
+            match rx.await {
+                Ok(Err(e)) => error!(%shard_id, error = %e, "Error writing {name} payload"),
+                Err(e) => error!(%shard_id, error = %e, "Error writing {name} payload"),
+                _ => {}
+            }
+        });
+
+        Ok(())
+    }
+
     // helper function
     pub fn kill(&self) {
         let kill_shard_tx = self.kill_shard_tx.lock().take();
@@ -244,15 +319,14 @@ impl<T: EventForwarder> Shard<T> {
         &mut self,
         mut rx: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
     ) -> Result<()> {
-        #[cfg(feature = "compression")]
-        let mut decoder = Decompress::new(true);
+        // The inflate context is shared by every message on this connection
+        #[cfg(feature = "transport-compression")]
+        let mut decoder = TransportDecoder::new();
 
         let kill_shard_rx = Arc::clone(&self.kill_shard_rx);
         let kill_shard_rx = &mut *kill_shard_rx.lock().await;
 
-        #[cfg(feature = "whitelabel")]
         let command_rx = Arc::clone(&self.command_rx);
-        #[cfg(feature = "whitelabel")]
         let command_rx = &mut *command_rx.lock().await;
 
         let heartbeat_rx = &mut self
@@ -263,12 +337,6 @@ impl<T: EventForwarder> Shard<T> {
 
         debug!("Starting read loop");
         loop {
-            #[cfg(feature = "whitelabel")]
-            let command_rx = async { command_rx.recv().await };
-
-            #[cfg(not(feature = "whitelabel"))]
-            let command_rx = future::pending::<InternalCommand>();
-
             tokio::select! {
                 // handle kill
                 _ = &mut *kill_shard_rx => {
@@ -300,6 +368,7 @@ impl<T: EventForwarder> Shard<T> {
 
                     if has_done_heartbeat && (elapsed.is_none() || elapsed.unwrap() > self.heartbeat_interval) {
                         error!("Haven't received heartbeat ack, killing");
+                        self.metrics.reconnecting("heartbeat_timeout");
                         if let Some(tx) = self.kill_shard_tx.lock().take() {
                             if let Err(e) = tx.send(()) {
                                 error!(error = ?e, "Error sending kill notification to shard");
@@ -311,11 +380,14 @@ impl<T: EventForwarder> Shard<T> {
 
                     if let Err(e) = self.do_heartbeat().await { // TODO: Don't block
                         error!(error = %e, "Error sending heartbeat");
+                        self.metrics.reconnecting("heartbeat_error");
                         self.kill();
                         break;
                     }
 
                     has_done_heartbeat = true;
+                    self.checkpoint_session();
+                    self.expire_member_requests();
                 }
 
                 // handle incoming payload
@@ -323,18 +395,21 @@ impl<T: EventForwarder> Shard<T> {
                     match payload {
                         None => {
                             warn!("Received None from websocket, killing");
+                            self.metrics.reconnecting("connection_lost");
                             self.kill();
                             break;
                         }
 
                         Some(Err(e)) => {
                             warn!(error = %e, "Error reading data from websocket, killing");
+                            self.metrics.reconnecting("connection_lost");
                             self.kill();
                             break;
                         }
 
                         Some(Ok(Message::Close(frame))) => {
                             info!(?frame, "Got close from gateway");
+                            self.metrics.reconnecting("closed");
                             self.kill();
 
                             if let Some(frame) = frame {
@@ -342,6 +417,7 @@ impl<T: EventForwarder> Shard<T> {
                                     // On code 1000, the session is invalidated
                                     CloseCode::Normal => {
                                         self.session_data = None;
+                                        self.checkpoint.invalidate();
                                     },
 
                                     CloseCode::Library(code) => {
@@ -368,20 +444,31 @@ impl<T: EventForwarder> Shard<T> {
                             self.process_payload(data).await
                         }
 
-                        #[cfg(feature = "compression")]
                         Some(Ok(Message::Binary(data))) => {
-                            let data = match self.decompress(data, &mut decoder).await {
-                                Ok(data) => data,
+                            #[cfg(feature = "transport-compression")]
+                            let data = match decoder.push(&data) {
+                                Ok(Some(data)) => data,
+
+                                // Wait for the rest of the message
+                                Ok(None) => continue,
+
+                                // The stream can't be recovered, so a new connection is needed
                                 Err(e) => {
-                                    error!(error = %e, "Error decompressing payload");
-                                    continue;
+                                    error!(error = %e, "Error decompressing payload, killing");
+                                    self.metrics.reconnecting("decompress_error");
+                                    decoder.reset();
+                                    self.kill();
+                                    break;
                                 }
                             };
 
-                            let value: Value = serde_json::from_slice(data.as_bytes())?;
+                            match self.decode_payload(data) {
+                                Ok(data) => {
+                                    trace!(data = %data.as_str(), "Received payload");
 
-                            if let Err(e) = Arc::clone(&self).process_payload(value, data.as_bytes()).await {
-                                self.log_err("An error occurred while processing a payload", &e);
+                                    self.process_payload(data).await
+                                }
+                                Err(e) => error!(error = %e, "Error decoding payload"),
                             }
                         }
 
@@ -390,33 +477,38 @@ impl<T: EventForwarder> Shard<T> {
                 }
 
                 // handle internal commands
-                command = command_rx => {
-                    #[cfg(feature = "whitelabel")]
-                    {
-                        let Some(command) = command else {continue};
+                command = command_rx.recv() => {
+                    let Some(command) = command else {continue};
 
-                        match command {
-                            InternalCommand::StatusUpdate { status } => {
-                                let payload = PresenceUpdate::new(status);
+                    match command {
+                        InternalCommand::StatusUpdate { status } => {
+                            let payload = PresenceUpdate::new(status);
 
-                                let (tx, rx) = oneshot::channel();
-
-                                if let Err(e) = self.write(payload, tx).await {
-                                    error!(error = %e, "Error writing presence update payload");
-                                    continue;
-                                }
-
-                                match rx.await {
-                                    Ok(Err(e)) => error!(shard_id = %self.get_shard_id(), error = %e, "Error writing presence update payload"),
-                                    Err(e) => error!(shard_id = %self.get_shard_id(), error = %e, "Error writing presence update payload"),
-                                    _ => {}
-                                }
+                            if let Err(e) = self.write_detached(payload, "presence update").await {
+                                error!(error = %e, "Error writing presence update payload");
                             }
-                            InternalCommand::Shutdown => {
-                                info!("Received shutdown command (via internal command)");
-                                break;
+                        }
+                        InternalCommand::RequestGuildMembers { guild_id, query, user_ids, nonce, reply } => {
+                            if let Err(e) = self.request_guild_members(guild_id, query, user_ids, nonce, reply).await {
+                                error!(error = %e, %guild_id, "Error requesting guild members");
                             }
                         }
+                        InternalCommand::Reconnect { resume } => {
+                            info!(resume, "Received reconnect command (via internal command)");
+                            self.metrics.reconnecting("command");
+
+                            if !resume {
+                                self.session_data = None;
+                                self.status.update(|status| status.session_id = None);
+                                self.checkpoint.invalidate();
+                            }
+
+                            break;
+                        }
+                        InternalCommand::Shutdown => {
+                            info!("Received shutdown command (via internal command)");
+                            break;
+                        }
                     }
                 }
             }
@@ -425,44 +517,15 @@ impl<T: EventForwarder> Shard<T> {
         Ok(())
     }
 
-    #[cfg(feature = "compression")]
-    async fn decompress(
-        self: Arc<Self>,
-        data: Vec<u8>,
-        decoder: &mut Decompress,
-    ) -> Result<Vec<u8>> {
-        let mut total_rx = self.total_rx.lock();
-
-        let mut output: Vec<u8> = Vec::with_capacity(CHUNK_SIZE);
-        let before = total_rx.clone();
-        let mut offset: usize = 0;
-
-        while ((decoder.total_in() - *total_rx) as usize) < data.len() {
-            let mut temp: Vec<u8> = Vec::with_capacity(CHUNK_SIZE);
-
-            match decoder
-                .decompress_vec(&data[offset..], &mut temp, FlushDecompress::Sync)
-                .map_err(GatewayError::DecompressError)
-            {
-                Ok(Status::StreamEnd) => break,
-                Ok(Status::Ok) | Ok(Status::BufError) => {
-                    output.append(&mut temp);
-                    offset = (decoder.total_in() - before) as usize;
-                }
-
-                // TODO: Should we reconnect?
-                Err(e) => {
-                    *total_rx = 0;
-                    decoder.reset(true);
-
-                    return Err(e);
-                }
-            };
-        }
-
-        *total_rx = decoder.total_in();
+    /// Converts a binary payload to JSON, which is what the gateway sends unless ETF is requested.
+    /// The error is boxed to keep the result small, as this is called for every payload.
+    fn decode_payload(&self, data: Vec<u8>) -> Result<String, Box<GatewayError>> {
+        let decoded = match self.config.gateway_encoding {
+            Encoding::Json => String::from_utf8(data).map_err(|e| e.utf8_error().into()),
+            Encoding::Etf => etf::to_json(&data).map_err(GatewayError::from),
+        };
 
-        return Ok(output);
+        decoded.map_err(Box::new)
     }
 
     #[tracing::instrument(skip(self, raw))]
@@ -479,6 +542,8 @@ impl<T: EventForwarder> Shard<T> {
             if let Some(ref mut session_data) = &mut self.session_data {
                 session_data.seq = seq; // TODO: Verify this works
             }
+
+            self.status.update(|status| status.seq = Some(seq));
         }
 
         match opcode {
@@ -498,6 +563,7 @@ impl<T: EventForwarder> Shard<T> {
 
             Opcode::Reconnect => {
                 info!("Received reconnect payload from Discord");
+                self.metrics.reconnecting("reconnect_requested");
                 self.kill();
             }
 
@@ -505,6 +571,9 @@ impl<T: EventForwarder> Shard<T> {
                 info!("Received invalid session payload from Discord");
 
                 self.session_data = None;
+                self.status.update(|status| status.session_id = None);
+                self.checkpoint.invalidate();
+                self.metrics.reconnecting("invalid_session");
                 self.kill();
             }
 
@@ -530,16 +599,23 @@ impl<T: EventForwarder> Shard<T> {
 
                 if let Some(resume_info) = resume_info {
                     info!(resume_url = ?resume_info.resume_url, seq = %resume_info.seq, session_id = %resume_info.session_id, "Attempting resume");
+                    self.status.update(|status| {
+                        status.state = ShardState::Resuming;
+                        status.session_id = Some(resume_info.session_id.clone());
+                    });
 
                     if let Err(e) = self
                         .do_resume(resume_info.session_id, resume_info.seq)
                         .await
                     {
                         error!(error = %e, "Error sending RESUME payload, killing");
+                        self.metrics.reconnecting("handshake_error");
                         self.kill();
                         return;
                     }
 
+                    self.metrics.resumed();
+
                     #[cfg(feature = "resume-after-identify")]
                     {
                         self.used_resume = true;
@@ -552,6 +628,7 @@ impl<T: EventForwarder> Shard<T> {
                             time_since_connect = self.connect_time.elapsed().as_millis(),
                             "Connected over 45s ago, Discord will kick us off. Reconnecting."
                         );
+                        self.metrics.reconnecting("handshake_error");
                         self.kill();
                         return;
                     }
@@ -560,18 +637,22 @@ impl<T: EventForwarder> Shard<T> {
                         debug!("Connected over 500ms ago, waiting for ratelimit");
                         if let Err(e) = self.wait_for_ratelimit().await {
                             error!(error = %e, "Error waiting for ratelimit, reconnecting");
+                            self.metrics.reconnecting("handshake_error");
                             self.kill();
                             return;
                         }
                     }
 
+                    self.status.set_state(ShardState::Identifying);
                     if let Err(e) = self.do_identify().await {
                         error!(error = %e, "Error sending IDENTIFY payload, killing");
+                        self.metrics.reconnecting("handshake_error");
                         self.kill();
                         return;
                     }
 
                     info!("Identified");
+                    self.metrics.identified();
 
                     if let Err(e) = self.update_ratelimit_after_identify().await {
                         error!(error = %e, "Error setting identify ratelimit value");
@@ -584,6 +665,12 @@ impl<T: EventForwarder> Shard<T> {
             Opcode::HeartbeatAck => {
                 trace!("Received heartbeat ack");
                 self.last_ack = Instant::now();
+
+                let latency = self.last_ack.saturating_duration_since(self.last_heartbeat);
+                self.metrics.heartbeat_acked(latency);
+                self.status.update(|status| {
+                    status.heartbeat_latency_ms = Some(latency.as_millis() as u64)
+                });
             }
 
             _ => {}
@@ -592,6 +679,8 @@ impl<T: EventForwarder> Shard<T> {
 
     #[tracing::instrument(skip(self, payload, data), fields(event_type = %payload.data))]
     async fn handle_event(&mut self, payload: Dispatch, data: String) -> Result<()> {
+        self.metrics.event_received(payload.data.name());
+
         // Gateway events
         match &payload.data {
             Event::Ready(ready) => {
@@ -603,17 +692,35 @@ impl<T: EventForwarder> Shard<T> {
 
                 self.ready_guild_count = ready.guilds.len() as u16;
 
+                self.status.update(|status| {
+                    status.state = ShardState::Ready;
+                    status.session_id = Some(ready.session_id.clone());
+                    status.guild_count = ready.guilds.len();
+                });
+                self.checkpoint_session();
+
                 info!(
                     guild_count = ready.guilds.len(),
                     username = ready.user.username,
                     "Got READY event"
                 );
 
+                // No GUILD_CREATEs will follow, so the shard is ready straight away
+                if ready.guilds.is_empty() && !self.is_ready {
+                    self.is_ready = true;
+
+                    if let Some(tx) = self.ready_tx.lock().take() {
+                        _ = tx.send(());
+                    }
+                }
+
                 return Ok(());
             }
 
             Event::Resumed(_) => {
                 info!("Received RESUME acknowledgement");
+                self.status.set_state(ShardState::Ready);
+                self.checkpoint_session();
 
                 if !self.is_ready {
                     self.is_ready = true;
@@ -637,41 +744,189 @@ impl<T: EventForwarder> Shard<T> {
                 }
             }
 
+            // Unavailable guilds are in an outage, and the bot is still in them
+            #[cfg(feature = "whitelabel")]
+            Event::GuildDelete(g) if g.unavailable != Some(true) => {
+                if let Err(e) = self.remove_whitelabel_guild(g.id).await {
+                    error!(error = %e, "Error removing whitelabel guild data");
+                }
+            }
+
             _ => {}
         }
 
-        if is_whitelisted(&payload.data) {
+        let route = self.config.forwarding_rules.route(&payload.data);
+        if route != Route::Drop {
             let guild_id: Option<Snowflake> = super::event_forwarding::get_guild_id(&payload.data);
 
-            let raw_payload = match RawValue::from_string(data) {
-                Ok(v) => v,
-                Err(e) => {
-                    error!(error = %e, "Error convering JSON string to RawValue");
-                    return Err(e.into());
-                }
-            };
+            let action = self.event_filters.apply(&payload.data, guild_id);
+            if action != FilterAction::Forward {
+                self.metrics.event_filtered(payload.data.name(), action);
+            }
 
-            // prepare payload
-            let wrapped = event_forwarding::Event {
-                bot_token: self.identify.data.token.clone(),
-                bot_id: self.user_id.0,
-                is_whitelabel: is_whitelabel(),
-                shard_id: self.get_shard_id(),
-                event: raw_payload,
-            };
+            if action != FilterAction::Drop {
+                self.forward_event(data, guild_id, route, action == FilterAction::Tag)
+                    .await?;
+            }
+        }
+
+        if let Event::GuildMembersChunk(chunk) = payload.data {
+            self.handle_members_chunk(chunk);
+        }
+
+        Ok(())
+    }
 
-            if let Err(e) = self
-                .event_forwarder
-                .forward_event(&self.config, wrapped, guild_id)
-                .await
-            {
-                error!(error = %e, "Error while executing worker HTTP request");
+    async fn forward_event(
+        &self,
+        data: String,
+        guild_id: Option<Snowflake>,
+        route: Route,
+        served_by_whitelabel: bool,
+    ) -> Result<()> {
+        let raw_payload = match RawValue::from_string(data) {
+            Ok(v) => v,
+            Err(e) => {
+                error!(error = %e, "Error convering JSON string to RawValue");
+                return Err(e.into());
             }
+        };
+
+        // prepare payload
+        let wrapped = event_forwarding::Event {
+            bot_token: self
+                .config
+                .include_bot_token
+                .then(|| self.identify.data.token.clone()),
+            bot_id: self.user_id.0,
+            token_key: self.token_key.clone(),
+            is_whitelabel: is_whitelabel(),
+            served_by_whitelabel,
+            shard_id: self.get_shard_id(),
+            event: raw_payload,
+        };
+
+        let start = Instant::now();
+        let res = self
+            .event_forwarder
+            .forward_event(&self.config, wrapped, guild_id, route)
+            .await;
+
+        self.metrics
+            .event_forwarded(route, start.elapsed(), res.is_ok());
+
+        if let Err(e) = res {
+            error!(error = %e, ?route, "Error forwarding event");
+        }
+
+        Ok(())
+    }
+
+    /// Writes a REQUEST_GUILD_MEMBERS payload. The reply is sent once all chunks are received,
+    /// or immediately if the request could not be made.
+    #[tracing::instrument(skip(self, query, user_ids, reply))]
+    async fn request_guild_members(
+        &mut self,
+        guild_id: Snowflake,
+        query: Option<String>,
+        user_ids: Option<Vec<Snowflake>>,
+        nonce: String,
+        reply: oneshot::Sender<Result<GuildMembers>>,
+    ) -> Result<()> {
+        let validation_error = if query.is_some() && user_ids.is_some() {
+            Some("query and user_ids are mutually exclusive")
+        } else if user_ids.as_ref().is_some_and(|ids| ids.len() > 100) {
+            Some("at most 100 user_ids may be requested at once")
+        } else if nonce.len() > 32 {
+            Some("nonce must be at most 32 bytes")
+        } else if self.pending_member_requests.contains_key(&nonce) {
+            Some("a member request with this nonce is already in progress")
+        } else {
+            None
+        };
+
+        if let Some(msg) = validation_error {
+            _ = reply.send(Err(GatewayError::custom(msg)));
+            return Ok(());
+        }
+
+        let payload = RequestGuildMembers::new(guild_id, query, user_ids, nonce.clone());
+        let pending = PendingMemberRequest::new(guild_id, reply);
+        let reply = pending.reply();
+
+        let (tx, rx) = oneshot::channel();
+        if let Err(e) = self.write(payload, tx).await {
+            reply.send(Err(GatewayError::custom(&e)));
+            return Err(e);
         }
 
+        self.pending_member_requests.insert(nonce, pending);
+
+        // The payload may be held back by the ratelimiter, or rejected if the write queue is full
+        tokio::spawn(async move {
+            let res = match rx.await {
+                Ok(res) => res,
+                Err(e) => Err(e.into()),
+            };
+
+            if let Err(e) = res {
+                error!(error = %e, %guild_id, "Error writing request guild members payload");
+                reply.send(Err(e));
+            }
+        });
+
         Ok(())
     }
 
+    fn handle_members_chunk(&mut self, chunk: GuildMembersChunk) {
+        let Some(nonce) = chunk.nonce.clone() else {
+            return;
+        };
+
+        let Some(pending) = self.pending_member_requests.get_mut(&nonce) else {
+            return;
+        };
+
+        debug!(
+            guild_id = %chunk.guild_id,
+            chunk_index = chunk.chunk_index,
+            chunk_count = chunk.chunk_count,
+            "Received requested guild members chunk"
+        );
+
+        if pending.push(chunk) {
+            if let Some(pending) = self.pending_member_requests.remove(&nonce) {
+                if !pending.complete() {
+                    warn!(%nonce, "Guild members requester hung up before chunks were received");
+                }
+            }
+        }
+    }
+
+    // Removes requests that are no longer being waited for, as their chunks may never arrive
+    fn expire_member_requests(&mut self) {
+        let now = Instant::now();
+        let abandoned: Vec<String> = self
+            .pending_member_requests
+            .iter()
+            .filter(|(_, pending)| pending.is_abandoned(now))
+            .map(|(nonce, _)| nonce.clone())
+            .collect();
+
+        for nonce in abandoned {
+            if let Some(pending) = self.pending_member_requests.remove(&nonce) {
+                warn!(%nonce, "Guild members request timed out");
+                pending.fail(GatewayError::custom("timed out waiting for guild members"));
+            }
+        }
+    }
+
+    fn fail_member_requests(&mut self, reason: &str) {
+        for (_, pending) in self.pending_member_requests.drain() {
+            pending.fail(GatewayError::custom(reason));
+        }
+    }
+
     #[tracing::instrument(skip(self))]
     async fn increment_received_count(&mut self) {
         self.received_count += 1;
@@ -692,6 +947,7 @@ impl<T: EventForwarder> Shard<T> {
                 #[cfg(feature = "resume-after-identify")]
                 if !self.used_resume {
                     let kill_shard_tx = self.kill_shard_tx.lock().take();
+                    let metrics = self.metrics.clone();
 
                     info!(
                         received_count = self.received_count,
@@ -700,6 +956,7 @@ impl<T: EventForwarder> Shard<T> {
                     tokio::spawn(async move {
                         tokio::time::sleep(Duration::from_secs(90)).await;
                         info!("Received 90% of guilds, killing shard");
+                        metrics.reconnecting("resume_after_identify");
 
                         match kill_shard_tx {
                             Some(kill_shard_tx) => {
@@ -728,6 +985,13 @@ impl<T: EventForwarder> Shard<T> {
         true
     }
 
+    // Saves the session so that it can be resumed if the sharder crashes
+    fn checkpoint_session(&self) {
+        if let Some(session_data) = &self.session_data {
+            self.checkpoint.save(session_data.clone());
+        }
+    }
+
     #[tracing::instrument(skip(self))]
     async fn do_heartbeat(&mut self) -> Result<()> {
         debug!("Sending heartbeat");
@@ -736,7 +1000,7 @@ impl<T: EventForwarder> Shard<T> {
         let payload = payloads::Heartbeat::new(seq);
 
         let (tx, rx) = oneshot::channel();
-        self.write(payload, tx).await?;
+        self.write_priority(payload, tx).await?;
 
         rx.await??;
 
@@ -747,7 +1011,7 @@ impl<T: EventForwarder> Shard<T> {
     #[tracing::instrument(skip(self))]
     async fn do_identify(&self) -> Result<()> {
         let (tx, rx) = oneshot::channel();
-        self.write(&self.identify, tx).await?;
+        self.write_priority(&self.identify, tx).await?;
 
         Ok(rx.await??)
     }
@@ -755,40 +1019,17 @@ impl<T: EventForwarder> Shard<T> {
     #[tracing::instrument(skip(self))]
     async fn wait_for_ratelimit(&self) -> Result<()> {
         info!("Waiting for IDENTIFY ratelimit");
-
-        let key = self.get_ratelimit_key();
-
-        let mut res = redis::Value::Nil;
-        while res == redis::Value::Nil {
-            let mut conn = self.redis.get().await?;
-
-            res = cmd("SET")
-                .arg(&[&key[..], "1", "NX", "PX", "8000"]) // some arbitrary value, set if not exist, set expiry, of 8s to allow websocket connection
-                .query_async(&mut conn)
-                .await?;
-
-            if res == redis::Value::Nil {
-                // get time to delay
-                let ttl = cmd("PTTL").arg(&key).query_async(&mut conn).await?;
-
-                if let redis::Value::Int(ttl) = ttl {
-                    // if number is negative, we can go ahead and identify
-                    // -1 = no expire, -2 = doesn't exist
-                    if ttl > 0 {
-                        let ttl = Duration::from_millis(ttl as u64);
-                        sleep(ttl).await
-                    }
-                }
-            }
-        }
-
+        self.identify_ratelimiter
+            .wait(&self.get_ratelimit_key())
+            .await?;
         info!("Got IDENTIFY ratelimit token");
         Ok(())
     }
 
     async fn update_ratelimit_after_identify(&self) -> Result<()> {
-        let key = self.get_ratelimit_key();
-        self.redis_write(key.as_str(), "1", Some(5)).await
+        self.identify_ratelimiter
+            .identified(&self.get_ratelimit_key())
+            .await
     }
 
     fn get_ratelimit_key(&self) -> String {
@@ -809,43 +1050,93 @@ impl<T: EventForwarder> Shard<T> {
         let payload = payloads::Resume::new(self.identify.data.token.clone(), session_id, seq);
 
         let (tx, rx) = oneshot::channel();
-        self.write(payload, tx).await?;
+        self.write_priority(payload, tx).await?;
 
         Ok(rx.await??)
     }
 
-    #[tracing::instrument(skip(self, value, expiry))]
-    async fn redis_write<U: ToString>(
-        &self,
-        key: &str,
-        value: U,
-        expiry: Option<usize>,
-    ) -> Result<()> {
-        let mut conn = self.redis.get().await?;
-        match expiry {
-            Some(expiry) => conn.set_ex(key, value.to_string(), expiry).await?,
-            None => conn.set(key, value.to_string()).await?,
-        }
-
-        Ok(())
-    }
-
     /// helper
     pub fn get_shard_id(&self) -> u16 {
         self.identify.data.shard_info.shard_id
     }
 }
 
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
 async fn handle_writes(
     mut tx: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
     mut rx: mpsc::Receiver<super::OutboundMessage>,
+    bot_id: Snowflake,
+    shard_id: u16,
 ) {
-    while let Some(msg) = rx.recv().await {
-        let payload = Message::text(msg.message);
-        let res = tx.send(payload).await;
+    let mut ratelimiter = Ratelimiter::new(
+        GATEWAY_SEND_LIMIT,
+        GATEWAY_SEND_PERIOD,
+        GATEWAY_SEND_RESERVED,
+    );
+    let mut queue: VecDeque<OutboundMessage> = VecDeque::new();
+
+    #[cfg(feature = "metrics")]
+    let (bot_id, shard_id) = (bot_id.to_string(), shard_id.to_string());
+    #[cfg(feature = "metrics")]
+    let queue_gauge = WRITE_QUEUE_GAUGE.with_label_values(&[&bot_id, &shard_id]);
+
+    loop {
+        // Write as much of the queue as the ratelimit allows
+        let mut wait = None;
+        while !queue.is_empty() {
+            match ratelimiter.try_acquire(false, Instant::now()) {
+                Ok(()) => {
+                    let msg = queue.pop_front().expect("queue is not empty");
+                    write_message(&mut tx, msg).await;
+                }
+                Err(duration) => {
+                    wait = Some(duration);
+                    break;
+                }
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        queue_gauge.set(queue.len() as i64);
 
-        if let Err(e) = msg.tx.send(res.map_err(|e| e.into())) {
-            error!(error = ?e, "Error while sending write result back to caller");
+        tokio::select! {
+            msg = rx.recv() => {
+                let Some(msg) = msg else { break };
+
+                if msg.priority {
+                    if let Err(duration) = ratelimiter.try_acquire(true, Instant::now()) {
+                        warn!(wait = ?duration, "Out of reserved gateway ratelimit capacity, delaying write");
+                        sleep(duration).await;
+                        _ = ratelimiter.try_acquire(true, Instant::now());
+                    }
+
+                    write_message(&mut tx, msg).await;
+                } else if queue.len() >= MAX_QUEUED_WRITES {
+                    if msg.tx.send(GatewayError::custom("gateway write queue is full").into()).is_err() {
+                        error!("Error while sending write result back to caller");
+                    }
+                } else {
+                    queue.push_back(msg);
+                }
+            }
+
+            _ = sleep(wait.unwrap_or_default()), if wait.is_some() => {}
         }
     }
+
+    #[cfg(feature = "metrics")]
+    if let Err(e) = WRITE_QUEUE_GAUGE.remove_label_values(&[&bot_id, &shard_id]) {
+        debug!(error = %e, "Error removing gateway write queue gauge");
+    }
+}
+
+async fn write_message(
+    tx: &mut SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
+    msg: OutboundMessage,
+) {
+    let res = tx.send(msg.message).await;
+
+    if let Err(e) = msg.tx.send(res.map_err(|e| e.into())) {
+        error!(error = ?e, "Error while sending write result back to caller");
+    }
 }
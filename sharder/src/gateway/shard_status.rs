@@ -0,0 +1,90 @@
+use model::Snowflake;
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShardState {
+    Connecting,
+    Identifying,
+    Resuming,
+    Ready,
+    Disconnected,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ShardStatus {
+    pub bot_id: Snowflake,
+    pub shard_id: u16,
+    pub state: ShardState,
+    /// Time between the last heartbeat and its ACK
+    pub heartbeat_latency_ms: Option<u64>,
+    pub seq: Option<usize>,
+    pub session_id: Option<String>,
+    /// Number of guilds in the last READY payload
+    pub guild_count: usize,
+}
+
+/// Status of a single shard, shared between the shard and the registry. The handle outlives the
+/// shard, as a new shard is built every time it reconnects.
+#[derive(Clone)]
+pub struct ShardStatusHandle(Arc<Mutex<ShardStatus>>);
+
+impl ShardStatusHandle {
+    pub fn new(bot_id: Snowflake, shard_id: u16) -> Self {
+        Self(Arc::new(Mutex::new(ShardStatus {
+            bot_id,
+            shard_id,
+            state: ShardState::Disconnected,
+            heartbeat_latency_ms: None,
+            seq: None,
+            session_id: None,
+            guild_count: 0,
+        })))
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut ShardStatus)) {
+        f(&mut self.0.lock());
+    }
+
+    pub fn set_state(&self, state: ShardState) {
+        self.0.lock().state = state;
+    }
+
+    pub fn get(&self) -> ShardStatus {
+        self.0.lock().clone()
+    }
+}
+
+/// Statuses of every shard run by a manager, keyed by the same ID as their session data
+#[derive(Default)]
+pub struct ShardRegistry {
+    shards: RwLock<HashMap<u64, ShardStatusHandle>>,
+}
+
+impl ShardRegistry {
+    /// Returns the handle for a shard, registering it if this is the first time it has started
+    pub fn handle(&self, id: u64, bot_id: Snowflake, shard_id: u16) -> ShardStatusHandle {
+        if let Some(handle) = self.shards.read().get(&id) {
+            return handle.clone();
+        }
+
+        self.shards
+            .write()
+            .entry(id)
+            .or_insert_with(|| ShardStatusHandle::new(bot_id, shard_id))
+            .clone()
+    }
+
+    pub fn remove(&self, id: u64) {
+        self.shards.write().remove(&id);
+    }
+
+    pub fn list(&self) -> Vec<ShardStatus> {
+        let mut statuses: Vec<_> = self.shards.read().values().map(|s| s.get()).collect();
+        statuses.sort_by_key(|s| (s.bot_id.0, s.shard_id));
+        statuses
+    }
+}
@@ -3,16 +3,59 @@ use crate::gateway::event_forwarding::EventForwarder;
 #[cfg(feature = "whitelabel")]
 use crate::{GatewayError, Result, Shard};
 #[cfg(feature = "whitelabel")]
+use common::whitelabel_guilds;
+#[cfg(feature = "whitelabel")]
+use deadpool_redis::redis::cmd;
+#[cfg(feature = "whitelabel")]
 use model::Snowflake;
 
 #[cfg(feature = "whitelabel")]
 impl<T: EventForwarder> Shard<T> {
     pub async fn store_whitelabel_guild(&self, guild_id: Snowflake) -> Result<()> {
-        self.database
+        let inserted = self
+            .database
             .whitelabel_guilds
             .insert(self.user_id, guild_id)
             .await
-            .map_err(GatewayError::DatabaseError)
+            .map_err(GatewayError::DatabaseError)?;
+
+        // GUILD_CREATE is received for every guild on each connect, so only joins are published
+        if inserted {
+            self.publish_whitelabel_guild(guild_id, true).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn remove_whitelabel_guild(&self, guild_id: Snowflake) -> Result<()> {
+        let deleted = self
+            .database
+            .whitelabel_guilds
+            .delete(self.user_id, guild_id)
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        if deleted {
+            self.publish_whitelabel_guild(guild_id, false).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn publish_whitelabel_guild(&self, guild_id: Snowflake, joined: bool) -> Result<()> {
+        let payload = whitelabel_guilds::Payload {
+            bot_id: self.user_id,
+            guild_id,
+            joined,
+        };
+
+        let mut conn = self.redis.get().await?;
+        cmd("PUBLISH")
+            .arg(&[whitelabel_guilds::KEY, &serde_json::to_string(&payload)?])
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        Ok(())
     }
 }
 
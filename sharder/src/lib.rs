@@ -8,10 +8,12 @@ pub use manager::{Options, ShardCount, ShardManager};
 pub use manager::PublicShardManager;
 
 #[cfg(feature = "whitelabel")]
-pub use manager::WhitelabelShardManager;
+pub use manager::{BotAssignment, WhitelabelShardManager};
 
 mod builders;
-pub use builders::{build_redis, setup_sentry};
+pub use builders::{
+    build_event_filters, build_event_forwarder, build_redis, build_session_store, setup_sentry,
+};
 
 mod config;
 pub use config::Config;
@@ -21,3 +23,6 @@ pub mod metrics_server;
 
 mod util;
 pub use util::*;
+
+#[doc(hidden)]
+pub mod test_support;
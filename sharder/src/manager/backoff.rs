@@ -0,0 +1,129 @@
+use rand::Rng;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Exponential backoff with jitter. Each delay is picked at random from the upper half of
+/// `base * 2^attempt`, capped at `max`, so that bots that fail together don't retry together.
+pub(crate) struct Backoff {
+    base: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            attempt: 0,
+        }
+    }
+
+    pub fn next(&mut self) -> Duration {
+        let ceiling = self
+            .base
+            .checked_mul(2u32.saturating_pow(self.attempt))
+            .unwrap_or(self.max)
+            .min(self.max);
+
+        self.attempt = self.attempt.saturating_add(1);
+
+        let half = ceiling / 2;
+        half + half.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// Counts failures within a sliding window
+pub(crate) struct FailureWindow {
+    limit: usize,
+    window: Duration,
+    failures: VecDeque<Instant>,
+}
+
+impl FailureWindow {
+    pub fn new(limit: usize, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            failures: VecDeque::with_capacity(limit),
+        }
+    }
+
+    /// Returns true if the limit has been reached
+    pub fn record(&mut self) -> bool {
+        self.record_at(Instant::now())
+    }
+
+    fn record_at(&mut self, now: Instant) -> bool {
+        while let Some(&oldest) = self.failures.front() {
+            if now.duration_since(oldest) < self.window {
+                break;
+            }
+
+            self.failures.pop_front();
+        }
+
+        self.failures.push_back(now);
+        self.failures.len() >= self.limit
+    }
+
+    pub fn clear(&mut self) {
+        self.failures.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let base = Duration::from_millis(500);
+        let max = Duration::from_secs(10);
+        let mut backoff = Backoff::new(base, max);
+
+        for attempt in 0..10 {
+            let ceiling = (base * 2u32.pow(attempt)).min(max);
+            let delay = backoff.next();
+            assert!(
+                delay >= ceiling / 2 && delay <= ceiling,
+                "{}: {:?}",
+                attempt,
+                delay
+            );
+        }
+
+        backoff.reset();
+        assert!(backoff.next() <= base);
+    }
+
+    #[test]
+    fn test_backoff_overflow() {
+        let max = Duration::from_secs(60);
+        let mut backoff = Backoff::new(Duration::from_secs(1), max);
+
+        for _ in 0..100 {
+            assert!(backoff.next() <= max);
+        }
+    }
+
+    #[test]
+    fn test_failure_window() {
+        let window = Duration::from_secs(60);
+        let mut failures = FailureWindow::new(3, window);
+        let start = Instant::now();
+
+        assert!(!failures.record_at(start));
+        assert!(!failures.record_at(start + Duration::from_secs(10)));
+        // The first failure has left the window
+        assert!(!failures.record_at(start + Duration::from_secs(61)));
+        assert!(failures.record_at(start + Duration::from_secs(62)));
+
+        failures.clear();
+        assert!(!failures.record_at(start + Duration::from_secs(63)));
+    }
+}
@@ -0,0 +1,545 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use deadpool_redis::redis::{cmd, Pipeline};
+use deadpool_redis::Pool;
+use model::Snowflake;
+use parking_lot::Mutex;
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::error;
+
+use crate::Result;
+
+const LEASE_KEY_PREFIX: &str = "tickets:whitelabel:lease";
+const SHARDERS_KEY: &str = "tickets:whitelabel:sharders";
+
+// Only touches the lease if it is still held by this sharder
+const RENEW_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("PEXPIRE", KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+const RELEASE_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+/// How whitelabel bots are split between sharders, chosen with BOT_ASSIGNMENT
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BotAssignment {
+    /// `bot_id % SHARDER_TOTAL == SHARDER_ID`
+    #[default]
+    Static,
+    /// Sharders claim bots with leases in Redis, so replicas can be added and removed at any time
+    Lease,
+}
+
+/// Where leases on whitelabel bots are held. Live sharders also register themselves, so that each
+/// can work out its share of the bots.
+#[async_trait]
+pub(crate) trait LeaseStore: Send + Sync {
+    fn instance_id(&self) -> &str;
+
+    /// Marks this sharder as live until the lease TTL passes, and returns the number of live
+    /// sharders
+    async fn register(&self) -> Result<usize>;
+
+    async fn deregister(&self) -> Result<()>;
+
+    /// Returns the bots that no sharder holds a lease on
+    async fn unclaimed(&self, bot_ids: &[Snowflake]) -> Result<Vec<Snowflake>>;
+
+    /// Returns false if another sharder holds the lease
+    async fn claim(&self, bot_id: Snowflake) -> Result<bool>;
+
+    /// Extends the leases, and returns the bots whose leases have been lost
+    async fn renew(&self, bot_ids: &[Snowflake]) -> Result<Vec<Snowflake>>;
+
+    async fn release(&self, bot_id: Snowflake) -> Result<()>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LeaseState {
+    Held,
+    /// The bot is being stopped, so that its lease can be released to another sharder
+    HandingOver,
+}
+
+/// The leases this sharder holds on whitelabel bots. A bot is run by the sharder holding its
+/// lease, which must be renewed before `ttl` passes, or the bot is picked up by another sharder.
+pub(crate) struct Leases {
+    store: Box<dyn LeaseStore>,
+    ttl: Duration,
+    owned: Mutex<HashMap<Snowflake, LeaseState>>,
+    // When the last successful renewal was sent. Every lease is held until at least `ttl` after.
+    renewed_at: Mutex<Instant>,
+}
+
+impl Leases {
+    pub fn new(store: impl LeaseStore + 'static, ttl: Duration) -> Self {
+        Self {
+            store: Box::new(store),
+            ttl,
+            owned: Mutex::new(HashMap::new()),
+            renewed_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn instance_id(&self) -> &str {
+        self.store.instance_id()
+    }
+
+    pub async fn register(&self) -> Result<usize> {
+        self.store.register().await
+    }
+
+    pub async fn deregister(&self) -> Result<()> {
+        self.store.deregister().await
+    }
+
+    /// None if the lease is not held
+    pub fn state(&self, bot_id: Snowflake) -> Option<LeaseState> {
+        self.owned.lock().get(&bot_id).copied()
+    }
+
+    pub fn owned(&self) -> Vec<Snowflake> {
+        self.owned.lock().keys().copied().collect()
+    }
+
+    /// Returns the bots that no sharder holds a lease on, in a random order
+    pub async fn unclaimed(&self, bot_ids: &[Snowflake]) -> Result<Vec<Snowflake>> {
+        let mut unclaimed = self.store.unclaimed(bot_ids).await?;
+        unclaimed.shuffle(&mut rand::thread_rng());
+        Ok(unclaimed)
+    }
+
+    /// Returns false if another sharder holds the lease
+    pub async fn claim(&self, bot_id: Snowflake) -> Result<bool> {
+        if !self.store.claim(bot_id).await? {
+            return Ok(false);
+        }
+
+        self.owned.lock().insert(bot_id, LeaseState::Held);
+        Ok(true)
+    }
+
+    pub async fn release(&self, bot_id: Snowflake) -> Result<()> {
+        self.owned.lock().remove(&bot_id);
+        self.store.release(bot_id).await
+    }
+
+    /// Extends the leases, and returns the bots whose leases have been lost, which must be
+    /// stopped. If the leases can't be renewed, every bot is treated as lost once the next renewal
+    /// would be too late, so that the bots have a renewal interval to stop before the leases
+    /// expire and other sharders can claim them.
+    pub async fn renew(&self, now: Instant) -> Result<Vec<Snowflake>> {
+        let owned = self.owned();
+        let deadline = self.ttl - renew_interval(self.ttl);
+
+        let lost = match self.store.renew(&owned).await {
+            Ok(lost) => {
+                *self.renewed_at.lock() = now;
+                lost
+            }
+            Err(e) if now.saturating_duration_since(*self.renewed_at.lock()) >= deadline => {
+                error!(error = %e, held = owned.len(), "Leases expired before they could be renewed");
+                owned
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut owned = self.owned.lock();
+        for bot_id in &lost {
+            owned.remove(bot_id);
+        }
+
+        Ok(lost)
+    }
+
+    /// Picks a running bot to hand over if more than `share` are held, unless one is already being
+    /// handed over, so that bots are moved one at a time
+    pub fn start_hand_over(
+        &self,
+        share: usize,
+        is_running: impl Fn(Snowflake) -> bool,
+    ) -> Option<Snowflake> {
+        let mut owned = self.owned.lock();
+        if owned.len() <= share || owned.values().any(|&s| s == LeaseState::HandingOver) {
+            return None;
+        }
+
+        // Only running bots can be handed over, as their sessions are needed
+        let bot_id = owned
+            .iter()
+            .find(|(&bot_id, &state)| state == LeaseState::Held && is_running(bot_id))
+            .map(|(&bot_id, _)| bot_id)?;
+
+        owned.insert(bot_id, LeaseState::HandingOver);
+        Some(bot_id)
+    }
+}
+
+/// Leases held in Redis, which expire after `ttl`
+pub(crate) struct RedisLeases {
+    redis: Arc<Pool>,
+    instance_id: String,
+    ttl: Duration,
+}
+
+impl RedisLeases {
+    pub fn new(redis: Arc<Pool>, instance_id: String, ttl: Duration) -> Self {
+        Self {
+            redis,
+            instance_id,
+            ttl,
+        }
+    }
+}
+
+#[async_trait]
+impl LeaseStore for RedisLeases {
+    fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    async fn register(&self) -> Result<usize> {
+        let now = unix_millis();
+        let expires_at = now + self.ttl.as_millis() as u64;
+
+        let mut conn = self.redis.get().await?;
+        let (live,): (usize,) = Pipeline::new()
+            .cmd("ZADD")
+            .arg(SHARDERS_KEY)
+            .arg(expires_at)
+            .arg(&self.instance_id)
+            .ignore()
+            .cmd("ZREMRANGEBYSCORE")
+            .arg(SHARDERS_KEY)
+            .arg("-inf")
+            .arg(now)
+            .ignore()
+            .cmd("ZCARD")
+            .arg(SHARDERS_KEY)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(live)
+    }
+
+    async fn deregister(&self) -> Result<()> {
+        let mut conn = self.redis.get().await?;
+        cmd("ZREM")
+            .arg(SHARDERS_KEY)
+            .arg(&self.instance_id)
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn unclaimed(&self, bot_ids: &[Snowflake]) -> Result<Vec<Snowflake>> {
+        if bot_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let keys: Vec<String> = bot_ids.iter().map(|&bot_id| lease_key(bot_id)).collect();
+
+        let mut conn = self.redis.get().await?;
+        let owners: Vec<Option<String>> = cmd("MGET").arg(keys).query_async(&mut conn).await?;
+
+        Ok(bot_ids
+            .iter()
+            .zip(owners)
+            .filter(|(_, owner)| owner.is_none())
+            .map(|(&bot_id, _)| bot_id)
+            .collect())
+    }
+
+    async fn claim(&self, bot_id: Snowflake) -> Result<bool> {
+        let mut conn = self.redis.get().await?;
+        let res: Option<String> = cmd("SET")
+            .arg(lease_key(bot_id))
+            .arg(&self.instance_id)
+            .arg("NX")
+            .arg("PX")
+            .arg(self.ttl.as_millis() as u64)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(res.is_some())
+    }
+
+    async fn renew(&self, bot_ids: &[Snowflake]) -> Result<Vec<Snowflake>> {
+        if bot_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut pipeline = Pipeline::new();
+        for &bot_id in bot_ids {
+            pipeline
+                .cmd("EVAL")
+                .arg(RENEW_SCRIPT)
+                .arg(1)
+                .arg(lease_key(bot_id))
+                .arg(&self.instance_id)
+                .arg(self.ttl.as_millis() as u64);
+        }
+
+        let mut conn = self.redis.get().await?;
+        let renewed: Vec<bool> = pipeline.query_async(&mut conn).await?;
+
+        Ok(bot_ids
+            .iter()
+            .zip(renewed)
+            .filter(|(_, renewed)| !renewed)
+            .map(|(&bot_id, _)| bot_id)
+            .collect())
+    }
+
+    async fn release(&self, bot_id: Snowflake) -> Result<()> {
+        let mut conn = self.redis.get().await?;
+        cmd("EVAL")
+            .arg(RELEASE_SCRIPT)
+            .arg(1)
+            .arg(lease_key(bot_id))
+            .arg(&self.instance_id)
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+fn lease_key(bot_id: Snowflake) -> String {
+    format!("{LEASE_KEY_PREFIX}:{bot_id}")
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// How often leases with a TTL of `ttl` are renewed
+pub(crate) fn renew_interval(ttl: Duration) -> Duration {
+    ttl / 3
+}
+
+/// The number of bots each of `sharders` should run, so that every bot is claimed
+pub(crate) fn share(bots: usize, sharders: usize) -> usize {
+    bots.div_ceil(sharders.max(1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GatewayError;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // Leases shared by every sharder using the same `owners`, which never expire. Fails while
+    // `down` is set, as if Redis were unavailable.
+    struct FakeLeases {
+        instance_id: String,
+        owners: Arc<Mutex<HashMap<Snowflake, String>>>,
+        down: Arc<AtomicBool>,
+    }
+
+    impl FakeLeases {
+        fn check(&self) -> Result<()> {
+            if self.down.load(Ordering::Relaxed) {
+                return GatewayError::custom("redis is down").into();
+            }
+
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl LeaseStore for FakeLeases {
+        fn instance_id(&self) -> &str {
+            &self.instance_id
+        }
+
+        async fn register(&self) -> Result<usize> {
+            self.check()?;
+            Ok(2)
+        }
+
+        async fn deregister(&self) -> Result<()> {
+            self.check()
+        }
+
+        async fn unclaimed(&self, bot_ids: &[Snowflake]) -> Result<Vec<Snowflake>> {
+            self.check()?;
+            let owners = self.owners.lock();
+            Ok(bot_ids
+                .iter()
+                .filter(|bot_id| !owners.contains_key(bot_id))
+                .copied()
+                .collect())
+        }
+
+        async fn claim(&self, bot_id: Snowflake) -> Result<bool> {
+            self.check()?;
+            let mut owners = self.owners.lock();
+            if owners.contains_key(&bot_id) {
+                return Ok(false);
+            }
+
+            owners.insert(bot_id, self.instance_id.clone());
+            Ok(true)
+        }
+
+        async fn renew(&self, bot_ids: &[Snowflake]) -> Result<Vec<Snowflake>> {
+            self.check()?;
+            let owners = self.owners.lock();
+            Ok(bot_ids
+                .iter()
+                .filter(|bot_id| owners.get(bot_id) != Some(&self.instance_id))
+                .copied()
+                .collect())
+        }
+
+        async fn release(&self, bot_id: Snowflake) -> Result<()> {
+            self.check()?;
+            let mut owners = self.owners.lock();
+            if owners.get(&bot_id) == Some(&self.instance_id) {
+                owners.remove(&bot_id);
+            }
+
+            Ok(())
+        }
+    }
+
+    const TTL: Duration = Duration::from_secs(30);
+
+    struct Cluster {
+        owners: Arc<Mutex<HashMap<Snowflake, String>>>,
+        down: Arc<AtomicBool>,
+    }
+
+    impl Cluster {
+        fn new() -> Self {
+            Self {
+                owners: Arc::default(),
+                down: Arc::default(),
+            }
+        }
+
+        fn sharder(&self, instance_id: &str) -> Leases {
+            let store = FakeLeases {
+                instance_id: instance_id.to_owned(),
+                owners: Arc::clone(&self.owners),
+                down: Arc::clone(&self.down),
+            };
+
+            Leases::new(store, TTL)
+        }
+    }
+
+    fn sorted(mut bot_ids: Vec<Snowflake>) -> Vec<Snowflake> {
+        bot_ids.sort_unstable_by_key(|bot_id| bot_id.0);
+        bot_ids
+    }
+
+    #[tokio::test]
+    async fn test_claim() {
+        let cluster = Cluster::new();
+        let (a, b) = (cluster.sharder("a"), cluster.sharder("b"));
+        let bot_ids = [Snowflake(1), Snowflake(2)];
+
+        assert!(a.claim(Snowflake(1)).await.unwrap());
+        assert!(!b.claim(Snowflake(1)).await.unwrap());
+        assert_eq!(b.unclaimed(&bot_ids).await.unwrap(), [Snowflake(2)]);
+        assert!(b.claim(Snowflake(2)).await.unwrap());
+
+        assert_eq!(a.state(Snowflake(1)), Some(LeaseState::Held));
+        assert_eq!(a.state(Snowflake(2)), None);
+        assert_eq!(b.owned(), [Snowflake(2)]);
+        assert!(a.unclaimed(&bot_ids).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_hand_over() {
+        let cluster = Cluster::new();
+        let (a, b) = (cluster.sharder("a"), cluster.sharder("b"));
+        for bot_id in 1..=3 {
+            assert!(a.claim(Snowflake(bot_id)).await.unwrap());
+        }
+
+        // Only bots that are running can be handed over
+        assert_eq!(a.start_hand_over(2, |_| false), None);
+        let bot_id = a
+            .start_hand_over(2, |bot_id| bot_id == Snowflake(2))
+            .unwrap();
+        assert_eq!(bot_id, Snowflake(2));
+        assert_eq!(a.state(bot_id), Some(LeaseState::HandingOver));
+
+        // One at a time
+        assert_eq!(a.start_hand_over(1, |_| true), None);
+
+        // The lease is released once the bot has stopped, and is then claimed by another sharder
+        assert!(!b.claim(bot_id).await.unwrap());
+        a.release(bot_id).await.unwrap();
+        assert_eq!(a.state(bot_id), None);
+        assert!(b.claim(bot_id).await.unwrap());
+
+        // Nothing to hand over once at the share
+        assert_eq!(a.start_hand_over(2, |_| true), None);
+    }
+
+    #[tokio::test]
+    async fn test_lease_lost() {
+        let cluster = Cluster::new();
+        let a = cluster.sharder("a");
+        for bot_id in 1..=2 {
+            assert!(a.claim(Snowflake(bot_id)).await.unwrap());
+        }
+
+        // Claimed by another sharder after the lease expired
+        cluster.owners.lock().insert(Snowflake(1), "b".to_owned());
+
+        let lost = a.renew(Instant::now()).await.unwrap();
+        assert_eq!(lost, [Snowflake(1)]);
+        assert_eq!(a.owned(), [Snowflake(2)]);
+    }
+
+    #[tokio::test]
+    async fn test_leases_expire_while_renewals_fail() {
+        let cluster = Cluster::new();
+        let a = cluster.sharder("a");
+        for bot_id in 1..=2 {
+            assert!(a.claim(Snowflake(bot_id)).await.unwrap());
+        }
+
+        let now = Instant::now();
+        assert!(a.renew(now).await.unwrap().is_empty());
+
+        cluster.down.store(true, Ordering::Relaxed);
+
+        // The leases are still held until the next renewal would be too late
+        assert!(a.renew(now + TTL / 3).await.is_err());
+        assert_eq!(a.owned().len(), 2);
+
+        let lost = a.renew(now + TTL * 2 / 3).await.unwrap();
+        assert_eq!(sorted(lost), [Snowflake(1), Snowflake(2)]);
+        assert!(a.owned().is_empty());
+    }
+
+    #[test]
+    fn test_share() {
+        assert_eq!(share(0, 3), 0);
+        assert_eq!(share(9, 3), 3);
+        assert_eq!(share(10, 3), 4);
+        assert_eq!(share(10, 0), 10);
+    }
+}
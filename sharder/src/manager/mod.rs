@@ -11,15 +11,36 @@ mod whitelabel_shard_manager;
 #[cfg(feature = "whitelabel")]
 pub use whitelabel_shard_manager::WhitelabelShardManager;
 
+#[cfg(feature = "whitelabel")]
+mod whitelabel_health;
+
+#[cfg(feature = "whitelabel")]
+mod backoff;
+
+#[cfg(feature = "whitelabel")]
+mod status_rotation;
+
+#[cfg(feature = "whitelabel")]
+mod lease;
+#[cfg(feature = "whitelabel")]
+pub use lease::BotAssignment;
+
 mod options;
 pub use options::*;
 
-use crate::gateway::Intents;
-fn get_intents() -> u64 {
-    Intents::build(vec![
-        Intents::Guilds,
-        Intents::GuildMembers,
-        Intents::GuildMessages,
-        Intents::MessageContent,
-    ])
+use crate::gateway::event_forwarding::intent_warnings;
+use crate::Config;
+use tracing::warn;
+
+// The default intents don't cover every event forwarded by default, so only intents that were
+// configured explicitly are checked
+fn warn_intent_mismatches(config: &Config) {
+    let intents = match config.gateway_intents {
+        Some(intents) => intents,
+        None => return,
+    };
+
+    for warning in intent_warnings(intents, &config.forwarding_rules) {
+        warn!(intents, "{warning}");
+    }
 }
@@ -4,7 +4,9 @@ use model::Snowflake;
 pub struct Options {
     pub token: Box<str>,
     pub shard_count: ShardCount,
+    // Used until a presence is published to common::presence_updates::KEY
     pub presence: StatusUpdate,
+    // Used if max_concurrency can't be fetched from /gateway/bot
     pub large_sharding_buckets: u16,
     pub user_id: Snowflake,
 }
@@ -5,75 +5,370 @@ use tracing::{error, info, warn};
 use super::Options;
 use super::ShardManager;
 
-use crate::gateway::{payloads::Identify, Shard, ShardInfo};
-use crate::{RedisSessionStore, SessionData, SessionStore, ShardIdentifier};
+use crate::gateway::{
+    payloads::Identify, GatewayBot, IdentifyRatelimiter, Shard, ShardInfo, DEFAULT_GATEWAY_BOT_URL,
+    MEMBER_REQUEST_TIMEOUT,
+};
+use crate::{
+    GuildMembers, InternalCommand, Result, SessionCheckpointer, SessionData, SessionStore,
+    ShardIdentifier, ShardRegistry, ShardStatus,
+};
 
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 
 use crate::config::Config;
-use crate::gateway::event_forwarding::EventForwarder;
+use crate::gateway::event_forwarding::{EventFilters, EventForwarder};
 use crate::GatewayError;
+use common::presence_updates;
+use deadpool_redis::redis::{self, cmd};
 use deadpool_redis::Pool;
+use futures::StreamExt;
+use model::user::StatusUpdate;
+use model::Snowflake;
 use std::time::Duration;
 use tokio::fs::File;
-use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::sync::{broadcast, mpsc, oneshot, watch, RwLock};
 use tokio::time::{sleep, timeout};
 
-pub struct PublicShardManager<T: EventForwarder> {
+// Presence updates are coalesced, so that each shard sends at most one per interval. Updates are
+// also subject to the gateway send ratelimit of the shard.
+const PRESENCE_UPDATE_INTERVAL: Duration = Duration::from_secs(15);
+const PRESENCE_RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+pub struct PublicShardManager<T: EventForwarder, S: SessionStore> {
     config: Arc<Config>,
     options: Options,
-    session_store: RedisSessionStore,
+    max_concurrency: u16,
+    session_store: Arc<S>,
+    checkpointer: SessionCheckpointer,
     redis: Arc<Pool>,
+    identify_ratelimiter: Arc<IdentifyRatelimiter>,
     event_forwarder: Arc<T>,
+    event_filters: Arc<EventFilters>,
     shutdown_tx: broadcast::Sender<mpsc::Sender<(ShardIdentifier, Option<SessionData>)>>,
+    shard_command_channels: RwLock<HashMap<u16, mpsc::Sender<InternalCommand>>>,
+    member_request_nonce: AtomicU64,
+    registry: ShardRegistry,
+    // The presence that shards identify with
+    presence: watch::Sender<StatusUpdate>,
+    ready: AtomicBool,
 }
 
-impl<T: EventForwarder> PublicShardManager<T> {
+impl<T: EventForwarder, S: SessionStore> PublicShardManager<T, S> {
     pub async fn new(
         config: Config,
         options: Options,
-        session_store: RedisSessionStore,
+        session_store: S,
         redis: Arc<Pool>,
         event_forwarder: Arc<T>,
+        event_filters: EventFilters,
     ) -> Self {
+        super::warn_intent_mismatches(&config);
+
+        let max_concurrency = Self::fetch_max_concurrency(&config, &options).await;
+
+        let session_store = Arc::new(session_store);
+        let checkpointer = SessionCheckpointer::start(
+            Arc::clone(&session_store),
+            Duration::from_secs(config.session_checkpoint_interval),
+        );
+
         let (shutdown_tx, _) = broadcast::channel(1);
+        let identify_ratelimiter = IdentifyRatelimiter::new(&config, Arc::clone(&redis));
+
+        let presence = Self::fetch_presence(&redis)
+            .await
+            .unwrap_or_else(|| options.presence.clone());
+        let (presence, _) = watch::channel(presence);
 
         Self {
             config: Arc::new(config),
             options,
+            max_concurrency,
             session_store,
+            checkpointer,
             redis,
+            identify_ratelimiter: Arc::new(identify_ratelimiter),
             event_forwarder,
+            event_filters: Arc::new(event_filters),
             shutdown_tx,
+            shard_command_channels: RwLock::new(HashMap::new()),
+            member_request_nonce: AtomicU64::new(0),
+            registry: ShardRegistry::default(),
+            presence,
+            ready: AtomicBool::new(false),
+        }
+    }
+
+    // Returns the presence last published to presence_updates::KEY, if there is one
+    async fn fetch_presence(redis: &Pool) -> Option<StatusUpdate> {
+        let res: Result<Option<String>> = async {
+            let mut conn = redis.get().await?;
+            let payload = cmd("GET")
+                .arg(presence_updates::CURRENT_KEY)
+                .query_async(&mut conn)
+                .await?;
+            Ok(payload)
+        }
+        .await;
+
+        let payload = match res {
+            Ok(Some(payload)) => payload,
+            Ok(None) => return None,
+            Err(e) => {
+                warn!(error = %e, "Failed to fetch current presence, using the default");
+                return None;
+            }
+        };
+
+        match serde_json::from_str::<presence_updates::Payload>(&payload) {
+            Ok(payload) => Some(to_status_update(payload)),
+            Err(e) => {
+                warn!(error = %e, "Failed to decode current presence, using the default");
+                None
+            }
         }
     }
 
-    fn build_shard(&self, shard_id: u16, ready_tx: Option<oneshot::Sender<()>>) -> Shard<T> {
+    /// Applies presences published to `presence_updates::KEY` to every shard, and to shards that
+    /// start later. If the subscription is lost, the sharder resubscribes and reloads the current
+    /// presence, in case an update was missed.
+    pub async fn listen_presence_updates(self: Arc<Self>) -> Result<()> {
+        let pubsub = self.subscribe_presence_updates().await?;
+
+        tokio::spawn(Arc::clone(&self).broadcast_presence());
+
+        tokio::spawn(async move {
+            let mut pubsub = Some(pubsub);
+
+            loop {
+                let conn = match pubsub.take() {
+                    Some(conn) => conn,
+                    None => match self.subscribe_presence_updates().await {
+                        Ok(conn) => {
+                            info!("Resubscribed to presence updates");
+
+                            if let Some(presence) = Self::fetch_presence(&self.redis).await {
+                                self.presence.send_replace(presence);
+                            }
+
+                            conn
+                        }
+                        Err(e) => {
+                            warn!(error = %e, "Failed to resubscribe to presence updates");
+                            sleep(PRESENCE_RESUBSCRIBE_DELAY).await;
+                            continue;
+                        }
+                    },
+                };
+
+                let mut stream = conn.into_on_message();
+                while let Some(m) = stream.next().await {
+                    match serde_json::from_slice::<presence_updates::Payload>(m.get_payload_bytes())
+                    {
+                        Ok(decoded) => {
+                            info!(activity = %decoded.activity, "Received presence update");
+                            self.presence.send_replace(to_status_update(decoded));
+                        }
+                        Err(e) => error!(error = %e, "Failed to decode presence update"),
+                    }
+                }
+
+                warn!("Presence update subscription closed, resubscribing");
+                sleep(PRESENCE_RESUBSCRIBE_DELAY).await;
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn subscribe_presence_updates(&self) -> Result<redis::aio::PubSub> {
+        let mut conn = redis::Client::open(self.config.get_redis_uri())?
+            .get_async_connection()
+            .await?
+            .into_pubsub();
+
+        conn.subscribe(presence_updates::KEY).await?;
+        Ok(conn)
+    }
+
+    // Sends the latest presence to every running shard
+    async fn broadcast_presence(self: Arc<Self>) {
+        let manager = &*self;
+        coalesce_presence(
+            self.presence.subscribe(),
+            PRESENCE_UPDATE_INTERVAL,
+            move |presence| manager.send_presence(presence),
+        )
+        .await
+    }
+
+    async fn send_presence(&self, presence: StatusUpdate) {
+        let channels: Vec<(u16, mpsc::Sender<InternalCommand>)> = self
+            .shard_command_channels
+            .read()
+            .await
+            .iter()
+            .map(|(&shard_id, command_tx)| (shard_id, command_tx.clone()))
+            .collect();
+
+        debug!(shards = channels.len(), "Broadcasting presence update");
+
+        for (shard_id, command_tx) in channels {
+            let command = InternalCommand::StatusUpdate {
+                status: presence.clone(),
+            };
+
+            if command_tx.send(command).await.is_err() {
+                debug!(%shard_id, "Shard disconnected before presence update");
+            }
+        }
+    }
+
+    // Gets the number of identify buckets from /gateway/bot, falling back to LARGE_SHARDING_BUCKETS
+    // if Discord can't be reached
+    async fn fetch_max_concurrency(config: &Config, options: &Options) -> u16 {
+        let url = config
+            .gateway_bot_url
+            .as_deref()
+            .unwrap_or(DEFAULT_GATEWAY_BOT_URL);
+
+        let gateway_bot = match GatewayBot::fetch(url, &options.token).await {
+            Ok(gateway_bot) => gateway_bot,
+            Err(e) => {
+                warn!(
+                    error = %e,
+                    large_sharding_buckets = options.large_sharding_buckets,
+                    "Failed to fetch /gateway/bot, falling back to LARGE_SHARDING_BUCKETS"
+                );
+                return options.large_sharding_buckets.max(1);
+            }
+        };
+
+        let limit = &gateway_bot.session_start_limit;
+        info!(
+            recommended_shards = gateway_bot.shards,
+            max_concurrency = limit.max_concurrency,
+            remaining_sessions = limit.remaining,
+            "Fetched /gateway/bot"
+        );
+
+        if options.shard_count.total < gateway_bot.shards {
+            warn!(
+                total = options.shard_count.total,
+                recommended = gateway_bot.shards,
+                "Shard count is lower than recommended by Discord"
+            );
+        }
+
+        let cluster_size = options.shard_count.highest - options.shard_count.lowest;
+        if limit.remaining < cluster_size.into() {
+            warn!(
+                remaining = limit.remaining,
+                reset_after = limit.reset_after,
+                "Not enough session starts remaining to identify every shard in the cluster"
+            );
+        }
+
+        limit.max_concurrency.max(1)
+    }
+
+    /// Requests members of a guild over the gateway, through the shard that the guild belongs to
+    pub async fn request_guild_members(
+        &self,
+        guild_id: Snowflake,
+        query: Option<String>,
+        user_ids: Option<Vec<Snowflake>>,
+    ) -> Result<GuildMembers> {
+        let shard_id = ((guild_id.0 >> 22) % self.options.shard_count.total as u64) as u16;
+        let nonce = self
+            .member_request_nonce
+            .fetch_add(1, Ordering::Relaxed)
+            .to_string();
+
+        let (reply, rx) = oneshot::channel();
+        let command = InternalCommand::RequestGuildMembers {
+            guild_id,
+            query,
+            user_ids,
+            nonce,
+            reply,
+        };
+
+        {
+            let channels = self.shard_command_channels.read().await;
+            let command_tx = channels.get(&shard_id).ok_or_else(|| {
+                GatewayError::custom(format!("shard {shard_id} is not running on this cluster"))
+            })?;
+
+            command_tx
+                .send(command)
+                .await
+                .map_err(|_| GatewayError::custom(format!("shard {shard_id} has disconnected")))?;
+        }
+
+        // The shard also gives up on the request, once it next checks for abandoned requests
+        timeout(MEMBER_REQUEST_TIMEOUT, rx)
+            .await
+            .map_err(|_| GatewayError::custom("timed out waiting for guild members"))??
+    }
+
+    fn build_shard(
+        &self,
+        shard_id: u16,
+        ready_tx: Option<oneshot::Sender<()>>,
+        command_rx: mpsc::Receiver<InternalCommand>,
+    ) -> Shard<T> {
         let shard_info = ShardInfo::new(shard_id, self.options.shard_count.total);
 
         let identify = Identify::new(
             self.options.token.clone().into_string(),
             None,
             shard_info,
-            Some(self.options.presence.clone()),
-            super::get_intents(),
+            Some(self.presence.borrow().clone()),
+            self.config.get_gateway_intents(),
         );
 
         Shard::new(
             Arc::clone(&self.config),
             identify,
-            self.options.large_sharding_buckets,
-            Arc::clone(&self.redis),
+            self.max_concurrency,
+            Arc::clone(&self.identify_ratelimiter),
             self.options.user_id,
             Arc::clone(&self.event_forwarder),
+            Arc::clone(&self.event_filters),
             ready_tx,
             self.shutdown_tx.subscribe(),
-            #[cfg(feature = "whitelabel")]
             command_rx,
+            self.registry
+                .handle(shard_id.into(), self.options.user_id, shard_id),
+            self.checkpointer.sender(shard_id.into()),
         )
     }
 
+    // Returns false if the shard is not running on this cluster
+    async fn send_command(&self, id: u64, command: InternalCommand) -> Result<bool> {
+        let Ok(shard_id) = u16::try_from(id) else {
+            return Ok(false);
+        };
+
+        let channels = self.shard_command_channels.read().await;
+        let Some(command_tx) = channels.get(&shard_id) else {
+            return Ok(false);
+        };
+
+        command_tx
+            .send(command)
+            .await
+            .map_err(|_| GatewayError::custom(format!("shard {shard_id} has disconnected")))?;
+
+        Ok(true)
+    }
+
     #[tracing::instrument(skip(self, resume_data, ready_tx))]
     async fn start_shard(
         &self,
@@ -81,10 +376,24 @@ impl<T: EventForwarder> PublicShardManager<T> {
         resume_data: Option<SessionData>,
         ready_tx: Option<oneshot::Sender<()>>,
     ) -> (bool, Option<SessionData>) {
-        let shard = self.build_shard(shard_id, ready_tx);
+        let (command_tx, command_rx) = mpsc::channel(4);
+        let shard = self.build_shard(shard_id, ready_tx, command_rx);
+
+        {
+            self.shard_command_channels
+                .write()
+                .await
+                .insert(shard_id, command_tx);
+        }
+
+        let res = shard.connect(resume_data.clone()).await;
+
+        {
+            self.shard_command_channels.write().await.remove(&shard_id);
+        }
 
         // TODO: Skip ready_rx await on error
-        match shard.connect(resume_data.clone()).await {
+        match res {
             Ok(session_data) => {
                 info!("Shard exited normally");
                 return (true, session_data);
@@ -104,52 +413,84 @@ impl<T: EventForwarder> PublicShardManager<T> {
             }
         }
     }
-}
 
-#[async_trait]
-impl<T: EventForwarder> ShardManager for PublicShardManager<T> {
+    // Spawns a task that runs the shard, reconnecting it until it exits with a fatal error, and
+    // waits until the shard has loaded its guilds or the ready timeout expires
     #[tracing::instrument(skip(self))]
-    async fn connect(self: Arc<Self>) {
-        for shard_id in self.options.shard_count.lowest..self.options.shard_count.highest {
-            let (ready_tx, ready_rx) = oneshot::channel::<()>();
-            let sm = Arc::clone(&self);
+    async fn start_shard_and_wait(self: Arc<Self>, shard_id: u16) {
+        let (ready_tx, ready_rx) = oneshot::channel::<()>();
 
-            debug!("Fetching resume data");
-            let resume_data = match self.session_store.get(shard_id.into()).await {
-                Ok(data) => data,
-                Err(e) => {
-                    error!(error = %e, "Failed to get session data"); // Continue
-                    None
+        debug!("Fetching resume data");
+        let resume_data = match self.session_store.get(shard_id.into()).await {
+            Ok(data) => data,
+            Err(e) => {
+                error!(error = %e, "Failed to get session data"); // Continue
+                None
+            }
+        };
+
+        let sm = Arc::clone(&self);
+        tokio::spawn(async move {
+            let mut ready_tx = Some(ready_tx);
+            let mut resume_data = resume_data;
+
+            loop {
+                let (reconnect, new_resume_data) = sm
+                    .as_ref()
+                    .start_shard(shard_id, resume_data.clone(), ready_tx.take())
+                    .await;
+
+                if !reconnect {
+                    warn!(%shard_id, "Shard exited with fatal error, not restarting");
+                    break;
                 }
-            };
 
-            tokio::spawn(async move {
-                let mut ready_tx = Some(ready_tx);
-                let mut resume_data = resume_data;
+                resume_data = new_resume_data;
 
-                loop {
-                    let (reconnect, new_resume_data) = sm
-                        .as_ref()
-                        .start_shard(shard_id, resume_data.clone(), ready_tx.take())
-                        .await;
+                sleep(Duration::from_millis(500)).await;
+            }
+        });
 
-                    if !reconnect {
-                        warn!(%shard_id, "Shard exited with fatal error, not restarting");
-                        break;
-                    }
+        let ready_timeout = Duration::from_secs(self.config.shard_ready_timeout);
+        match timeout(ready_timeout, ready_rx).await {
+            Ok(Ok(_)) => info!(shard_id = %shard_id, "Loaded guilds"),
+            Ok(Err(e)) => error!(shard_id = %shard_id, error = %e, "Error reading ready rx"),
+            Err(_) => warn!(
+                shard_id = %shard_id,
+                timeout = ?ready_timeout,
+                "Shard did not become ready in time, starting the next shard"
+            ),
+        }
+    }
+}
 
-                    resume_data = new_resume_data;
+#[async_trait]
+impl<T: EventForwarder, S: SessionStore> ShardManager for PublicShardManager<T, S> {
+    #[tracing::instrument(skip(self))]
+    async fn connect(self: Arc<Self>) {
+        // Shards in different identify buckets can be started in parallel, while shards in the
+        // same bucket are started one at a time
+        let buckets = self.max_concurrency;
+        let handles: Vec<_> = (0..buckets)
+            .map(|bucket| {
+                let sm = Arc::clone(&self);
 
-                    sleep(Duration::from_millis(500)).await;
-                }
-            });
+                tokio::spawn(async move {
+                    let shard_ids = sm.options.shard_count.lowest..sm.options.shard_count.highest;
+                    for shard_id in shard_ids.filter(|shard_id| shard_id % buckets == bucket) {
+                        Arc::clone(&sm).start_shard_and_wait(shard_id).await;
+                    }
+                })
+            })
+            .collect();
 
-            match ready_rx.await {
-                Ok(_) => info!(shard_id = %shard_id, "Loaded guilds"),
-                Err(e) => error!(shard_id = %shard_id, error = %e, "Error reading ready rx"),
+        for handle in handles {
+            if let Err(e) = handle.await {
+                error!(error = %e, "Identify bucket task failed");
             }
         }
 
+        self.ready.store(true, Ordering::Relaxed);
         File::create("/tmp/ready").await.unwrap(); // panic if can't create
         info!("Reported readiness to probe");
     }
@@ -187,6 +528,9 @@ impl<T: EventForwarder> ShardManager for PublicShardManager<T> {
             sessions.insert(identifier.shard_id.into(), session_data);
         }
 
+        // Checkpoints are older than the sessions reported by the shards, so must be written first
+        self.checkpointer.flush().await;
+
         if let Err(e) = self.session_store.set_bulk(sessions).await {
             error!(error = %e, "Failed to save session data");
         }
@@ -195,4 +539,92 @@ impl<T: EventForwarder> ShardManager for PublicShardManager<T> {
             error!(error = %e, "Failed to flush event forwarder");
         }
     }
+
+    fn shard_statuses(&self) -> Vec<ShardStatus> {
+        self.registry.list()
+    }
+
+    fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    async fn reconnect_shard(&self, id: u64) -> Result<bool> {
+        self.send_command(id, InternalCommand::Reconnect { resume: true })
+            .await
+    }
+
+    async fn invalidate_session(&self, id: u64) -> Result<()> {
+        self.session_store.invalidate(id).await?;
+        self.send_command(id, InternalCommand::Reconnect { resume: false })
+            .await?;
+        Ok(())
+    }
+}
+
+// Calls `broadcast` with the latest presence whenever it changes, waiting `interval` between
+// broadcasts. Presences replaced while waiting are never broadcast, as only the latest matters.
+async fn coalesce_presence<F, Fut>(
+    mut presence_rx: watch::Receiver<StatusUpdate>,
+    interval: Duration,
+    mut broadcast: F,
+) where
+    F: FnMut(StatusUpdate) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    while presence_rx.changed().await.is_ok() {
+        let presence = presence_rx.borrow_and_update().clone();
+        broadcast(presence).await;
+
+        sleep(interval).await;
+    }
+}
+
+fn to_status_update(payload: presence_updates::Payload) -> StatusUpdate {
+    StatusUpdate::new(payload.activity_type, payload.activity, payload.status)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use model::user::{ActivityType, StatusType};
+    use parking_lot::Mutex;
+
+    fn presence(activity: &str) -> StatusUpdate {
+        StatusUpdate::new(ActivityType::Game, activity.to_owned(), StatusType::Online)
+    }
+
+    async fn wait_for_len(broadcasts: &Mutex<Vec<String>>, len: usize) {
+        while broadcasts.lock().len() < len {
+            sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_coalesces_presence_updates() {
+        let (presence_tx, presence_rx) = watch::channel(presence("initial"));
+        let broadcasts: Arc<Mutex<Vec<String>>> = Arc::default();
+
+        let recorded = Arc::clone(&broadcasts);
+        tokio::spawn(coalesce_presence(
+            presence_rx,
+            Duration::from_millis(100),
+            move |presence| {
+                recorded.lock().push(presence.game.unwrap().name);
+                async {}
+            },
+        ));
+
+        // Sent straight away
+        presence_tx.send_replace(presence("first"));
+        wait_for_len(&broadcasts, 1).await;
+
+        // Only the latest of the updates made during the interval is sent
+        for activity in ["second", "third", "fourth"] {
+            presence_tx.send_replace(presence(activity));
+        }
+        wait_for_len(&broadcasts, 2).await;
+
+        sleep(Duration::from_millis(150)).await;
+        assert_eq!(*broadcasts.lock(), ["first", "fourth"]);
+    }
 }
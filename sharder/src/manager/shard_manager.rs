@@ -1,9 +1,24 @@
 use async_trait::async_trait;
 
+use crate::{Result, ShardStatus};
 use std::sync::Arc;
 
+/// Shards are addressed by the ID their session is stored under: the shard ID for the public
+/// bot, and the bot ID for whitelabel bots.
 #[async_trait]
-pub trait ShardManager {
+pub trait ShardManager: Send + Sync {
     async fn connect(self: Arc<Self>);
     async fn shutdown(self: Arc<Self>);
+
+    fn shard_statuses(&self) -> Vec<ShardStatus>;
+
+    /// Returns true once the initial connection of every shard has been started
+    fn is_ready(&self) -> bool;
+
+    /// Closes the shard's connection, after which it will reconnect and resume. Returns false if
+    /// the shard is not running.
+    async fn reconnect_shard(&self, id: u64) -> Result<bool>;
+
+    /// Deletes the shard's stored session. If the shard is running, it reconnects and IDENTIFYs.
+    async fn invalidate_session(&self, id: u64) -> Result<()>;
 }
@@ -0,0 +1,197 @@
+use database::BotStatus;
+use model::user::{ActivityType, StatusType, StatusUpdate};
+use model::Snowflake;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Presence updates count towards the gateway rate limit of the shard
+const MIN_ROTATION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The status of each whitelabel bot, and its position in the rotation of the bot's activities
+#[derive(Default)]
+pub(crate) struct StatusRotations {
+    bots: Mutex<HashMap<Snowflake, Rotation>>,
+}
+
+struct Rotation {
+    status: BotStatus,
+    index: usize,
+    next_at: Instant,
+}
+
+impl Rotation {
+    fn current(&self) -> StatusUpdate {
+        let (activity, activity_type) = self.status.activities[self.index].clone();
+        StatusUpdate::new(activity_type, activity, self.status.presence)
+    }
+
+    fn interval(&self) -> Duration {
+        self.status.rotation_interval.max(MIN_ROTATION_INTERVAL)
+    }
+}
+
+impl StatusRotations {
+    /// Restarts the rotation from the first activity
+    pub fn set(&self, bot_id: Snowflake, status: Option<BotStatus>) {
+        let status = match status {
+            // None of the activities could be read, but the presence still applies
+            Some(status) if status.activities.is_empty() => BotStatus {
+                presence: status.presence,
+                ..default_status()
+            },
+            Some(status) => status,
+            None => default_status(),
+        };
+
+        let mut rotation = Rotation {
+            status,
+            index: 0,
+            next_at: Instant::now(),
+        };
+        rotation.next_at += rotation.interval();
+
+        self.bots.lock().insert(bot_id, rotation);
+    }
+
+    /// Restarts the rotation of a bot that is running, and returns its new status. Returns None
+    /// if the bot has stopped.
+    pub fn replace(&self, bot_id: Snowflake, status: Option<BotStatus>) -> Option<StatusUpdate> {
+        if !self.is_running(bot_id) {
+            return None;
+        }
+
+        self.set(bot_id, status);
+        Some(self.current(bot_id))
+    }
+
+    pub fn is_running(&self, bot_id: Snowflake) -> bool {
+        self.bots.lock().contains_key(&bot_id)
+    }
+
+    pub fn remove(&self, bot_id: Snowflake) {
+        self.bots.lock().remove(&bot_id);
+    }
+
+    /// The status to identify with
+    pub fn current(&self, bot_id: Snowflake) -> StatusUpdate {
+        match self.bots.lock().get(&bot_id) {
+            Some(rotation) => rotation.current(),
+            None => {
+                let (activity, activity_type) = default_status().activities.remove(0);
+                StatusUpdate::new(activity_type, activity, StatusType::Online)
+            }
+        }
+    }
+
+    /// Moves every bot that is due on to its next activity, and returns the new statuses
+    pub fn advance(&self, now: Instant) -> Vec<(Snowflake, StatusUpdate)> {
+        let mut bots = self.bots.lock();
+
+        bots.iter_mut()
+            .filter(|(_, rotation)| rotation.status.activities.len() > 1 && rotation.next_at <= now)
+            .map(|(&bot_id, rotation)| {
+                rotation.index = (rotation.index + 1) % rotation.status.activities.len();
+                rotation.next_at = now + rotation.interval();
+                (bot_id, rotation.current())
+            })
+            .collect()
+    }
+}
+
+fn default_status() -> BotStatus {
+    BotStatus {
+        presence: StatusType::Online,
+        activities: vec![("to /help".to_owned(), ActivityType::Listening)],
+        rotation_interval: Duration::ZERO,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn activity(update: &StatusUpdate) -> &str {
+        &update.game.as_ref().unwrap().name
+    }
+
+    #[test]
+    fn test_advance() {
+        let rotations = StatusRotations::default();
+        let bot_id = Snowflake(1);
+
+        rotations.set(
+            bot_id,
+            Some(BotStatus {
+                presence: StatusType::Idle,
+                activities: vec![
+                    ("first".to_owned(), ActivityType::Game),
+                    ("second".to_owned(), ActivityType::Watching),
+                ],
+                rotation_interval: Duration::from_secs(120),
+            }),
+        );
+
+        let start = Instant::now();
+        assert_eq!(activity(&rotations.current(bot_id)), "first");
+        assert!(rotations.advance(start).is_empty());
+
+        let updates = rotations.advance(start + Duration::from_secs(121));
+        assert_eq!(updates.len(), 1);
+        assert_eq!(activity(&updates[0].1), "second");
+        assert_eq!(updates[0].1.status, StatusType::Idle);
+        assert_eq!(activity(&rotations.current(bot_id)), "second");
+
+        let updates = rotations.advance(start + Duration::from_secs(242));
+        assert_eq!(activity(&updates[0].1), "first");
+    }
+
+    #[test]
+    fn test_advance_single_activity() {
+        let rotations = StatusRotations::default();
+        rotations.set(Snowflake(1), None);
+
+        let later = Instant::now() + Duration::from_secs(3600);
+        assert!(rotations.advance(later).is_empty());
+        assert_eq!(activity(&rotations.current(Snowflake(1))), "to /help");
+    }
+
+    #[test]
+    fn test_keeps_presence_without_activities() {
+        let rotations = StatusRotations::default();
+        rotations.set(
+            Snowflake(1),
+            Some(BotStatus {
+                presence: StatusType::Idle,
+                activities: Vec::new(),
+                rotation_interval: Duration::from_secs(120),
+            }),
+        );
+
+        let current = rotations.current(Snowflake(1));
+        assert_eq!(activity(&current), "to /help");
+        assert_eq!(current.status, StatusType::Idle);
+    }
+
+    #[test]
+    fn test_min_interval() {
+        let rotations = StatusRotations::default();
+        rotations.set(
+            Snowflake(1),
+            Some(BotStatus {
+                presence: StatusType::Online,
+                activities: vec![
+                    ("first".to_owned(), ActivityType::Game),
+                    ("second".to_owned(), ActivityType::Game),
+                ],
+                rotation_interval: Duration::from_secs(1),
+            }),
+        );
+
+        let start = Instant::now();
+        assert!(rotations
+            .advance(start + Duration::from_secs(30))
+            .is_empty());
+        assert_eq!(rotations.advance(start + Duration::from_secs(61)).len(), 1);
+    }
+}
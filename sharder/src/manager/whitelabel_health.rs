@@ -0,0 +1,176 @@
+use chrono::Utc;
+use common::whitelabel_health::{self, BotState};
+use database::{BotHealth, Database};
+use deadpool_redis::redis::cmd;
+use deadpool_redis::Pool;
+use model::Snowflake;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::Result;
+
+// Length of the "last_error" column
+const MAX_ERROR_LENGTH: usize = 255;
+
+/// Records the connection state of each whitelabel bot in the `whitelabel_health` table, and
+/// publishes every change to `whitelabel_health::KEY`. The health of connected bots is also
+/// refreshed with `refresh_connected`. Failed writes are logged, and never stop
+/// the bot.
+pub(crate) struct HealthReporter {
+    database: Arc<Database>,
+    redis: Arc<Pool>,
+    bots: Mutex<HashMap<Snowflake, BotHealth>>,
+}
+
+impl HealthReporter {
+    pub fn new(database: Arc<Database>, redis: Arc<Pool>) -> Self {
+        Self {
+            database,
+            redis,
+            bots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The bots that have been reported since they were last removed
+    pub fn bot_ids(&self) -> Vec<Snowflake> {
+        self.bots.lock().keys().copied().collect()
+    }
+
+    pub async fn connecting(&self, bot_id: Snowflake) {
+        self.update(bot_id, BotState::Connecting, |_| {}).await;
+    }
+
+    pub async fn connected(&self, bot_id: Snowflake, guild_count: usize, message_content: bool) {
+        self.update(bot_id, BotState::Connected, |health| {
+            health.last_connected = Some(Utc::now());
+            health.guild_count = guild_count as i32;
+            health.message_content = message_content;
+        })
+        .await;
+    }
+
+    /// The bot exited and is about to reconnect, after an error if there was one
+    pub async fn reconnecting(&self, bot_id: Snowflake, error: Option<String>) {
+        self.update(bot_id, BotState::Reconnecting, |health| {
+            health.reconnect_count += 1;
+
+            if let Some(error) = error {
+                health.last_error = Some(truncate(error));
+            }
+        })
+        .await;
+    }
+
+    pub async fn stopped(&self, bot_id: Snowflake) {
+        self.update(bot_id, BotState::Stopped, |_| {}).await;
+    }
+
+    pub async fn quarantined(&self, bot_id: Snowflake, error: String) {
+        self.update(bot_id, BotState::Quarantined, |health| {
+            health.last_error = Some(truncate(error));
+        })
+        .await;
+    }
+
+    /// The bot's row is deleted along with the bot, so the change is only published
+    pub async fn removed(&self, bot_id: Snowflake) {
+        let health = self.bots.lock().remove(&bot_id);
+        let mut health = health.unwrap_or_else(|| new_health(bot_id));
+        health.state = BotState::Removed.as_str().to_owned();
+
+        if let Err(e) = self.publish(bot_id, BotState::Removed, &health).await {
+            warn!(error = %e, %bot_id, "Failed to publish whitelabel bot health");
+        }
+    }
+
+    /// Refreshes the health of the connected bots, so that they aren't treated as down once
+    /// `whitelabel_health::CONNECTED_TTL` passes
+    pub async fn refresh_connected(&self) {
+        let connected: Vec<BotHealth> = self
+            .bots
+            .lock()
+            .values()
+            .filter(|health| health.state == BotState::Connected.as_str())
+            .cloned()
+            .collect();
+
+        if connected.is_empty() {
+            return;
+        }
+
+        let bot_ids: Vec<Snowflake> = connected
+            .iter()
+            .map(|health| Snowflake(health.bot_id as u64))
+            .collect();
+
+        if let Err(e) = self.database.whitelabel_health.touch(&bot_ids).await {
+            warn!(error = %e, "Failed to refresh whitelabel bot health");
+        }
+
+        for (bot_id, health) in bot_ids.into_iter().zip(connected) {
+            if let Err(e) = self.publish(bot_id, BotState::Connected, &health).await {
+                warn!(error = %e, %bot_id, "Failed to publish whitelabel bot health");
+            }
+        }
+    }
+
+    async fn update(&self, bot_id: Snowflake, state: BotState, f: impl FnOnce(&mut BotHealth)) {
+        let health = {
+            let mut bots = self.bots.lock();
+            let health = bots.entry(bot_id).or_insert_with(|| new_health(bot_id));
+
+            health.state = state.as_str().to_owned();
+            f(health);
+            health.clone()
+        };
+
+        if let Err(e) = self.database.whitelabel_health.set(&health).await {
+            warn!(error = %e, %bot_id, "Failed to write whitelabel bot health");
+        }
+
+        if let Err(e) = self.publish(bot_id, state, &health).await {
+            warn!(error = %e, %bot_id, "Failed to publish whitelabel bot health");
+        }
+    }
+
+    async fn publish(&self, bot_id: Snowflake, state: BotState, health: &BotHealth) -> Result<()> {
+        let payload = whitelabel_health::Payload {
+            bot_id,
+            state,
+            last_connected: health.last_connected.map(|time| time.timestamp()),
+            reconnect_count: health.reconnect_count as u32,
+            guild_count: health.guild_count as u32,
+            message_content: health.message_content,
+            last_error: health.last_error.clone(),
+        };
+
+        let mut conn = self.redis.get().await?;
+        cmd("PUBLISH")
+            .arg(&[whitelabel_health::KEY, &serde_json::to_string(&payload)?])
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+fn new_health(bot_id: Snowflake) -> BotHealth {
+    BotHealth {
+        bot_id: bot_id.0 as i64,
+        state: BotState::Connecting.as_str().to_owned(),
+        last_connected: None,
+        reconnect_count: 0,
+        guild_count: 0,
+        message_content: false,
+        last_error: None,
+    }
+}
+
+pub(crate) fn truncate(error: String) -> String {
+    match error.char_indices().nth(MAX_ERROR_LENGTH) {
+        Some((i, _)) => error[..i].to_owned(),
+        None => error,
+    }
+}
@@ -1,58 +1,107 @@
 use async_trait::async_trait;
 
+use super::backoff::{Backoff, FailureWindow};
+use super::lease::{self, BotAssignment, LeaseState, Leases, RedisLeases};
+use super::status_rotation::StatusRotations;
+use super::whitelabel_health::{self, HealthReporter};
 use super::ShardManager;
 
 use crate::gateway::payloads::Identify;
-use crate::gateway::{Shard, ShardInfo};
+use crate::gateway::{CloseKind, IdentifyRatelimiter, Intents, Shard, ShardInfo};
 
-use crate::gateway::event_forwarding::EventForwarder;
+use crate::gateway::event_forwarding::{EventFilters, EventForwarder};
 use crate::{
-    Config, GatewayError, InternalCommand, RedisSessionStore, SessionData, SessionStore,
-    ShardIdentifier,
+    Config, GatewayError, InternalCommand, Result, SessionCheckpointer, SessionData, SessionStore,
+    ShardIdentifier, ShardRegistry, ShardStatus,
 };
-use common::token_change;
+use common::{token_change, whitelabel_retry};
 use database::{Database, WhitelabelBot};
 use deadpool_redis::redis;
 use deadpool_redis::Pool;
 use futures::StreamExt;
-use model::user::{ActivityType, StatusType, StatusUpdate};
 use model::Snowflake;
+use parking_lot::Mutex;
 use std::collections::HashMap;
 use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::{broadcast, mpsc, RwLock};
-use tokio::time::{sleep, timeout};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, oneshot, RwLock};
+use tokio::time::{interval, sleep, timeout, MissedTickBehavior};
 use tracing::{error, info, warn};
 
-pub struct WhitelabelShardManager<T: EventForwarder> {
+// How often the statuses of bots are checked for rotation
+const STATUS_ROTATION_TICK: Duration = Duration::from_secs(5);
+
+pub struct WhitelabelShardManager<T: EventForwarder, S: SessionStore> {
     config: Arc<Config>,
     database: Arc<Database>,
     redis: Arc<Pool>,
-    session_store: RedisSessionStore,
+    identify_ratelimiter: Arc<IdentifyRatelimiter>,
+    session_store: Arc<S>,
+    checkpointer: SessionCheckpointer,
     event_forwarder: Arc<T>,
+    event_filters: Arc<EventFilters>,
     shutdown_tx: broadcast::Sender<mpsc::Sender<(ShardIdentifier, Option<SessionData>)>>,
     shard_command_channels: RwLock<HashMap<Snowflake, mpsc::Sender<InternalCommand>>>,
+    registry: ShardRegistry,
+    health: HealthReporter,
+    // Released by sending to the channel
+    quarantined: Mutex<HashMap<Snowflake, oneshot::Sender<()>>>,
+    // None unless BOT_ASSIGNMENT is lease
+    leases: Option<Leases>,
+    statuses: StatusRotations,
+    ready: AtomicBool,
 }
 
-impl<T: EventForwarder> WhitelabelShardManager<T> {
+impl<T: EventForwarder, S: SessionStore> WhitelabelShardManager<T, S> {
     pub fn new(
         config: Config,
         database: Arc<Database>,
         redis: Arc<Pool>,
-        session_store: RedisSessionStore,
+        session_store: S,
         event_forwarder: Arc<T>,
+        event_filters: EventFilters,
     ) -> Self {
+        super::warn_intent_mismatches(&config);
+
+        let session_store = Arc::new(session_store);
+        let checkpointer = SessionCheckpointer::start(
+            Arc::clone(&session_store),
+            Duration::from_secs(config.session_checkpoint_interval),
+        );
+
         let (shutdown_tx, _) = broadcast::channel(1);
 
+        let health = HealthReporter::new(Arc::clone(&database), Arc::clone(&redis));
+        let identify_ratelimiter = IdentifyRatelimiter::new(&config, Arc::clone(&redis));
+
+        let leases = match config.bot_assignment {
+            BotAssignment::Static => None,
+            BotAssignment::Lease => {
+                let ttl = Duration::from_secs(config.lease_ttl);
+                let store = RedisLeases::new(Arc::clone(&redis), instance_id(), ttl);
+                Some(Leases::new(store, ttl))
+            }
+        };
+
         WhitelabelShardManager {
             config: Arc::new(config),
             database,
             redis,
+            identify_ratelimiter: Arc::new(identify_ratelimiter),
             session_store,
+            checkpointer,
             event_forwarder,
+            event_filters: Arc::new(event_filters),
             shutdown_tx,
             shard_command_channels: RwLock::new(HashMap::new()),
+            registry: ShardRegistry::default(),
+            health,
+            quarantined: Mutex::new(HashMap::new()),
+            leases,
+            statuses: StatusRotations::default(),
+            ready: AtomicBool::new(false),
         }
     }
 
@@ -62,22 +111,14 @@ impl<T: EventForwarder> WhitelabelShardManager<T> {
         let sm = Arc::clone(&self);
         tokio::spawn(async move {
             // retrieve bot status
-            let (status, status_type) = match self.database.whitelabel_status.get(bot_id).await {
-                Ok((status, Some(status_type))) => Some((status, status_type)),
-                Ok((_, None)) => {
-                    eprintln!("Bot {} has invalid status type", bot_id);
-                    None
-                }
-                Err(database::sqlx::Error::RowNotFound) => None,
+            let status = match self.database.whitelabel_status.get_status(bot_id).await {
+                Ok(status) => status,
                 Err(e) => {
-                    eprintln!(
-                        "Error occurred while retrieving status for {}: {:?}",
-                        bot.bot_id, e
-                    );
+                    error!(bot_id = %bot_id, error = %e, "Error retrieving bot status");
                     None
                 }
-            }
-            .unwrap_or_else(|| ("to /help".to_owned(), ActivityType::Listening));
+            };
+            self.statuses.set(bot_id, status);
 
             let mut resume_data = sm
                 .session_store
@@ -85,30 +126,56 @@ impl<T: EventForwarder> WhitelabelShardManager<T> {
                 .await
                 .expect("Failed to fetch session data"); // TODO: Log, not panic
 
+            // Reduced if Discord rejects the privileged intents, until the sharder restarts
+            let mut intents = self.config.get_gateway_intents();
+
+            let mut backoff = Backoff::new(
+                Duration::from_millis(self.config.reconnect_backoff_base),
+                Duration::from_millis(self.config.reconnect_backoff_max),
+            );
+            let mut failures = FailureWindow::new(
+                self.config.quarantine_threshold,
+                Duration::from_secs(self.config.quarantine_window),
+            );
+
             loop {
+                match self.lease_state(bot_id) {
+                    Some(LeaseState::Held) => {}
+                    Some(LeaseState::HandingOver) => {
+                        self.hand_over(bot_id, resume_data.take()).await;
+                        break;
+                    }
+                    None => {
+                        info!(bot_id = %bot_id, "Lease lost, stopping");
+                        break;
+                    }
+                }
+
                 let shard_info = ShardInfo::new(0, 1);
-                let presence = StatusUpdate::new(status_type, status.clone(), StatusType::Online);
-                let identify = Identify::new(
-                    bot.token.clone(),
-                    None,
-                    shard_info,
-                    Some(presence),
-                    super::get_intents(),
-                );
+                // Continues the rotation after a reconnect
+                let presence = self.statuses.current(bot_id);
+                let identify =
+                    Identify::new(bot.token.clone(), None, shard_info, Some(presence), intents);
 
                 let (command_tx, command_rx) = mpsc::channel(4);
+                let (ready_tx, ready_rx) = oneshot::channel();
+                let status = self.registry.handle(bot_id.0, bot_id, 0);
 
                 let shard = Shard::new(
                     self.config.clone(),
                     identify,
                     1,
-                    Arc::clone(&self.redis),
+                    Arc::clone(&self.identify_ratelimiter),
                     bot_id,
                     Arc::clone(&self.event_forwarder),
-                    None,
+                    Arc::clone(&self.event_filters),
+                    Some(ready_tx),
                     self.shutdown_tx.subscribe(),
-                    Arc::clone(&self.database),
                     command_rx,
+                    status.clone(),
+                    self.checkpointer.sender(bot_id.0),
+                    Arc::clone(&self.redis),
+                    Arc::clone(&self.database),
                 );
 
                 // Validate bot still exists
@@ -116,16 +183,19 @@ impl<T: EventForwarder> WhitelabelShardManager<T> {
                     Ok(Some(_)) => (),
                     Ok(None) => {
                         info!("Bot no longer exists, stopping");
+                        self.health.removed(bot_id).await;
+                        self.drop_lease(bot_id).await;
                         break;
                     }
                     Err(e) => {
                         error!(error = %e, "Error checking if bot still exists in the database");
-                        sleep(Duration::from_secs(10)).await;
+                        sleep(backoff.next()).await;
                         continue;
                     }
                 }
 
                 info!("Starting");
+                self.health.connecting(bot_id).await;
 
                 {
                     sm.shard_command_channels
@@ -134,7 +204,23 @@ impl<T: EventForwarder> WhitelabelShardManager<T> {
                         .insert(bot_id, command_tx);
                 }
 
-                let res = shard.connect(resume_data.clone()).await;
+                let (res, connected) = tokio::join!(shard.connect(resume_data.clone()), async {
+                    // Sent once the shard has resumed or loaded its guilds, or dropped if it
+                    // exits first
+                    let connected = ready_rx.await.is_ok();
+                    if connected {
+                        let message_content = intents & Intents::MessageContent as u64 != 0;
+                        self.health
+                            .connected(bot_id, status.get().guild_count, message_content)
+                            .await;
+                    }
+
+                    connected
+                });
+
+                if connected {
+                    backoff.reset();
+                }
 
                 {
                     sm.shard_command_channels.write().await.remove(&bot_id);
@@ -144,45 +230,303 @@ impl<T: EventForwarder> WhitelabelShardManager<T> {
                     Ok(session_data) => {
                         resume_data = session_data;
                         self.log_for_bot(bot_id, "Exited with Ok");
+                        self.health.reconnecting(bot_id, None).await;
                     }
-                    Err(GatewayError::AuthenticationError { data, .. }) => {
-                        self.log_err_for_bot(
-                            bot_id,
-                            "Exited with authentication error, removing bot",
-                            &GatewayError::custom(&data.error),
-                        );
-
-                        if let Err(e) = self
-                            .database
-                            .whitelabel_errors
-                            .append(Snowflake(bot.user_id as u64), data.error)
-                            .await
-                        {
+                    Err(GatewayError::AuthenticationError { data, .. }) => match data.kind() {
+                        CloseKind::InvalidToken => {
                             self.log_err_for_bot(
                                 bot_id,
-                                "Error occurred while recording error to database",
-                                &GatewayError::DatabaseError(e),
+                                "Exited with invalid token, removing bot",
+                                &GatewayError::custom(&data.error),
                             );
+
+                            self.record_error(&bot, data.error).await;
+
+                            if let Err(e) = self.database.whitelabel.delete_by_bot_id(bot_id).await
+                            {
+                                self.log_err_for_bot(
+                                    bot_id,
+                                    "Error occurred while deleting bot",
+                                    &GatewayError::DatabaseError(e),
+                                );
+                            }
+
+                            self.health.removed(bot_id).await;
+                            self.drop_lease(bot_id).await;
+                            break;
                         }
+                        CloseKind::DisallowedIntents
+                            if Intents::without_privileged(intents) != intents =>
+                        {
+                            self.log_err_for_bot(
+                                bot_id,
+                                "Exited with disallowed intents, retrying without privileged intents",
+                                &GatewayError::custom(&data.error),
+                            );
+
+                            let error = disallowed_intents_error(intents);
+                            self.record_error(&bot, error.clone()).await;
+                            self.health.reconnecting(bot_id, Some(error)).await;
 
-                        if let Err(e) = self.database.whitelabel.delete_by_bot_id(bot_id).await {
+                            intents = Intents::without_privileged(intents);
+                            resume_data = None;
+                        }
+                        _ => {
                             self.log_err_for_bot(
                                 bot_id,
-                                "Error occurred while deleting bot",
-                                &GatewayError::DatabaseError(e),
+                                "Exited with fatal close, stopping",
+                                &GatewayError::custom(&data.error),
                             );
+
+                            self.record_error(&bot, data.error).await;
+                            self.health.stopped(bot_id).await;
+                            break;
                         }
+                    },
+                    Err(e) => {
+                        self.log_err_for_bot(bot_id, "Exited with error", &e);
 
-                        break;
+                        if failures.record() {
+                            self.quarantine(&bot, &e).await;
+                            failures.clear();
+                            backoff.reset();
+                            continue;
+                        }
+
+                        self.health.reconnecting(bot_id, Some(e.to_string())).await;
                     }
-                    Err(e) => self.log_err_for_bot(bot_id, "Exited with error", &e),
                 }
 
-                sleep(Duration::from_millis(500)).await;
+                sleep(backoff.next()).await;
             }
+
+            sm.registry.remove(bot_id.0);
+            sm.statuses.remove(bot_id);
         });
     }
 
+    // Parks the bot until it is released by a retry command, or deleted
+    async fn quarantine(&self, bot: &WhitelabelBot, error: &GatewayError) {
+        let bot_id = Snowflake(bot.bot_id as u64);
+
+        let reason = whitelabel_health::truncate(format!(
+            "Your bot has been paused after failing to connect {} times in {} minutes. \
+            Last error: {}",
+            self.config.quarantine_threshold,
+            self.config.quarantine_window / 60,
+            error
+        ));
+
+        warn!(bot_id = %bot_id, error = %error, "Quarantining bot");
+
+        let (release_tx, release_rx) = oneshot::channel();
+        self.quarantined.lock().insert(bot_id, release_tx);
+
+        self.record_error(bot, reason.clone()).await;
+        self.health.quarantined(bot_id, reason).await;
+
+        let _ = release_rx.await;
+        self.log_for_bot(bot_id, "Released from quarantine");
+    }
+
+    // Returns false if the bot is not quarantined on this cluster
+    fn release(&self, bot_id: Snowflake) -> bool {
+        match self.quarantined.lock().remove(&bot_id) {
+            Some(release_tx) => release_tx.send(()).is_ok(),
+            None => false,
+        }
+    }
+
+    // Always Held unless bots are assigned by lease. None if the lease has been lost.
+    fn lease_state(&self, bot_id: Snowflake) -> Option<LeaseState> {
+        match &self.leases {
+            Some(leases) => leases.state(bot_id),
+            None => Some(LeaseState::Held),
+        }
+    }
+
+    // Returns false if another sharder holds the lease
+    async fn claim(self: &Arc<Self>, bot_id: Snowflake) -> Result<bool> {
+        let Some(leases) = &self.leases else {
+            return Ok(false);
+        };
+
+        if !leases.claim(bot_id).await? {
+            return Ok(false);
+        }
+
+        match self.database.whitelabel.get_bot_by_id(bot_id).await {
+            Ok(Some(bot)) => {
+                self.log_for_bot(bot_id, "Claimed bot");
+                Arc::clone(self).connect_bot(bot).await;
+                Ok(true)
+            }
+            Ok(None) => {
+                self.drop_lease(bot_id).await;
+                Ok(false)
+            }
+            Err(e) => {
+                self.drop_lease(bot_id).await;
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn drop_lease(&self, bot_id: Snowflake) {
+        let Some(leases) = &self.leases else {
+            return;
+        };
+
+        if let Err(e) = leases.release(bot_id).await {
+            warn!(bot_id = %bot_id, error = %e, "Failed to release lease");
+        }
+    }
+
+    // Saves the session before releasing the lease, so that the next sharder to claim the bot
+    // resumes it
+    async fn hand_over(&self, bot_id: Snowflake, session_data: Option<SessionData>) {
+        // Checkpoints are older than the session of the stopped shard, so must be written first
+        self.checkpointer.flush().await;
+
+        if let Some(session_data) = session_data {
+            if let Err(e) = self.session_store.set(bot_id.0, session_data).await {
+                warn!(bot_id = %bot_id, error = %e, "Failed to save session data for handover");
+            }
+        }
+
+        self.drop_lease(bot_id).await;
+        self.log_for_bot(bot_id, "Handed over bot");
+    }
+
+    fn lease_interval(&self) -> Duration {
+        lease::renew_interval(Duration::from_secs(self.config.lease_ttl))
+    }
+
+    async fn maintain_leases(self: Arc<Self>) {
+        loop {
+            sleep(self.lease_interval()).await;
+
+            if let Err(e) = self.rebalance().await {
+                error!(error = %e, "Error maintaining bot leases");
+            }
+        }
+    }
+
+    // Renews the leases held by this sharder, then claims or hands over bots until it runs its
+    // share of them. Bots are handed over one at a time, so that a scale up is gradual.
+    async fn rebalance(self: &Arc<Self>) -> Result<()> {
+        let Some(leases) = &self.leases else {
+            return Ok(());
+        };
+
+        // Renewed first, so that bots are stopped once their leases expire even if Redis is down
+        for bot_id in leases.renew(Instant::now()).await? {
+            warn!(bot_id = %bot_id, "Lost lease, stopping bot");
+
+            // The bot stops once it is back at the start of its loop, whether it is running or
+            // quarantined
+            self.release(bot_id);
+            if let Err(e) = self.send_command(bot_id, InternalCommand::Shutdown).await {
+                warn!(bot_id = %bot_id, error = %e, "Failed to stop bot");
+            }
+        }
+
+        let sharders = leases.register().await?;
+
+        let bot_ids = self.database.whitelabel.get_bot_ids().await?;
+        let share = lease::share(bot_ids.len(), sharders);
+
+        let held = leases.owned().len();
+        if held < share {
+            let mut claimed = 0;
+            for bot_id in leases.unclaimed(&bot_ids).await? {
+                if held + claimed >= share {
+                    break;
+                }
+
+                if self.claim(bot_id).await? {
+                    claimed += 1;
+                }
+            }
+        } else {
+            self.hand_over_one(leases, share).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn hand_over_one(&self, leases: &Leases, share: usize) -> Result<()> {
+        let bot_id = {
+            let channels = self.shard_command_channels.read().await;
+            leases.start_hand_over(share, |bot_id| channels.contains_key(&bot_id))
+        };
+
+        if let Some(bot_id) = bot_id {
+            self.log_for_bot(bot_id, "Handing over bot to rebalance");
+            self.send_command(bot_id, InternalCommand::Shutdown).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn rotate_statuses(self: Arc<Self>) {
+        let mut ticker = interval(STATUS_ROTATION_TICK);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+
+            for (bot_id, status) in self.statuses.advance(Instant::now()) {
+                let cmd = InternalCommand::StatusUpdate { status };
+
+                if let Err(e) = self.send_command(bot_id, cmd).await {
+                    warn!(bot_id = %bot_id, error = %e, "Failed to rotate status");
+                }
+            }
+        }
+    }
+
+    async fn refresh_health(self: Arc<Self>) {
+        let mut ticker = interval(common::whitelabel_health::REFRESH_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            self.health.refresh_connected().await;
+        }
+    }
+
+    // Shown to the owner of the bot
+    async fn record_error(&self, bot: &WhitelabelBot, error: String) {
+        if let Err(e) = self
+            .database
+            .whitelabel_errors
+            .append(Snowflake(bot.user_id as u64), error)
+            .await
+        {
+            self.log_err_for_bot(
+                Snowflake(bot.bot_id as u64),
+                "Error occurred while recording error to database",
+                &GatewayError::DatabaseError(e),
+            );
+        }
+    }
+
+    // Returns false if the bot is not running on this cluster
+    async fn send_command(&self, bot_id: Snowflake, command: InternalCommand) -> Result<bool> {
+        let channels = self.shard_command_channels.read().await;
+        let Some(command_tx) = channels.get(&bot_id) else {
+            return Ok(false);
+        };
+
+        command_tx
+            .send(command)
+            .await
+            .map_err(|_| GatewayError::custom(format!("bot {bot_id} has disconnected")))?;
+
+        Ok(true)
+    }
+
     pub async fn listen_status_updates(self: Arc<Self>) -> Result<(), GatewayError> {
         let database = Arc::clone(&self.database);
 
@@ -200,19 +544,21 @@ impl<T: EventForwarder> WhitelabelShardManager<T> {
             while let Some(m) = stream.next().await {
                 match m.get_payload::<String>().map(|s| s.parse::<Snowflake>()) {
                     Ok(Ok(bot_id)) => {
-                        if let Some(tx) = self.shard_command_channels.read().await.get(&bot_id) {
+                        if self.statuses.is_running(bot_id) {
                             println!("[RPC] Received status update payload for bot {bot_id}");
 
                             // retrieve new status
                             // TODO: New tokio::spawn for this?
-                            match database.whitelabel_status.get(bot_id).await {
-                                Ok((status, Some(status_type))) => {
-                                    let status =
-                                        StatusUpdate::new(status_type, status, StatusType::Online);
+                            match database.whitelabel_status.get_status(bot_id).await {
+                                Ok(status) => {
+                                    // Bots that are reconnecting identify with the new status
+                                    let Some(status) = self.statuses.replace(bot_id, status) else {
+                                        continue;
+                                    };
 
                                     let cmd = InternalCommand::StatusUpdate { status };
 
-                                    if let Err(e) = tx.send(cmd).await {
+                                    if let Err(e) = self.send_command(bot_id, cmd).await {
                                         eprintln!(
                                             "An error occured while updating status for {}: {}",
                                             bot_id, e
@@ -220,10 +566,6 @@ impl<T: EventForwarder> WhitelabelShardManager<T> {
                                     }
                                 }
 
-                                Ok((_, None)) => {
-                                    eprintln!("Bot {} has invalid status type", bot_id);
-                                }
-
                                 Err(e) => eprintln!("Error retrieving status from db: {}", e),
                             }
                         }
@@ -253,6 +595,12 @@ impl<T: EventForwarder> WhitelabelShardManager<T> {
                 let manager = Arc::clone(&self);
 
                 match serde_json::from_slice::<token_change::Payload>(m.get_payload_bytes()) {
+                    // The first sharder to claim the bot runs it
+                    Ok(payload) if manager.leases.is_some() => {
+                        if let Err(e) = manager.claim(payload.new_id).await {
+                            error!(bot_id = %payload.new_id, error = %e, "Error claiming bot");
+                        }
+                    }
                     Ok(payload) => {
                         // start new bot
                         if payload.new_id.0 % (manager.config.sharder_total as u64)
@@ -297,6 +645,13 @@ impl<T: EventForwarder> WhitelabelShardManager<T> {
                     Ok(Ok(bot_id)) => {
                         println!("[RPC] Received delete payload for {bot_id}");
 
+                        // Quarantined bots stop once they find that they no longer exist
+                        sm.release(bot_id);
+
+                        // Published by every sharder, as the bot may not be running, so that the
+                        // public sharder stops filtering the events of its guilds
+                        sm.health.removed(bot_id).await;
+
                         let channels = sm.shard_command_channels.read().await;
                         let command_tx = channels.get(&bot_id);
                         if let Some(command_tx) = command_tx {
@@ -319,6 +674,34 @@ impl<T: EventForwarder> WhitelabelShardManager<T> {
         Ok(())
     }
 
+    pub async fn listen_retry(self: Arc<Self>) -> Result<(), GatewayError> {
+        let mut conn = redis::Client::open(self.config.get_redis_uri())
+            .unwrap()
+            .get_async_connection()
+            .await?
+            .into_pubsub();
+
+        conn.subscribe(whitelabel_retry::KEY).await?;
+
+        tokio::spawn(async move {
+            let mut stream = conn.on_message();
+
+            while let Some(m) = stream.next().await {
+                match m.get_payload::<String>().map(|s| s.parse::<Snowflake>()) {
+                    Ok(Ok(bot_id)) => {
+                        if self.release(bot_id) {
+                            info!(bot_id = %bot_id, "[RPC] Releasing bot from quarantine");
+                        }
+                    }
+                    Ok(Err(e)) => warn!(error = %e, "Received invalid retry payload"),
+                    Err(e) => error!(error = %e, "Error reading retry payload"),
+                }
+            }
+        });
+
+        Ok(())
+    }
+
     pub fn log_for_bot(&self, bot_id: Snowflake, msg: impl Display) {
         info!(bot_id = %bot_id, "{msg}");
     }
@@ -328,9 +711,63 @@ impl<T: EventForwarder> WhitelabelShardManager<T> {
     }
 }
 
+// Unique to each run of the sharder, so that a restarted sharder doesn't renew the leases it held
+// before the restart
+fn instance_id() -> String {
+    let hostname = std::env::var("HOSTNAME").unwrap_or_else(|_| "sharder".to_owned());
+    format!("{}-{:08x}", hostname, rand::random::<u32>())
+}
+
+fn disallowed_intents_error(intents: u64) -> String {
+    // As they are named in the developer portal
+    const PORTAL_NAMES: [(Intents, &str); 3] = [
+        (Intents::GuildPresences, "Presence Intent"),
+        (Intents::GuildMembers, "Server Members Intent"),
+        (Intents::MessageContent, "Message Content Intent"),
+    ];
+
+    let names: Vec<&str> = PORTAL_NAMES
+        .iter()
+        .filter(|(intent, _)| intent.is_set(intents))
+        .map(|(_, name)| *name)
+        .collect();
+
+    // Errors are limited to 255 characters
+    format!(
+        "Missing privileged intents ({}). Enable them under Bot > Privileged Gateway Intents in \
+        the Discord developer portal. Until then, your bot runs without them and some features \
+        will not work.",
+        names.join(", ")
+    )
+}
+
 #[async_trait]
-impl<T: EventForwarder> ShardManager for WhitelabelShardManager<T> {
+impl<T: EventForwarder, S: SessionStore> ShardManager for WhitelabelShardManager<T, S> {
     async fn connect(self: Arc<Self>) {
+        tokio::spawn(Arc::clone(&self).rotate_statuses());
+        tokio::spawn(Arc::clone(&self).refresh_health());
+
+        if let Some(leases) = &self.leases {
+            let sharders = leases.register().await.expect("Failed to register sharder");
+            info!(
+                instance_id = leases.instance_id(),
+                sharders, "Registered sharder"
+            );
+
+            // Sharders that start together register before claiming bots, so the bots are split
+            // between them
+            sleep(self.lease_interval()).await;
+
+            if let Err(e) = self.rebalance().await {
+                error!(error = %e, "Error claiming bots");
+            }
+
+            tokio::spawn(Arc::clone(&self).maintain_leases());
+
+            self.ready.store(true, Ordering::Relaxed);
+            return;
+        }
+
         // we should panic if we cant read db
         let bots = self
             .database
@@ -342,6 +779,8 @@ impl<T: EventForwarder> ShardManager for WhitelabelShardManager<T> {
         for bot in bots {
             Arc::clone(&self).connect_bot(bot).await;
         }
+
+        self.ready.store(true, Ordering::Relaxed);
     }
 
     async fn shutdown(self: Arc<Self>) {
@@ -375,8 +814,54 @@ impl<T: EventForwarder> ShardManager for WhitelabelShardManager<T> {
             sessions.insert(identifier.bot_id.0, session_data);
         }
 
+        // Checkpoints are older than the sessions reported by the shards, so must be written first
+        self.checkpointer.flush().await;
+
         if let Err(e) = self.session_store.set_bulk(sessions).await {
             error!(error = %e, "Failed to save session data");
         }
+
+        // Before the leases are released, so that the states written by the next sharders to run
+        // the bots aren't overwritten
+        let bot_ids = match &self.leases {
+            Some(leases) => leases.owned(),
+            None => self.health.bot_ids(),
+        };
+        for bot_id in bot_ids {
+            self.health.stopped(bot_id).await;
+        }
+
+        // Released once the sessions have been saved, so that the next sharders resume the bots
+        if let Some(leases) = &self.leases {
+            for bot_id in leases.owned() {
+                if let Err(e) = leases.release(bot_id).await {
+                    warn!(bot_id = %bot_id, error = %e, "Failed to release lease");
+                }
+            }
+
+            if let Err(e) = leases.deregister().await {
+                warn!(error = %e, "Failed to deregister sharder");
+            }
+        }
+    }
+
+    fn shard_statuses(&self) -> Vec<ShardStatus> {
+        self.registry.list()
+    }
+
+    fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    async fn reconnect_shard(&self, id: u64) -> Result<bool> {
+        self.send_command(Snowflake(id), InternalCommand::Reconnect { resume: true })
+            .await
+    }
+
+    async fn invalidate_session(&self, id: u64) -> Result<()> {
+        self.session_store.invalidate(id).await?;
+        self.send_command(Snowflake(id), InternalCommand::Reconnect { resume: false })
+            .await?;
+        Ok(())
     }
 }
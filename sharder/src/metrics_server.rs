@@ -1,17 +1,43 @@
+use std::sync::Arc;
 use std::{convert::Infallible, net::SocketAddr, str::FromStr};
 
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Request, Response, Server};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
 
 use prometheus::{Encoder, TextEncoder};
-use tracing::{info, trace};
+use serde::Serialize;
+use tracing::{info, trace, warn};
 
-use crate::Result;
+use crate::{Result, ShardManager};
 
-pub async fn start_server(server_addr: &str) -> Result<()> {
+/// Serves Prometheus metrics, along with the admin API:
+/// - `GET /metrics`
+/// - `GET /healthz`
+/// - `GET /readyz`, 503 until every shard has been started
+/// - `GET /shards`, the status of every shard
+/// - `POST /shards/{id}/reconnect`, reconnects and resumes a shard
+/// - `POST /shards/{id}/invalidate-session`, deletes a shard's session and reconnects it
+///
+/// The `POST` endpoints require `Authorization: Bearer {admin_token}`, and are disabled if there
+/// is no admin token. Any other path is a 404.
+pub async fn start_server(
+    server_addr: &str,
+    admin_token: Option<String>,
+    manager: Arc<dyn ShardManager>,
+) -> Result<()> {
     let addr = SocketAddr::from_str(server_addr)?;
+    let admin_token = Arc::new(admin_token);
 
-    let make_svc = make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(handle)) });
+    let make_svc = make_service_fn(move |_conn| {
+        let manager = Arc::clone(&manager);
+        let admin_token = Arc::clone(&admin_token);
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                handle(req, Arc::clone(&manager), Arc::clone(&admin_token))
+            }))
+        }
+    });
 
     let server = Server::bind(&addr).serve(make_svc);
 
@@ -21,7 +47,69 @@ pub async fn start_server(server_addr: &str) -> Result<()> {
     Ok(())
 }
 
-async fn handle(_: Request<Body>) -> Result<Response<Body>, prometheus::Error> {
+async fn handle(
+    req: Request<Body>,
+    manager: Arc<dyn ShardManager>,
+    admin_token: Arc<Option<String>>,
+) -> Result<Response<Body>, prometheus::Error> {
+    let segments: Vec<&str> = req.uri().path().trim_matches('/').split('/').collect();
+
+    let res = match (req.method(), segments.as_slice()) {
+        (&Method::GET, ["metrics"]) => metrics()?,
+        (&Method::GET, ["healthz"]) => respond(StatusCode::OK, "ok"),
+        (&Method::GET, ["readyz"]) => {
+            if manager.is_ready() {
+                respond(StatusCode::OK, "ready")
+            } else {
+                respond(StatusCode::SERVICE_UNAVAILABLE, "starting shards")
+            }
+        }
+        (&Method::GET, ["shards"]) => respond_json(&manager.shard_statuses()),
+        (&Method::POST, ["shards", id, action]) => {
+            let Some(admin_token) = admin_token.as_deref() else {
+                return Ok(respond(StatusCode::FORBIDDEN, "admin API is disabled"));
+            };
+
+            if !is_authorized(&req, admin_token) {
+                return Ok(respond(StatusCode::UNAUTHORIZED, "invalid admin token"));
+            }
+
+            let Ok(id) = id.parse::<u64>() else {
+                return Ok(respond(StatusCode::BAD_REQUEST, "invalid shard ID"));
+            };
+
+            match *action {
+                "reconnect" => reconnect(manager.as_ref(), id).await,
+                "invalidate-session" => invalidate_session(manager.as_ref(), id).await,
+                _ => respond(StatusCode::NOT_FOUND, "unknown action"),
+            }
+        }
+        _ => respond(StatusCode::NOT_FOUND, "not found"),
+    };
+
+    Ok(res)
+}
+
+fn is_authorized(req: &Request<Body>, admin_token: &str) -> bool {
+    let Some(token) = req
+        .headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+
+    // Compare every byte, so that the time taken doesn't reveal how much of the token matched
+    token.len() == admin_token.len()
+        && token
+            .bytes()
+            .zip(admin_token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn metrics() -> Result<Response<Body>, prometheus::Error> {
     trace!("Received metrics request");
 
     let mut buffer = Vec::new();
@@ -32,3 +120,71 @@ async fn handle(_: Request<Body>) -> Result<Response<Body>, prometheus::Error> {
 
     Ok(Response::new(Body::from(buffer)))
 }
+
+async fn reconnect(manager: &dyn ShardManager, id: u64) -> Response<Body> {
+    info!(%id, "Reconnecting shard (via admin API)");
+
+    match manager.reconnect_shard(id).await {
+        Ok(true) => respond(StatusCode::ACCEPTED, "reconnecting"),
+        Ok(false) => respond(StatusCode::NOT_FOUND, "shard is not running"),
+        Err(e) => {
+            warn!(%id, error = %e, "Error reconnecting shard");
+            respond(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        }
+    }
+}
+
+async fn invalidate_session(manager: &dyn ShardManager, id: u64) -> Response<Body> {
+    info!(%id, "Invalidating shard session (via admin API)");
+
+    match manager.invalidate_session(id).await {
+        Ok(()) => respond(StatusCode::OK, "session invalidated"),
+        Err(e) => {
+            warn!(%id, error = %e, "Error invalidating shard session");
+            respond(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        }
+    }
+}
+
+fn respond(status: StatusCode, body: impl Into<Body>) -> Response<Body> {
+    let mut res = Response::new(body.into());
+    *res.status_mut() = status;
+    res
+}
+
+fn respond_json<T: Serialize>(value: &T) -> Response<Body> {
+    match serde_json::to_vec(value) {
+        Ok(body) => {
+            let mut res = respond(StatusCode::OK, body);
+            res.headers_mut().insert(
+                hyper::header::CONTENT_TYPE,
+                hyper::header::HeaderValue::from_static("application/json"),
+            );
+            res
+        }
+        Err(e) => respond(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(authorization: Option<&str>) -> Request<Body> {
+        let mut req = Request::post("/shards/0/reconnect");
+        if let Some(authorization) = authorization {
+            req = req.header(hyper::header::AUTHORIZATION, authorization);
+        }
+
+        req.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_is_authorized() {
+        assert!(is_authorized(&request(Some("Bearer secret")), "secret"));
+        assert!(!is_authorized(&request(Some("Bearer secreT")), "secret"));
+        assert!(!is_authorized(&request(Some("Bearer secret2")), "secret"));
+        assert!(!is_authorized(&request(Some("secret")), "secret"));
+        assert!(!is_authorized(&request(None), "secret"));
+    }
+}
@@ -0,0 +1,47 @@
+//! Fixtures shared by the unit tests and the integration tests. Not part of the public API.
+
+use common::event_forwarding;
+use serde_json::value::RawValue;
+
+use crate::Config;
+
+const BASE_ENV: &[(&str, &str)] = &[
+    ("SHARDER_ID", "0"),
+    ("SHARDER_TOTAL", "1"),
+    ("REDIS_ADDR", "127.0.0.1:6379"),
+    ("REDIS_THREADS", "1"),
+    ("SENTRY_DSN", ""),
+    ("KAFKA_BROKERS", "127.0.0.1:9092"),
+    ("KAFKA_TOPIC", "events"),
+    ("METRICS_ADDR", "127.0.0.1:0"),
+    ("SHARDER_TOKEN", "token"),
+    ("SHARDER_CLUSTER_SIZE", "1"),
+    ("BOT_ID", "1"),
+    ("DATABASE_URI", "postgres://localhost"),
+];
+
+/// Loads a config from the variables that are required, overridden or added to by `vars`
+pub fn config(vars: &[(&str, &str)]) -> Config {
+    let base = BASE_ENV
+        .iter()
+        .filter(|(key, _)| !vars.iter().any(|(var, _)| var == key));
+
+    envy::from_iter(
+        base.chain(vars)
+            .map(|(key, value)| (key.to_string(), value.to_string())),
+    )
+    .expect("Failed to load test config")
+}
+
+/// An event from the bot in `config`, told apart by its shard ID
+pub fn event(shard_id: u16) -> event_forwarding::Event {
+    event_forwarding::Event {
+        bot_token: None,
+        bot_id: 1,
+        token_key: event_forwarding::token_key("token"),
+        is_whitelabel: false,
+        served_by_whitelabel: false,
+        shard_id,
+        event: RawValue::from_string("{}".to_owned()).unwrap(),
+    }
+}
@@ -0,0 +1,558 @@
+// Whitelabel shards need a database
+#![cfg(not(feature = "whitelabel"))]
+
+mod mock_gateway;
+
+use async_trait::async_trait;
+use common::event_forwarding::{token_key, Event};
+use mock_gateway::{Connection, MockGateway, Step, TOKEN};
+use model::user::{ActivityType, StatusType, StatusUpdate};
+use model::Snowflake;
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use sharder::event_forwarding::{EventFilter, EventFilters, EventForwarder, FilterAction, Route};
+use sharder::payloads::event::Event as GatewayEvent;
+use sharder::payloads::Identify;
+use sharder::test_support;
+use sharder::{
+    build_redis, Config, GatewayError, IdentifyRatelimiter, InternalCommand, MemorySessionStore,
+    Options, PublicShardManager, Result, SessionCheckpointer, SessionData, SessionStore, Shard,
+    ShardCount, ShardIdentifier, ShardInfo, ShardManager, ShardState, ShardStatusHandle,
+};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time::timeout;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct RecordingForwarder {
+    events: Mutex<Vec<String>>,
+    tokens: Mutex<Vec<(Option<String>, String)>>,
+    tagged: Mutex<Vec<bool>>,
+}
+
+#[async_trait]
+impl EventForwarder for RecordingForwarder {
+    async fn forward_event(
+        &self,
+        _config: &Config,
+        event: Event,
+        _guild_id: Option<Snowflake>,
+        _route: Route,
+    ) -> Result<()> {
+        let payload: Value = serde_json::from_str(event.event.get())?;
+        self.tokens.lock().push((event.bot_token, event.token_key));
+        self.tagged.lock().push(event.served_by_whitelabel);
+        self.events
+            .lock()
+            .push(payload["t"].as_str().unwrap().to_owned());
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Counts bulk writes, to check that checkpoints are coalesced
+#[derive(Default)]
+struct RecordingStore {
+    inner: MemorySessionStore,
+    bulk_writes: Mutex<usize>,
+}
+
+#[async_trait]
+impl SessionStore for RecordingStore {
+    async fn get(&self, shard_id: u64) -> Result<Option<SessionData>> {
+        self.inner.get(shard_id).await
+    }
+
+    async fn get_bulk(&self, shard_ids: &[u64]) -> Result<HashMap<u64, SessionData>> {
+        self.inner.get_bulk(shard_ids).await
+    }
+
+    async fn set(&self, shard_id: u64, info: SessionData) -> Result<()> {
+        self.inner.set(shard_id, info).await
+    }
+
+    async fn set_bulk(&self, data: HashMap<u64, SessionData>) -> Result<()> {
+        *self.bulk_writes.lock() += 1;
+        self.inner.set_bulk(data).await
+    }
+
+    async fn invalidate(&self, shard_id: u64) -> Result<()> {
+        self.inner.invalidate(shard_id).await
+    }
+
+    async fn invalidate_bulk(&self, shard_ids: &[u64]) -> Result<()> {
+        self.inner.invalidate_bulk(shard_ids).await
+    }
+}
+
+struct TestShard {
+    shard: Shard<RecordingForwarder>,
+    forwarder: Arc<RecordingForwarder>,
+    ready_rx: oneshot::Receiver<()>,
+    command_tx: mpsc::Sender<InternalCommand>,
+    status: ShardStatusHandle,
+    store: Arc<RecordingStore>,
+    checkpointer: SessionCheckpointer,
+    // The shard stops if every shutdown sender is dropped
+    _shutdown_tx: broadcast::Sender<mpsc::Sender<(ShardIdentifier, Option<SessionData>)>>,
+}
+
+// Drops the events of one guild, and tags the events of another
+struct GuildFilter {
+    dropped: Snowflake,
+    tagged: Snowflake,
+}
+
+impl EventFilter for GuildFilter {
+    fn filter(&self, _event: &GatewayEvent, guild_id: Option<Snowflake>) -> FilterAction {
+        match guild_id {
+            Some(guild_id) if guild_id == self.dropped => FilterAction::Drop,
+            Some(guild_id) if guild_id == self.tagged => FilterAction::Tag,
+            _ => FilterAction::Forward,
+        }
+    }
+}
+
+fn build_shard(gateway: &MockGateway, vars: &[(&str, &str)]) -> TestShard {
+    build_shard_with_filters(gateway, vars, EventFilters::new())
+}
+
+// Sessions and the IDENTIFY ratelimit are kept in memory, so that Redis isn't needed
+fn build_config(gateway: &MockGateway, vars: &[(&str, &str)]) -> Config {
+    let redis_addr = std::env::var("REDIS_ADDR").unwrap_or_else(|_| "127.0.0.1:6379".to_owned());
+
+    let mut env = vec![
+        ("REDIS_ADDR", redis_addr.as_str()),
+        ("SHARDER_TOKEN", TOKEN),
+        ("BOT_ID", "508391840525975553"),
+        ("GATEWAY_URL", gateway.url()),
+        // Nothing listens on port 1, so the sharder falls back to LARGE_SHARDING_BUCKETS
+        ("GATEWAY_BOT_URL", "http://127.0.0.1:1/gateway/bot"),
+        ("SESSION_STORE", "memory"),
+    ];
+    env.extend_from_slice(vars);
+
+    test_support::config(&env)
+}
+
+fn build_shard_with_filters(
+    gateway: &MockGateway,
+    vars: &[(&str, &str)],
+    event_filters: EventFilters,
+) -> TestShard {
+    let config = build_config(gateway, vars);
+
+    let identify = Identify::new(
+        TOKEN.to_owned(),
+        None,
+        ShardInfo::new(0, 1),
+        None,
+        config.get_gateway_intents(),
+    );
+
+    let forwarder = Arc::new(RecordingForwarder::default());
+    let (ready_tx, ready_rx) = oneshot::channel();
+    let (command_tx, command_rx) = mpsc::channel(4);
+    let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+    let redis = Arc::new(build_redis(&config));
+    let identify_ratelimiter = Arc::new(IdentifyRatelimiter::new(&config, redis));
+    let status = ShardStatusHandle::new(Snowflake(508391840525975553), 0);
+
+    // Only written on demand, using flush
+    let store = Arc::new(RecordingStore::default());
+    let checkpointer = SessionCheckpointer::start(Arc::clone(&store), Duration::from_secs(3600));
+
+    let shard = Shard::new(
+        Arc::new(config),
+        identify,
+        1,
+        identify_ratelimiter,
+        Snowflake(508391840525975553),
+        Arc::clone(&forwarder),
+        Arc::new(event_filters),
+        Some(ready_tx),
+        shutdown_rx,
+        command_rx,
+        status.clone(),
+        checkpointer.sender(0),
+    );
+
+    TestShard {
+        shard,
+        forwarder,
+        ready_rx,
+        command_tx,
+        status,
+        store,
+        checkpointer,
+        _shutdown_tx: shutdown_tx,
+    }
+}
+
+async fn within<F: Future>(f: F) -> F::Output {
+    timeout(TIMEOUT, f).await.expect("timed out")
+}
+
+async fn wait_until(condition: impl Fn() -> bool) {
+    within(async {
+        while !condition() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_resume() {
+    let gateway = MockGateway::start(vec![Connection::new()
+        .then(Step::Dispatch(
+            "GUILD_ROLE_DELETE",
+            json!({ "guild_id": "1", "role_id": "2" }),
+        ))
+        .then(Step::Reconnect)])
+    .await;
+
+    let session = gateway.add_session("session", 5);
+    let test = build_shard(&gateway, &[]);
+
+    let session = within(test.shard.connect(Some(session)))
+        .await
+        .unwrap()
+        .expect("RECONNECT should keep the session");
+
+    // RESUMED is 6, GUILD_ROLE_DELETE is 7
+    assert_eq!(session.session_id, "session");
+    assert_eq!(session.seq, 7);
+    assert!(test.ready_rx.await.is_ok());
+    assert_eq!(*test.forwarder.events.lock(), ["GUILD_ROLE_DELETE"]);
+
+    let status = test.status.get();
+    assert_eq!(status.state, ShardState::Disconnected);
+    assert_eq!(status.seq, Some(7));
+    assert_eq!(status.session_id.as_deref(), Some("session"));
+
+    // Checkpointed on RESUMED
+    test.checkpointer.flush().await;
+    let session = test.store.get(0).await.unwrap().unwrap();
+    assert_eq!(session.seq, 6);
+
+    let resume = &gateway.received()[0];
+    assert_eq!(resume["op"], 6);
+    assert_eq!(resume["d"]["session_id"], "session");
+    assert_eq!(resume["d"]["seq"], 5);
+}
+
+#[tokio::test]
+async fn test_events_reference_token() {
+    let gateway = MockGateway::start(vec![Connection::new()
+        .then(Step::Dispatch(
+            "GUILD_ROLE_DELETE",
+            json!({ "guild_id": "1", "role_id": "2" }),
+        ))
+        .then(Step::Reconnect)])
+    .await;
+
+    let session = gateway.add_session("session", 1);
+    let test = build_shard(&gateway, &[("INCLUDE_BOT_TOKEN", "false")]);
+
+    within(test.shard.connect(Some(session))).await.unwrap();
+
+    assert_eq!(*test.forwarder.tokens.lock(), [(None, token_key(TOKEN))]);
+}
+
+#[tokio::test]
+async fn test_event_filters() {
+    let gateway = MockGateway::start(vec![Connection::new()
+        .then(Step::Dispatch(
+            "GUILD_ROLE_DELETE",
+            json!({ "guild_id": "1", "role_id": "2" }),
+        ))
+        .then(Step::Dispatch(
+            "GUILD_ROLE_DELETE",
+            json!({ "guild_id": "3", "role_id": "4" }),
+        ))
+        .then(Step::Dispatch(
+            "GUILD_ROLE_DELETE",
+            json!({ "guild_id": "5", "role_id": "6" }),
+        ))
+        .then(Step::Reconnect)])
+    .await;
+
+    let filters = EventFilters::new().with(Arc::new(GuildFilter {
+        dropped: Snowflake(1),
+        tagged: Snowflake(3),
+    }));
+
+    let session = gateway.add_session("session", 1);
+    let test = build_shard_with_filters(&gateway, &[], filters);
+
+    within(test.shard.connect(Some(session))).await.unwrap();
+
+    assert_eq!(test.forwarder.events.lock().len(), 2);
+    assert_eq!(*test.forwarder.tagged.lock(), [true, false]);
+}
+
+#[tokio::test]
+async fn test_resume_etf() {
+    let gateway = MockGateway::start(vec![Connection::new().then(Step::Dispatch(
+        "GUILD_ROLE_DELETE",
+        json!({ "guild_id": "1", "role_id": "2" }),
+    ))])
+    .await;
+
+    let session = gateway.add_session("session", 1);
+    let test = build_shard(&gateway, &[("GATEWAY_ENCODING", "etf")]);
+    let forwarder = Arc::clone(&test.forwarder);
+    let connect = tokio::spawn(test.shard.connect(Some(session)));
+
+    within(test.ready_rx).await.unwrap();
+    wait_until(|| !forwarder.events.lock().is_empty()).await;
+    test.command_tx
+        .send(InternalCommand::Shutdown)
+        .await
+        .unwrap();
+
+    let session = within(connect).await.unwrap().unwrap().unwrap();
+    assert_eq!(session.seq, 3);
+    assert_eq!(*forwarder.events.lock(), ["GUILD_ROLE_DELETE"]);
+}
+
+#[tokio::test]
+async fn test_unknown_session_is_invalidated() {
+    let gateway = MockGateway::start(vec![Connection::new()]).await;
+
+    let session = SessionData {
+        seq: 1,
+        session_id: "unknown".to_owned(),
+        resume_url: Some(gateway.url().to_owned()),
+    };
+
+    let test = build_shard(&gateway, &[]);
+    let session = within(test.shard.connect(Some(session))).await.unwrap();
+
+    assert!(session.is_none());
+    assert_eq!(gateway.received_ops(), [6]);
+}
+
+#[tokio::test]
+async fn test_invalid_session_drops_session() {
+    let gateway =
+        MockGateway::start(vec![Connection::new().then(Step::InvalidSession(false))]).await;
+
+    let session = gateway.add_session("session", 1);
+    let test = build_shard(&gateway, &[]);
+
+    assert!(within(test.shard.connect(Some(session)))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_zombie_connection_is_killed() {
+    let gateway = MockGateway::start(vec![Connection::new()
+        .heartbeat_interval(Duration::from_millis(100))
+        .omit_heartbeat_acks()])
+    .await;
+
+    let session = gateway.add_session("session", 1);
+    let test = build_shard(&gateway, &[]);
+
+    let session = within(test.shard.connect(Some(session))).await.unwrap();
+
+    // The session can still be resumed on a new connection
+    assert!(session.is_some());
+    assert_eq!(gateway.received_ops(), [6, 1]);
+}
+
+#[tokio::test]
+async fn test_fatal_close() {
+    let gateway = MockGateway::start(vec![Connection::new().then(Step::Close(4004))]).await;
+
+    let session = gateway.add_session("session", 1);
+    let test = build_shard(&gateway, &[]);
+
+    match within(test.shard.connect(Some(session))).await {
+        Err(GatewayError::AuthenticationError { data, .. }) => {
+            assert_eq!(data.status_code, 4004)
+        }
+        res => panic!("expected authentication error, got {:?}", res),
+    }
+}
+
+#[tokio::test]
+async fn test_non_fatal_close_keeps_session() {
+    let gateway = MockGateway::start(vec![Connection::new().then(Step::Close(4000))]).await;
+
+    let session = gateway.add_session("session", 1);
+    let test = build_shard(&gateway, &[]);
+
+    let session = within(test.shard.connect(Some(session))).await.unwrap();
+    assert_eq!(session.unwrap().session_id, "session");
+}
+
+#[tokio::test]
+async fn test_identify() {
+    let gateway = MockGateway::start(vec![Connection::new()]).await;
+    let test = build_shard(&gateway, &[]);
+
+    let connect = tokio::spawn(test.shard.connect(None));
+
+    wait_until(|| !gateway.received_ops().is_empty()).await;
+
+    // Give the shard time to process READY
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    test.command_tx
+        .send(InternalCommand::Shutdown)
+        .await
+        .unwrap();
+
+    let session = within(connect).await.unwrap().unwrap().unwrap();
+    assert_eq!(session.session_id, "mock-session-0");
+    assert_eq!(session.resume_url.as_deref(), Some(gateway.url()));
+
+    let identify = &gateway.received()[0];
+    assert_eq!(identify["op"], 2);
+    assert_eq!(identify["d"]["token"], TOKEN);
+    assert_eq!(identify["d"]["shard"], json!([0, 1]));
+}
+
+#[tokio::test]
+async fn test_reconnect_command() {
+    let gateway = MockGateway::start(vec![Connection::new()]).await;
+
+    let session = gateway.add_session("session", 1);
+    let test = build_shard(&gateway, &[]);
+    let connect = tokio::spawn(test.shard.connect(Some(session)));
+
+    within(test.ready_rx).await.unwrap();
+    assert_eq!(test.status.get().state, ShardState::Ready);
+
+    test.command_tx
+        .send(InternalCommand::Reconnect { resume: false })
+        .await
+        .unwrap();
+
+    assert!(within(connect).await.unwrap().unwrap().is_none());
+    assert_eq!(test.status.get().session_id, None);
+
+    // The checkpoint from RESUMED is coalesced with the invalidation
+    test.checkpointer.flush().await;
+    assert!(test.store.get(0).await.unwrap().is_none());
+    assert_eq!(*test.store.bulk_writes.lock(), 1);
+}
+
+async fn start_manager(
+    gateway: &MockGateway,
+    store: Arc<RecordingStore>,
+) -> Arc<PublicShardManager<RecordingForwarder, Arc<RecordingStore>>> {
+    // Both shards identify at once, in separate buckets
+    let config = build_config(gateway, &[("LARGE_SHARDING_BUCKETS", "2")]);
+
+    let options = Options {
+        token: Box::from(TOKEN),
+        shard_count: ShardCount {
+            total: 2,
+            lowest: 0,
+            highest: 2,
+        },
+        presence: StatusUpdate::new(
+            ActivityType::Listening,
+            "/help".to_owned(),
+            StatusType::Online,
+        ),
+        large_sharding_buckets: config.large_sharding_buckets,
+        user_id: config.bot_id,
+    };
+
+    let redis = Arc::new(build_redis(&config));
+    let manager = PublicShardManager::new(
+        config,
+        options,
+        store,
+        redis,
+        Arc::new(RecordingForwarder::default()),
+        EventFilters::new(),
+    )
+    .await;
+
+    let manager = Arc::new(manager);
+    within(Arc::clone(&manager).connect()).await;
+    manager
+}
+
+#[tokio::test]
+async fn test_manager_identifies_every_shard() {
+    let gateway = MockGateway::start(vec![]).await;
+    let store = Arc::new(RecordingStore::default());
+    let manager = start_manager(&gateway, Arc::clone(&store)).await;
+
+    assert!(manager.is_ready());
+
+    let mut shards: Vec<_> = gateway
+        .received()
+        .into_iter()
+        .filter(|payload| payload["op"] == 2)
+        .map(|identify| identify["d"]["shard"].clone())
+        .collect();
+    shards.sort_by_key(|shard| shard[0].as_u64());
+    assert_eq!(shards, [json!([0, 2]), json!([1, 2])]);
+
+    let statuses = manager.shard_statuses();
+    assert_eq!(statuses.len(), 2);
+    assert!(statuses
+        .iter()
+        .all(|status| status.state == ShardState::Ready));
+
+    // Sessions are saved on shutdown, so that they can be resumed
+    within(Arc::clone(&manager).shutdown()).await;
+    for shard_id in 0..2 {
+        let session = store.get(shard_id).await.unwrap().unwrap();
+        assert!(session.session_id.starts_with("mock-session-"));
+    }
+}
+
+#[tokio::test]
+async fn test_manager_reconnect_shard() {
+    let gateway = MockGateway::start(vec![]).await;
+    let manager = start_manager(&gateway, Arc::new(RecordingStore::default())).await;
+
+    assert!(!manager.reconnect_shard(2).await.unwrap());
+    assert!(manager.reconnect_shard(1).await.unwrap());
+
+    // The shard resumes its session on a new connection, and is given RESUMED
+    wait_until(|| {
+        manager
+            .shard_statuses()
+            .iter()
+            .any(|status| status.shard_id == 1 && status.seq == Some(2))
+    })
+    .await;
+
+    let resume = gateway
+        .received()
+        .into_iter()
+        .find(|payload| payload["op"] == 6)
+        .unwrap();
+
+    let status = manager
+        .shard_statuses()
+        .into_iter()
+        .find(|status| status.shard_id == 1)
+        .unwrap();
+    assert_eq!(status.state, ShardState::Ready);
+    assert_eq!(resume["d"]["seq"], 1);
+    assert_eq!(
+        resume["d"]["session_id"].as_str(),
+        status.session_id.as_deref()
+    );
+}
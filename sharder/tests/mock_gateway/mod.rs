@@ -0,0 +1,431 @@
+//! A local server speaking enough of the gateway protocol to drive a `Shard` without connecting
+//! to Discord. Each connection is given HELLO, has its IDENTIFY or RESUME validated, and is then
+//! sent the scripted steps for that connection. Everything the shard sends is recorded so tests
+//! can assert on it. Payloads are compressed with the transport compression that the shard asks
+//! for.
+
+#[cfg(feature = "compression")]
+use flate2::{Compress, Compression, FlushCompress};
+use futures::{SinkExt, StreamExt};
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use sharder::{etf, SessionData};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+#[cfg(feature = "compression-zstd")]
+use zstd::stream::raw::{Encoder, InBuffer, Operation, OutBuffer};
+
+/// The only token accepted in IDENTIFY and RESUME payloads
+pub const TOKEN: &str = "mock-token";
+
+pub enum Step {
+    /// Sends a dispatch with the next sequence number of the session
+    Dispatch(&'static str, Value),
+    Reconnect,
+    InvalidSession(bool),
+    Close(u16),
+}
+
+/// What the server does on a single connection, once the shard has identified or resumed
+pub struct Connection {
+    heartbeat_interval: Duration,
+    ack_heartbeats: bool,
+    steps: Vec<Step>,
+}
+
+impl Connection {
+    pub fn new() -> Self {
+        Self {
+            heartbeat_interval: Duration::from_millis(41250),
+            ack_heartbeats: true,
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+
+    /// Leaves heartbeats unacknowledged, as a zombied connection would
+    pub fn omit_heartbeat_acks(mut self) -> Self {
+        self.ack_heartbeats = false;
+        self
+    }
+
+    pub fn then(mut self, step: Step) -> Self {
+        self.steps.push(step);
+        self
+    }
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Default)]
+struct State {
+    // Connections beyond the end of the script are given Connection::default()
+    script: VecDeque<Connection>,
+    // Session ID -> last sequence number sent
+    sessions: HashMap<String, usize>,
+    received: Vec<Value>,
+}
+
+pub struct MockGateway {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockGateway {
+    pub async fn start(script: Vec<Connection>) -> MockGateway {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        let state = Arc::new(Mutex::new(State {
+            script: script.into(),
+            ..Default::default()
+        }));
+
+        let gateway = MockGateway {
+            url: url.clone(),
+            state: Arc::clone(&state),
+        };
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle_connection(stream, Arc::clone(&state), url.clone()));
+            }
+        });
+
+        gateway
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Registers a session that can be resumed, returning the data a shard needs to resume it
+    pub fn add_session(&self, session_id: &str, seq: usize) -> SessionData {
+        self.state
+            .lock()
+            .sessions
+            .insert(session_id.to_owned(), seq);
+
+        SessionData {
+            seq,
+            session_id: session_id.to_owned(),
+            resume_url: Some(self.url.clone()),
+        }
+    }
+
+    /// Every payload received, across all connections
+    pub fn received(&self) -> Vec<Value> {
+        self.state.lock().received.clone()
+    }
+
+    pub fn received_ops(&self) -> Vec<u64> {
+        self.received()
+            .iter()
+            .filter_map(|payload| payload["op"].as_u64())
+            .collect()
+    }
+}
+
+// Compresses every payload on a connection as a single stream, flushed after each payload
+#[cfg(feature = "transport-compression")]
+enum Compressor {
+    #[cfg(feature = "compression")]
+    Zlib(Compress),
+    #[cfg(feature = "compression-zstd")]
+    Zstd(Encoder<'static>),
+}
+
+#[cfg(feature = "transport-compression")]
+impl Compressor {
+    // From the value of the compress query parameter
+    fn new(compress: &str) -> Option<Self> {
+        match compress {
+            #[cfg(feature = "compression")]
+            "zlib-stream" => Some(Compressor::Zlib(Compress::new(
+                Compression::default(),
+                true,
+            ))),
+            #[cfg(feature = "compression-zstd")]
+            "zstd-stream" => Some(Compressor::Zstd(Encoder::new(0).unwrap())),
+            _ => None,
+        }
+    }
+
+    fn compress(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(data.len() + 64);
+
+        match self {
+            #[cfg(feature = "compression")]
+            Compressor::Zlib(deflater) => {
+                let start = deflater.total_in();
+                loop {
+                    output.reserve(64);
+                    let consumed = (deflater.total_in() - start) as usize;
+                    deflater
+                        .compress_vec(&data[consumed..], &mut output, FlushCompress::Sync)
+                        .unwrap();
+
+                    // Flushed once there is space left in the output
+                    let consumed = (deflater.total_in() - start) as usize;
+                    if consumed == data.len() && output.len() < output.capacity() {
+                        break;
+                    }
+                }
+            }
+            #[cfg(feature = "compression-zstd")]
+            Compressor::Zstd(encoder) => {
+                let mut input = InBuffer::around(data);
+                while input.pos() < data.len() {
+                    output.reserve(64);
+                    let pos = output.len();
+                    let mut out = OutBuffer::around_pos(&mut output, pos);
+                    encoder.run(&mut input, &mut out).unwrap();
+                }
+
+                loop {
+                    output.reserve(64);
+                    let pos = output.len();
+                    let mut out = OutBuffer::around_pos(&mut output, pos);
+                    if encoder.flush(&mut out).unwrap() == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+
+        output
+    }
+}
+
+// Serialises payloads in the encoding and compression requested by the shard, and queues them for
+// the writer
+struct Sender {
+    tx: mpsc::UnboundedSender<Message>,
+    etf: bool,
+    #[cfg(feature = "transport-compression")]
+    compressor: Mutex<Option<Compressor>>,
+}
+
+impl Sender {
+    fn send(&self, payload: Value) {
+        let message = if self.etf {
+            Message::Binary(etf::encode(&payload))
+        } else {
+            Message::Text(payload.to_string())
+        };
+
+        #[cfg(feature = "transport-compression")]
+        let message = {
+            // Held until the message is queued, so that messages are queued in the order that
+            // they were compressed
+            let mut compressor = self.compressor.lock();
+            match compressor.as_mut() {
+                Some(compressor) => {
+                    let compressed = compressor.compress(&message.into_data());
+                    _ = self.tx.send(Message::Binary(compressed));
+                    return;
+                }
+                None => message,
+            }
+        };
+
+        _ = self.tx.send(message);
+    }
+
+    fn close(&self, code: u16, reason: &str) {
+        _ = self.tx.send(Message::Close(Some(CloseFrame {
+            code: CloseCode::from(code),
+            reason: reason.to_owned().into(),
+        })));
+    }
+}
+
+async fn handle_connection(stream: TcpStream, state: Arc<Mutex<State>>, url: String) {
+    let mut params = HashMap::new();
+    #[allow(clippy::result_large_err)] // The error type is set by tungstenite
+    let callback = |req: &Request, res: Response| {
+        let query = req.uri().query().unwrap_or_default();
+        params = query
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect();
+        Ok(res)
+    };
+
+    let Ok(ws) = tokio_tungstenite::accept_hdr_async(stream, callback).await else {
+        return;
+    };
+
+    let connection = state.lock().script.pop_front().unwrap_or_default();
+
+    let (mut ws_tx, mut ws_rx) = ws.split();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let sender = Sender {
+        tx,
+        etf: params
+            .get("encoding")
+            .is_some_and(|encoding| encoding == "etf"),
+        #[cfg(feature = "transport-compression")]
+        compressor: Mutex::new(params.get("compress").and_then(|c| Compressor::new(c))),
+    };
+
+    tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            let is_close = matches!(message, Message::Close(_));
+            if ws_tx.send(message).await.is_err() || is_close {
+                break;
+            }
+        }
+    });
+
+    sender.send(json!({
+        "op": 10,
+        "d": { "heartbeat_interval": connection.heartbeat_interval.as_millis() as u64 },
+    }));
+
+    let mut steps = Some(connection.steps);
+    while let Some(Ok(message)) = ws_rx.next().await {
+        let payload: Value = match message {
+            Message::Text(data) => serde_json::from_str(&data).unwrap(),
+            Message::Binary(data) => serde_json::from_str(&etf::to_json(&data).unwrap()).unwrap(),
+            Message::Close(_) => break,
+            _ => continue,
+        };
+
+        state.lock().received.push(payload.clone());
+
+        match payload["op"].as_u64() {
+            // HEARTBEAT
+            Some(1) if connection.ack_heartbeats => sender.send(json!({ "op": 11 })),
+            Some(1) => {}
+
+            // IDENTIFY
+            Some(2) => {
+                let Some(steps) = steps.take() else {
+                    sender.close(4005, "Already authenticated.");
+                    break;
+                };
+
+                if let Err((code, reason)) = validate_identify(&payload["d"]) {
+                    sender.close(code, reason);
+                    break;
+                }
+
+                let session_id = {
+                    let mut state = state.lock();
+                    let session_id = format!("mock-session-{}", state.sessions.len());
+                    state.sessions.insert(session_id.clone(), 0);
+                    session_id
+                };
+
+                let ready = json!({
+                    "v": 10,
+                    "user": { "id": "508391840525975553", "username": "mock", "global_name": null, "avatar": null },
+                    "guilds": [],
+                    "session_id": session_id,
+                    "resume_gateway_url": url,
+                    "shard": payload["d"]["shard"],
+                });
+
+                dispatch(&state, &sender, &session_id, "READY", ready);
+                send_steps(steps, &state, &sender, &session_id);
+            }
+
+            // RESUME
+            Some(6) => {
+                let Some(steps) = steps.take() else {
+                    sender.close(4005, "Already authenticated.");
+                    break;
+                };
+
+                let data = &payload["d"];
+                if data["token"] != TOKEN {
+                    sender.close(4004, "Authentication failed.");
+                    break;
+                }
+
+                let session_id = data["session_id"].as_str().unwrap_or_default().to_owned();
+                let known_seq = state.lock().sessions.get(&session_id).copied();
+
+                match (known_seq, data["seq"].as_u64()) {
+                    (Some(known_seq), Some(seq)) if seq as usize <= known_seq => {
+                        dispatch(&state, &sender, &session_id, "RESUMED", json!({}));
+                        send_steps(steps, &state, &sender, &session_id);
+                    }
+                    _ => sender.send(json!({ "op": 9, "d": false })),
+                }
+            }
+
+            // Anything else must wait until the shard has identified or resumed
+            _ if steps.is_some() => {
+                sender.close(4003, "Not authenticated.");
+                break;
+            }
+
+            _ => {}
+        }
+    }
+}
+
+fn validate_identify(data: &Value) -> Result<(), (u16, &'static str)> {
+    if data["token"] != TOKEN {
+        return Err((4004, "Authentication failed."));
+    }
+
+    match (data["shard"][0].as_u64(), data["shard"][1].as_u64()) {
+        (Some(id), Some(total)) if id < total => {}
+        _ => return Err((4010, "Invalid shard.")),
+    }
+
+    if data["intents"].as_u64().is_none() {
+        return Err((4013, "Invalid intent(s)."));
+    }
+
+    Ok(())
+}
+
+fn dispatch(
+    state: &Mutex<State>,
+    sender: &Sender,
+    session_id: &str,
+    event_type: &str,
+    data: Value,
+) {
+    let seq = {
+        let mut state = state.lock();
+        let seq = state.sessions.entry(session_id.to_owned()).or_default();
+        *seq += 1;
+        *seq
+    };
+
+    sender.send(json!({ "op": 0, "s": seq, "t": event_type, "d": data }));
+}
+
+fn send_steps(steps: Vec<Step>, state: &Mutex<State>, sender: &Sender, session_id: &str) {
+    for step in steps {
+        match step {
+            Step::Dispatch(event_type, data) => {
+                dispatch(state, sender, session_id, event_type, data)
+            }
+            Step::Reconnect => sender.send(json!({ "op": 7, "d": null })),
+            Step::InvalidSession(resumable) => sender.send(json!({ "op": 9, "d": resumable })),
+            Step::Close(code) => sender.close(code, "Closed by mock gateway."),
+        }
+    }
+}